@@ -0,0 +1,45 @@
+/* Alternative Morse character tables */
+/* Each table is behind its own cargo feature so the flash cost is opt-in.
+   Figures, punctuation and prosigns always come from the common tables in morse.rs. */
+
+/* Russian Cyrillic */
+#[cfg(feature = "cyrillic")]
+pub const CYRILLIC: &[(char, &str)] = &[
+    ('А', ".-"),    ('Б', "-..."),  ('В', ".--"),   ('Г', "--."),
+    ('Д', "-.."),   ('Е', "."),     ('Ж', "...-"),  ('З', "--.."),
+    ('И', ".."),    ('Й', ".---"),  ('К', "-.-"),   ('Л', ".-.."),
+    ('М', "--"),    ('Н', "-."),    ('О', "---"),   ('П', ".--."),
+    ('Р', ".-."),   ('С', "..."),   ('Т', "-"),     ('У', "..-"),
+    ('Ф', "..-."),  ('Х', "...."),  ('Ц', "-.-."),  ('Ч', "---."),
+    ('Ш', "----"),  ('Щ', "--.-"),  ('Ъ', "--.--"), ('Ы', "-.--"),
+    ('Ь', "-..-"),  ('Э', "..-.."), ('Ю', "..--"),  ('Я', ".-.-"),
+];
+
+/* Greek */
+#[cfg(feature = "greek")]
+pub const GREEK: &[(char, &str)] = &[
+    ('Α', ".-"),    ('Β', "-..."),  ('Γ', "--."),   ('Δ', "-.."),
+    ('Ε', "."),     ('Ζ', "--.."),  ('Η', "...."),  ('Θ', "-.-."),
+    ('Ι', ".."),    ('Κ', "-.-"),   ('Λ', ".-.."),  ('Μ', "--"),
+    ('Ν', "-."),    ('Ξ', "-..-"),  ('Ο', "---"),   ('Π', ".--."),
+    ('Ρ', ".-."),   ('Σ', "..."),   ('Τ', "-"),     ('Υ', "-.--"),
+    ('Φ', "..-."),  ('Χ', "----"),  ('Ψ', "--.-"),  ('Ω', ".--"),
+];
+
+/* Wabun code (Japanese katakana, iroha order) */
+#[cfg(feature = "wabun")]
+pub const WABUN: &[(char, &str)] = &[
+    ('イ', ".-"),    ('ロ', ".-.-"),  ('ハ', "-..."),  ('ニ', "-.-."),
+    ('ホ', "-.."),   ('ヘ', "."),     ('ト', "..-.."), ('チ', "..-."),
+    ('リ', "--."),   ('ヌ', "...."),  ('ル', "-.--."), ('ヲ', ".---"),
+    ('ワ', "-.-"),   ('カ', ".-.."),  ('ヨ', "--"),    ('タ', "-."),
+    ('レ', "---"),   ('ソ', "---."),  ('ツ', ".--."),  ('ネ', "--.-"),
+    ('ナ', ".-."),   ('ラ', "..."),   ('ム', "-"),     ('ウ', "..-"),
+    ('ヰ', ".-..-"), ('ノ', "..--"),  ('オ', ".-..."), ('ク', "...-"),
+    ('ヤ', ".--"),   ('マ', "-..-"),  ('ケ', "-.--"),  ('フ', "--.."),
+    ('コ', "----"),  ('エ', "-.---"), ('テ', ".-.--"), ('ア', "--.--"),
+    ('サ', "-.-.-"), ('キ', "-.-.."), ('ユ', "-..--"), ('メ', "-...-"),
+    ('ミ', "..-.-"), ('シ', "--.-."), ('ヱ', ".--.."), ('ヒ', "--..-"),
+    ('モ', "-..-."), ('セ', ".---."), ('ス', "---.-"), ('ン', ".-.-."),
+    ('゛', ".."),    ('゜', "..--."), ('ー', ".--.-"),
+];
@@ -0,0 +1,325 @@
+/* Adaptive Morse decoder */
+/* Fed with the rising and falling edge timestamps of a straight key.
+   1. Key-down time is a mark: shorter than 2 dots is a dit, longer is a dah
+   2. Key-up time is a gap: 2 dots or more ends a character, 5 dots or more ends a word
+   3. Every mark nudges the dot estimate so the decoder follows the operator's speed
+   Timestamps are free-running microsecond counters and may wrap around. */
+
+use crate::morse::{self, Charset, Decoded};
+use crate::timing::dot_us;
+
+/* Longest pattern we collect before giving up on a character */
+const MAX_MARKS: usize = 10;
+
+/* Size of the decoded output queue */
+const QUEUE_LEN: usize = 16;
+
+/* Limits for the dot estimate: 10 ms (120 WPM) to 400 ms (3 WPM) */
+const MIN_DOT_US: u32 = 10_000;
+const MAX_DOT_US: u32 = 400_000;
+
+/* One decoded output symbol */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symbol {
+    Char(char),     /* Character found in the table */
+    Prosign(&'static str),  /* Prosign or digraph name, e.g. "SK" */
+    Unknown,        /* Complete character with a pattern we don't know */
+    Space,          /* Word boundary */
+}
+
+pub struct Decoder {
+    dot_us: u32,                 /* Current dot length estimate */
+    charset: Charset,            /* Table letters are decoded with */
+    marks: [u8; MAX_MARKS],      /* '.' and '-' of the character being received */
+    len: usize,                  /* Number of marks collected */
+    overflow: bool,              /* Character had more marks than we can hold */
+    down_at: Option<u32>,        /* Timestamp of the last key-down, None while key is up */
+    up_at: Option<u32>,          /* Timestamp of the last key-up, None before the first mark */
+    in_word: bool,               /* A character was emitted since the last word gap */
+    queue: [Symbol; QUEUE_LEN],  /* Ring buffer of decoded symbols */
+    head: usize,
+    count: usize,
+}
+
+impl Decoder {
+    /* Create a decoder starting at the given speed guess, held to 3-120 WPM
+       like the estimate it adapts */
+    pub const fn new(wpm: u32) -> Self {
+        let dot_us = dot_us(wpm);
+        let dot_us = if dot_us < MIN_DOT_US {
+            MIN_DOT_US
+        } else if dot_us > MAX_DOT_US {
+            MAX_DOT_US
+        } else {
+            dot_us
+        };
+        Decoder {
+            dot_us,
+            charset: Charset::Latin,
+            marks: [0; MAX_MARKS],
+            len: 0,
+            overflow: false,
+            down_at: None,
+            up_at: None,
+            in_word: false,
+            queue: [Symbol::Space; QUEUE_LEN],
+            head: 0,
+            count: 0,
+        }
+    }
+
+    /* Current dot length estimate in microseconds */
+    pub fn dot_us(&self) -> u32 {
+        self.dot_us
+    }
+
+    /* Current speed estimate in words per minute */
+    pub fn wpm(&self) -> u32 {
+        1_200_000 / self.dot_us
+    }
+
+    /* Choose the character set letters are decoded with */
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /* Rising edge: the key was pressed at time t */
+    pub fn key_down(&mut self, t: u32) {
+        if self.down_at.is_some() {
+            return;
+        }
+        /* The gap that just ended decides character and word boundaries */
+        if let Some(up) = self.up_at {
+            self.check_gap(t.wrapping_sub(up));
+        }
+        self.down_at = Some(t);
+    }
+
+    /* Falling edge: the key was released at time t */
+    pub fn key_up(&mut self, t: u32) {
+        let down = match self.down_at.take() {
+            Some(down) => down,
+            None => return,
+        };
+        let mark = t.wrapping_sub(down);
+
+        /* Contact bounce: far too short to be a dit, pretend it never happened */
+        if mark < self.dot_us / 4 {
+            return;
+        }
+
+        /* Classify and adapt the dot estimate (1/4 weight for the new sample) */
+        let (symbol, sample) = if mark < 2 * self.dot_us {
+            (b'.', mark)
+        } else {
+            (b'-', mark / 3)
+        };
+        let dot = (3 * self.dot_us + sample) / 4;
+        self.dot_us = dot.clamp(MIN_DOT_US, MAX_DOT_US);
+
+        if self.len < MAX_MARKS {
+            self.marks[self.len] = symbol;
+            self.len += 1;
+        } else {
+            self.overflow = true;
+        }
+        self.up_at = Some(t);
+    }
+
+    /* Call periodically while idle so the last character and word gap get flushed
+       without waiting for the next key press */
+    pub fn poll(&mut self, now: u32) {
+        if self.down_at.is_some() {
+            return;
+        }
+        if let Some(up) = self.up_at {
+            self.check_gap(now.wrapping_sub(up));
+        }
+    }
+
+    /* Take the next decoded symbol, oldest first */
+    pub fn read(&mut self) -> Option<Symbol> {
+        if self.count == 0 {
+            return None;
+        }
+        let symbol = self.queue[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.count -= 1;
+        Some(symbol)
+    }
+
+    /* Forget any half-received character and the output queue, keep the speed estimate
+       and character set */
+    pub fn reset(&mut self) {
+        let (dot_us, charset) = (self.dot_us, self.charset);
+        *self = Decoder::new(1);
+        self.dot_us = dot_us;
+        self.charset = charset;
+    }
+
+    fn check_gap(&mut self, gap: u32) {
+        if gap >= 2 * self.dot_us {
+            self.finish_char();
+        }
+        if gap >= 5 * self.dot_us && self.in_word {
+            self.in_word = false;
+            self.push(Symbol::Space);
+        }
+    }
+
+    fn finish_char(&mut self) {
+        if self.len == 0 && !self.overflow {
+            return;
+        }
+        /* Marks only ever hold '.' or '-', so the slice is valid UTF-8 */
+        let pattern = core::str::from_utf8(&self.marks[..self.len]).unwrap_or("");
+        let symbol = match morse::decode(pattern, self.charset) {
+            _ if self.overflow => Symbol::Unknown,
+            Some(Decoded::Char(c)) => Symbol::Char(c),
+            Some(Decoded::Prosign(name)) => Symbol::Prosign(name),
+            None => Symbol::Unknown,
+        };
+        self.len = 0;
+        self.overflow = false;
+        self.in_word = true;
+        self.push(symbol);
+    }
+
+    fn push(&mut self, symbol: Symbol) {
+        /* When full, drop the oldest symbol rather than the newest */
+        if self.count == QUEUE_LEN {
+            self.head = (self.head + 1) % QUEUE_LEN;
+            self.count -= 1;
+        }
+        self.queue[(self.head + self.count) % QUEUE_LEN] = symbol;
+        self.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::Timing;
+
+    /* Key the text into a decoder with exact timing, starting at t, returns the end time */
+    fn key(decoder: &mut Decoder, text: &str, timing: &Timing, mut t: u32) -> u32 {
+        for element in morse::encode(text) {
+            if element.is_key_down() {
+                decoder.key_down(t);
+                t = t.wrapping_add(timing.duration_us(element));
+                decoder.key_up(t);
+            } else {
+                t = t.wrapping_add(timing.duration_us(element));
+            }
+        }
+        t
+    }
+
+    fn read_all(decoder: &mut Decoder) -> String {
+        let mut text = String::new();
+        while let Some(symbol) = decoder.read() {
+            match symbol {
+                Symbol::Char(c) => text.push(c),
+                Symbol::Prosign(name) => {
+                    text.push('<');
+                    text.push_str(name);
+                    text.push('>');
+                }
+                Symbol::Unknown => text.push('?'),
+                Symbol::Space => text.push(' '),
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn decodes_at_the_starting_speed() {
+        let mut decoder = Decoder::new(20);
+        let timing = Timing::standard(20);
+        let t = key(&mut decoder, "PARIS MORSE", &timing, 0);
+        decoder.poll(t + 10 * timing.dot_us);
+        assert_eq!(read_all(&mut decoder), "PARIS MORSE ");
+    }
+
+    #[test]
+    fn follows_a_speed_change() {
+        let mut decoder = Decoder::new(12);
+        let timing = Timing::standard(25);
+        let t = key(&mut decoder, "EEEE TTTT ", &timing, 0);
+        let t = key(&mut decoder, "THE QUICK FOX", &timing, t + timing.word_gap_us);
+        decoder.poll(t + 10 * timing.dot_us);
+        assert!(read_all(&mut decoder).ends_with("THE QUICK FOX "));
+        assert!((decoder.wpm() as i32 - 25).abs() <= 2, "wpm = {}", decoder.wpm());
+    }
+
+    #[test]
+    fn ignores_contact_bounce() {
+        let mut decoder = Decoder::new(20);
+        let dot = decoder.dot_us();
+        decoder.key_down(0);
+        decoder.key_up(1_000);
+        decoder.key_down(2_000);
+        decoder.key_up(2_000 + dot);
+        decoder.poll(2_000 + 4 * dot);
+        assert_eq!(decoder.read(), Some(Symbol::Char('E')));
+        assert_eq!(decoder.read(), None);
+    }
+
+    #[test]
+    fn survives_timer_wraparound() {
+        let mut decoder = Decoder::new(20);
+        let timing = Timing::standard(20);
+        let t = key(&mut decoder, "CQ", &timing, u32::MAX - 500_000);
+        decoder.poll(t.wrapping_add(10 * timing.dot_us));
+        assert_eq!(read_all(&mut decoder), "CQ ");
+    }
+
+    #[test]
+    fn too_many_marks_is_unknown() {
+        let mut decoder = Decoder::new(20);
+        let dot = decoder.dot_us();
+        let mut t = 0;
+        for _ in 0..12 {
+            decoder.key_down(t);
+            decoder.key_up(t + dot);
+            t += 2 * dot - dot / 2;
+        }
+        decoder.poll(t + 4 * dot);
+        assert_eq!(decoder.read(), Some(Symbol::Unknown));
+    }
+
+    #[test]
+    fn decodes_prosigns() {
+        let mut decoder = Decoder::new(20);
+        let timing = Timing::standard(20);
+        let t = key(&mut decoder, "<SK>", &timing, 0);
+        decoder.poll(t + 4 * timing.dot_us);
+        assert_eq!(decoder.read(), Some(Symbol::Prosign("SK")));
+    }
+
+    #[test]
+    fn queue_keeps_the_newest_symbols() {
+        let mut decoder = Decoder::new(20);
+        let timing = Timing::standard(20);
+        let t = key(&mut decoder, "ABCDEFGHIJKLMNOPQRST", &timing, 0);
+        decoder.poll(t + 4 * timing.dot_us);
+        assert_eq!(read_all(&mut decoder), "EFGHIJKLMNOPQRST");
+    }
+
+    #[test]
+    fn reset_keeps_speed_and_charset() {
+        let mut decoder = Decoder::new(20);
+        decoder.key_down(0);
+        decoder.key_up(100_000);
+        let dot = decoder.dot_us();
+        decoder.reset();
+        assert_eq!(decoder.dot_us(), dot);
+        assert_eq!(decoder.charset(), Charset::Latin);
+        decoder.poll(10_000_000);
+        assert_eq!(decoder.read(), None);
+    }
+}
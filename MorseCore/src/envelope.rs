@@ -0,0 +1,189 @@
+/* Keying envelope for the sidetone */
+/* Ramps the tone amplitude up at key-down and down at key-up so the keyed
+   tone doesn't click. Stepped at a fixed tick rate from a timer interrupt.
+   All maths is integer: the Cortex-M0+ has no FPU.
+   1. Phase runs 0..=PHASE_MAX along the ramp (Q16)
+   2. Level is the amplitude 0..=LEVEL_MAX (Q15) for the current phase
+   3. Rise starts at key-down, fall starts at key-up, so mark lengths stay exact */
+
+/* Full amplitude */
+pub const LEVEL_MAX: u32 = 1 << 15;
+
+/* End of the ramp */
+const PHASE_MAX: u32 = 1 << 16;
+
+/* Allowed ramp lengths */
+const MIN_RAMP_US: u32 = 1_000;
+const MAX_RAMP_US: u32 = 10_000;
+
+/* (1 - cos(pi * i / 32)) / 2 in Q15, i = 0..=32 */
+const RAISED_COSINE: [u16; 33] = [
+    0, 79, 315, 705, 1247, 1935, 2761, 3719,
+    4799, 5990, 7282, 8661, 10114, 11628, 13188, 14778,
+    16384, 17990, 19580, 21140, 22654, 24107, 25486, 26778,
+    27969, 29049, 30007, 30833, 31521, 32063, 32453, 32689,
+    32768,
+];
+
+/* Ramp shape */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Linear,
+    RaisedCosine,
+}
+
+pub struct Envelope {
+    shape: Shape,
+    rise_step: u32,   /* Phase added per tick while the key is down */
+    fall_step: u32,   /* Phase removed per tick while the key is up */
+    tick_hz: u32,     /* Rate tick() is called at */
+    phase: u32,       /* Position along the ramp */
+    key: bool,        /* Current key state */
+}
+
+/* Phase step for a ramp of ramp_us at tick_hz (at least one tick per ramp) */
+const fn phase_step(ramp_us: u32, tick_hz: u32) -> u32 {
+    let ramp_us = if ramp_us < MIN_RAMP_US {
+        MIN_RAMP_US
+    } else if ramp_us > MAX_RAMP_US {
+        MAX_RAMP_US
+    } else {
+        ramp_us
+    };
+    let ticks = ramp_us * tick_hz / 1_000_000;
+    let ticks = if ticks == 0 { 1 } else { ticks };
+    PHASE_MAX.div_ceil(ticks)
+}
+
+impl Envelope {
+    /* rise_us and fall_us are clamped to 1-10 ms, tick_hz is the rate tick() is called at */
+    pub const fn new(shape: Shape, rise_us: u32, fall_us: u32, tick_hz: u32) -> Self {
+        Envelope {
+            shape,
+            rise_step: phase_step(rise_us, tick_hz),
+            fall_step: phase_step(fall_us, tick_hz),
+            tick_hz,
+            phase: 0,
+            key: false,
+        }
+    }
+
+    pub fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+
+    /* Start the rise (true) or the fall (false) from wherever the ramp is now */
+    pub fn key(&mut self, down: bool) {
+        self.key = down;
+    }
+
+    /* Advance one tick and return the new level */
+    pub fn tick(&mut self) -> u32 {
+        self.phase = if self.key {
+            (self.phase + self.rise_step).min(PHASE_MAX)
+        } else {
+            self.phase.saturating_sub(self.fall_step)
+        };
+        self.level()
+    }
+
+    /* Amplitude for the current phase, 0..=LEVEL_MAX */
+    pub fn level(&self) -> u32 {
+        match self.shape {
+            Shape::Linear => self.phase >> 1,
+            Shape::RaisedCosine => {
+                /* 32 table segments of 2048 phase steps, interpolate inside a segment */
+                let index = (self.phase >> 11) as usize;
+                if index >= 32 {
+                    return LEVEL_MAX;
+                }
+                let frac = self.phase & 0x7ff;
+                let a = RAISED_COSINE[index] as u32;
+                let b = RAISED_COSINE[index + 1] as u32;
+                a + (((b - a) * frac) >> 11)
+            }
+        }
+    }
+
+    /* Ramp finished: fully on with the key down, or silent with the key up */
+    pub fn is_settled(&self) -> bool {
+        if self.key {
+            self.phase == PHASE_MAX
+        } else {
+            self.phase == 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rises_and_falls_within_the_ramp_time() {
+        for shape in [Shape::Linear, Shape::RaisedCosine] {
+            /* 5 ms at 4 kHz: 20 ticks */
+            let mut envelope = Envelope::new(shape, 5_000, 5_000, 4_000);
+            assert_eq!(envelope.level(), 0);
+            envelope.key(true);
+            let mut last = 0;
+            for _ in 0..20 {
+                let level = envelope.tick();
+                assert!(level >= last);
+                last = level;
+            }
+            assert_eq!(last, LEVEL_MAX);
+            assert!(envelope.is_settled());
+            envelope.key(false);
+            assert!(!envelope.is_settled());
+            for _ in 0..20 {
+                let level = envelope.tick();
+                assert!(level <= last);
+                last = level;
+            }
+            assert_eq!(last, 0);
+            assert!(envelope.is_settled());
+        }
+    }
+
+    #[test]
+    fn ramp_length_is_clamped() {
+        /* 100 us asks for less than a millisecond: clamped to 1 ms = 4 ticks */
+        let mut envelope = Envelope::new(Shape::Linear, 100, 100, 4_000);
+        envelope.key(true);
+        let ticks = (1..).find(|_| envelope.tick() == LEVEL_MAX).unwrap();
+        assert_eq!(ticks, 4);
+    }
+
+    #[test]
+    fn raised_cosine_is_symmetric() {
+        let mut envelope = Envelope::new(Shape::RaisedCosine, 8_000, 8_000, 4_000);
+        envelope.key(true);
+        let rise: Vec<u32> = (0..32).map(|_| envelope.tick()).collect();
+        envelope.key(false);
+        let fall: Vec<u32> = (0..32).map(|_| envelope.tick()).collect();
+        /* The fall retraces the rise: up at tick i matches down at tick 30 - i */
+        assert_eq!(rise[31], LEVEL_MAX);
+        assert_eq!(fall[31], 0);
+        for i in 0..31 {
+            assert!(rise[i].abs_diff(fall[30 - i]) <= 1, "tick {i}: {} {}", rise[i], fall[30 - i]);
+        }
+        /* The curve itself is point-symmetric about the half-way level */
+        for i in 0..31 {
+            assert!((rise[i] + rise[30 - i]).abs_diff(LEVEL_MAX) < 64, "tick {i}");
+        }
+        assert!(rise[15].abs_diff(LEVEL_MAX / 2) < 64);
+    }
+
+    #[test]
+    fn key_up_mid_ramp_falls_from_current_level() {
+        let mut envelope = Envelope::new(Shape::Linear, 5_000, 5_000, 4_000);
+        envelope.key(true);
+        for _ in 0..5 {
+            envelope.tick();
+        }
+        let peak = envelope.level();
+        envelope.key(false);
+        assert!(envelope.tick() < peak);
+    }
+}
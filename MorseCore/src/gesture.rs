@@ -0,0 +1,166 @@
+/* Push button gesture recogniser */
+/* Fed with press and release timestamps from the button interrupt and polled
+   from the main loop:
+   1. Short presses in quick succession are counted: 1, 2, 3 clicks
+   2. The count is reported once the button has stayed up for CLICK_GAP_US
+   3. A press held for HOLD_US is reported as a hold while still held
+   Timestamps are free-running microsecond counters and may wrap around. */
+
+/* Presses shorter than this are contact bounce */
+const DEBOUNCE_US: u32 = 20_000;
+
+/* Button up this long ends a click sequence */
+const CLICK_GAP_US: u32 = 400_000;
+
+/* Button down this long is a hold */
+const HOLD_US: u32 = 1_000_000;
+
+/* A recognised gesture */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Click(u8),  /* Number of short presses */
+    Hold,
+}
+
+pub struct Gestures {
+    down_at: Option<u32>,  /* Timestamp of the current press, None while the button is up */
+    up_at: u32,            /* Timestamp of the last counted release */
+    clicks: u8,            /* Short presses counted so far */
+    ignore: bool,          /* Current press was a hold or was cancelled */
+    hold: bool,            /* Hold released before poll() saw it */
+}
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Gestures::new()
+    }
+}
+
+impl Gestures {
+    pub const fn new() -> Self {
+        Gestures {
+            down_at: None,
+            up_at: 0,
+            clicks: 0,
+            ignore: false,
+            hold: false,
+        }
+    }
+
+    /* Button pressed at time t */
+    pub fn press(&mut self, t: u32) {
+        if self.down_at.is_none() {
+            self.down_at = Some(t);
+            self.ignore = false;
+        }
+    }
+
+    /* Button released at time t */
+    pub fn release(&mut self, t: u32) {
+        let down = match self.down_at.take() {
+            Some(down) => down,
+            None => return,
+        };
+        if self.ignore || t.wrapping_sub(down) < DEBOUNCE_US {
+            return;
+        }
+        if t.wrapping_sub(down) >= HOLD_US {
+            /* Held but not polled in time: still a hold, report it on the next poll */
+            self.clicks = 0;
+            self.hold = true;
+            return;
+        }
+        self.clicks = self.clicks.saturating_add(1);
+        self.up_at = t;
+    }
+
+    /* Check for a finished gesture, call regularly while not idle */
+    pub fn poll(&mut self, now: u32) -> Option<Gesture> {
+        if self.hold {
+            self.hold = false;
+            return Some(Gesture::Hold);
+        }
+        if let Some(down) = self.down_at {
+            if !self.ignore && now.wrapping_sub(down) >= HOLD_US {
+                self.ignore = true;
+                self.clicks = 0;
+                return Some(Gesture::Hold);
+            }
+            return None;
+        }
+        if self.clicks > 0 && now.wrapping_sub(self.up_at) >= CLICK_GAP_US {
+            let clicks = self.clicks;
+            self.clicks = 0;
+            return Some(Gesture::Click(clicks));
+        }
+        None
+    }
+
+    /* Drop the clicks so far and ignore the press in progress (it was used for something else) */
+    pub fn cancel(&mut self) {
+        self.clicks = 0;
+        self.hold = false;
+        self.ignore = true;
+    }
+
+    /* Nothing in progress: the main loop can sleep until the next press */
+    pub fn is_idle(&self) -> bool {
+        self.down_at.is_none() && self.clicks == 0 && !self.hold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_clicks() {
+        let mut gestures = Gestures::new();
+        gestures.press(0);
+        gestures.release(100_000);
+        gestures.press(200_000);
+        gestures.release(300_000);
+        assert_eq!(gestures.poll(500_000), None);
+        assert!(!gestures.is_idle());
+        assert_eq!(gestures.poll(700_000), Some(Gesture::Click(2)));
+        assert!(gestures.is_idle());
+    }
+
+    #[test]
+    fn hold_is_reported_while_held() {
+        let mut gestures = Gestures::new();
+        gestures.press(0);
+        assert_eq!(gestures.poll(500_000), None);
+        assert_eq!(gestures.poll(HOLD_US), Some(Gesture::Hold));
+        assert_eq!(gestures.poll(HOLD_US + 1), None);
+        gestures.release(2 * HOLD_US);
+        assert_eq!(gestures.poll(3 * HOLD_US), None);
+        assert!(gestures.is_idle());
+    }
+
+    #[test]
+    fn late_poll_still_sees_the_hold() {
+        let mut gestures = Gestures::new();
+        gestures.press(0);
+        gestures.release(HOLD_US + 1);
+        assert_eq!(gestures.poll(HOLD_US + 2), Some(Gesture::Hold));
+    }
+
+    #[test]
+    fn ignores_bounce() {
+        let mut gestures = Gestures::new();
+        gestures.press(0);
+        gestures.release(DEBOUNCE_US - 1);
+        assert!(gestures.is_idle());
+    }
+
+    #[test]
+    fn cancel_swallows_the_press() {
+        let mut gestures = Gestures::new();
+        gestures.press(0);
+        gestures.cancel();
+        gestures.release(100_000);
+        assert_eq!(gestures.poll(HOLD_US * 2), None);
+        assert!(gestures.is_idle());
+    }
+}
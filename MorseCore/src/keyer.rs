@@ -0,0 +1,233 @@
+/* Iambic paddle keyer */
+/* Turns the state of a dit paddle and a dah paddle into a keying timeline.
+   The caller plays every element it gets back and keeps feeding the paddle state:
+   1. next() at every element boundary, returns the element to play (None = idle)
+   2. sample() as often as it likes while an element plays, to latch paddle memory
+   Every mark is followed by a one unit IntraGap, so the output never needs extra spacing. */
+
+use crate::morse::Element;
+
+/* Squeeze behaviour when both paddles are held */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyerMode {
+    IambicA,    /* Alternate dits and dahs, stop as soon as both paddles are released */
+    IambicB,    /* Like A, but releasing during a squeeze sends one more opposite element */
+    Ultimatic,  /* The paddle pressed last wins and repeats */
+}
+
+pub struct Keyer {
+    mode: KeyerMode,
+    auto_space: bool,              /* Stretch the idle gap after a character to 3 units */
+    last_mark: Option<Element>,    /* Last Dit or Dah sent, for alternation */
+    last_pressed: Option<Element>, /* Paddle that went down most recently (ultimatic) */
+    dit_down: bool,                /* Paddle state at the previous sample */
+    dah_down: bool,
+    dit_mem: bool,                 /* Dit paddle was tapped while something else played */
+    dah_mem: bool,                 /* Dah paddle was tapped while something else played */
+    opposite_armed: bool,          /* Opposite paddle may set memory during this element */
+    mark_ended: bool,              /* A mark just finished, its IntraGap is due */
+    space_left: u32,               /* Auto-space units still to play */
+    in_char: bool,                 /* Marks were sent since the last character gap */
+}
+
+/* The other paddle's element */
+const fn opposite(mark: Element) -> Element {
+    match mark {
+        Element::Dit => Element::Dah,
+        _ => Element::Dit,
+    }
+}
+
+impl Keyer {
+    pub const fn new(mode: KeyerMode, auto_space: bool) -> Self {
+        Keyer {
+            mode,
+            auto_space,
+            last_mark: None,
+            last_pressed: None,
+            dit_down: false,
+            dah_down: false,
+            dit_mem: false,
+            dah_mem: false,
+            opposite_armed: false,
+            mark_ended: false,
+            space_left: 0,
+            in_char: false,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: KeyerMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_auto_space(&mut self, auto_space: bool) {
+        self.auto_space = auto_space;
+    }
+
+    /* Feed the paddle state while an element is playing */
+    pub fn sample(&mut self, dit: bool, dah: bool) {
+        /* Remember which paddle was pressed last (used by ultimatic squeezes) */
+        if dit && !self.dit_down {
+            self.last_pressed = Some(Element::Dit);
+        }
+        if dah && !self.dah_down {
+            self.last_pressed = Some(Element::Dah);
+        }
+        self.dit_down = dit;
+        self.dah_down = dah;
+
+        /* Memory: the opposite paddle going down during an element queues it.
+           In mode A and ultimatic a paddle that was already held when the element
+           started only counts once it has been released and pressed again. */
+        let mark = match self.last_mark {
+            Some(mark) => mark,
+            None => return,
+        };
+        let other = opposite(mark);
+        let other_down = if other == Element::Dit { dit } else { dah };
+        if !other_down {
+            self.opposite_armed = true;
+        } else if self.opposite_armed {
+            match other {
+                Element::Dit => self.dit_mem = true,
+                _ => self.dah_mem = true,
+            }
+        }
+    }
+
+    /* Called when the previous element has finished: returns the next one to play */
+    pub fn next(&mut self, dit: bool, dah: bool) -> Option<Element> {
+        self.sample(dit, dah);
+
+        /* Every mark is followed by one unit of silence */
+        if self.mark_ended {
+            self.mark_ended = false;
+            return Some(Element::IntraGap);
+        }
+
+        /* Auto-space: paddles are ignored (memory still latches) until the
+           character gap is complete */
+        if self.space_left > 0 {
+            self.space_left -= 1;
+            return Some(Element::IntraGap);
+        }
+
+        let mark = if self.dit_mem && self.dah_mem {
+            self.last_mark.map(opposite).unwrap_or(Element::Dit)
+        } else if self.dit_mem {
+            Element::Dit
+        } else if self.dah_mem {
+            Element::Dah
+        } else if dit && dah {
+            match self.mode {
+                KeyerMode::Ultimatic => self.last_pressed.unwrap_or(Element::Dit),
+                _ => self.last_mark.map(opposite).unwrap_or(Element::Dit),
+            }
+        } else if dit {
+            Element::Dit
+        } else if dah {
+            Element::Dah
+        } else {
+            /* Idle: one IntraGap has already elapsed, two more make a character gap */
+            if self.auto_space && self.in_char {
+                self.in_char = false;
+                self.space_left = Element::CharGap.units() - 2 * Element::IntraGap.units();
+                return Some(Element::IntraGap);
+            }
+            self.in_char = false;
+            return None;
+        };
+
+        match mark {
+            Element::Dit => self.dit_mem = false,
+            _ => self.dah_mem = false,
+        }
+        let other_down = if mark == Element::Dit { dah } else { dit };
+        self.opposite_armed = self.mode == KeyerMode::IambicB || !other_down;
+        self.last_mark = Some(mark);
+        self.mark_ended = true;
+        self.in_char = true;
+        Some(mark)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Run the keyer with paddle state given per dot unit: paddles(t) -> (dit, dah).
+       Returns the marks sent as '.' and '-', with ' ' for auto-space gaps. */
+    fn run(mode: KeyerMode, auto_space: bool, units: u32, paddles: impl Fn(u32) -> (bool, bool)) -> String {
+        let mut keyer = Keyer::new(mode, auto_space);
+        let mut out = String::new();
+        let mut t = 0;
+        let mut gaps = 0;
+        while t < units {
+            let (dit, dah) = paddles(t);
+            match keyer.next(dit, dah) {
+                Some(element) => {
+                    match element {
+                        Element::Dit => out.push('.'),
+                        Element::Dah => out.push('-'),
+                        _ => gaps += 1,
+                    }
+                    if element.is_key_down() {
+                        gaps = 0;
+                    } else if gaps == 3 {
+                        out.push(' ');
+                    }
+                    for u in 0..element.units() {
+                        let (dit, dah) = paddles(t + u);
+                        keyer.sample(dit, dah);
+                    }
+                    t += element.units();
+                }
+                None => t += 1,
+            }
+        }
+        out.trim_end().to_string()
+    }
+
+    #[test]
+    fn single_paddles_repeat() {
+        assert_eq!(run(KeyerMode::IambicA, false, 6, |_| (true, false)), "...");
+        assert_eq!(run(KeyerMode::IambicA, false, 8, |_| (false, true)), "--");
+    }
+
+    #[test]
+    fn squeeze_alternates() {
+        /* Both paddles held for 10 units, dit first */
+        let squeeze = |t: u32| (t < 10, t < 10 && t > 0);
+        assert_eq!(run(KeyerMode::IambicA, false, 20, squeeze), ".-.-");
+    }
+
+    #[test]
+    fn mode_b_adds_one_element_on_release() {
+        /* Squeeze released during the dah */
+        let squeeze = |t: u32| (t < 4, t > 0 && t < 4);
+        assert_eq!(run(KeyerMode::IambicA, false, 20, squeeze), ".-");
+        assert_eq!(run(KeyerMode::IambicB, false, 20, squeeze), ".-.");
+    }
+
+    #[test]
+    fn ultimatic_last_paddle_wins() {
+        /* Dit first, then dah added and both held: dahs repeat */
+        let squeeze = |t: u32| (t < 14, t > 0 && t < 14);
+        assert_eq!(run(KeyerMode::Ultimatic, false, 20, squeeze), ".---");
+    }
+
+    #[test]
+    fn dit_memory_from_a_tap() {
+        /* Dah held, dit tapped during the first dah */
+        let paddles = |t: u32| (t == 1, t < 3);
+        assert_eq!(run(KeyerMode::IambicA, false, 20, paddles), "-.");
+    }
+
+    #[test]
+    fn auto_space_stretches_character_gap() {
+        /* Two dits, released for a unit, then two more */
+        let paddles = |t: u32| (t < 3 || (6..9).contains(&t), false);
+        assert_eq!(run(KeyerMode::IambicA, false, 20, paddles), "....");
+        assert_eq!(run(KeyerMode::IambicA, true, 20, paddles), ".. ..");
+    }
+}
@@ -0,0 +1,219 @@
+/* Resistor ladder button decoder */
+/* Several buttons share one ADC input: with a pull-up on the pin, each button
+   pulls it down through a different resistor, so each gives its own reading.
+   Fed with ADC samples and their timestamps:
+   1. Each button owns the readings closer to its nominal level than to any
+      other one, released is everything above the last button's window
+   2. Hysteresis: a reading only leaves the current window once it is more than
+      `hysteresis` counts past the boundary, so noise at a boundary can't flicker
+   3. Debounce: a new state has to hold for `debounce_us` before it counts
+   4. Going straight from one button to another reports the release first,
+      then the press on the next sample
+   Events carry the time the new state first appeared, so the debounce delay
+   doesn't skew press lengths. Timestamps may wrap around. */
+
+/* Largest 12-bit ADC reading, what the pull-up gives with nothing pressed */
+pub const FULL_SCALE: u16 = 4095;
+
+/* A button and its nominal ADC reading */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Level<B> {
+    pub button: B,
+    pub reading: u16,
+}
+
+/* Button pressed or released at time `at` */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event<B> {
+    pub button: B,
+    pub pressed: bool,
+    pub at: u32,
+}
+
+pub struct Ladder<B: 'static> {
+    levels: &'static [Level<B>],    /* Ascending readings */
+    hysteresis: u16,
+    debounce_us: u32,
+    state: Option<usize>,           /* Debounced: index of the button held, None = released */
+    candidate: Option<usize>,       /* Raw state of the latest samples */
+    since: u32,                     /* When the candidate first appeared */
+}
+
+impl<B: Copy> Ladder<B> {
+    /* levels must be sorted by reading, lowest first */
+    pub const fn new(levels: &'static [Level<B>], hysteresis: u16, debounce_us: u32) -> Self {
+        Ladder {
+            levels,
+            hysteresis,
+            debounce_us,
+            state: None,
+            candidate: None,
+            since: 0,
+        }
+    }
+
+    /* Readings [low, high] belonging to a state, released runs up to FULL_SCALE */
+    fn window(&self, state: Option<usize>) -> (u16, u16) {
+        let midpoint = |a: u16, b: u16| ((a as u32 + b as u32) / 2) as u16;
+        let n = self.levels.len();
+        let top = |i: usize| {
+            let next = self.levels.get(i + 1).map_or(FULL_SCALE, |level| level.reading);
+            midpoint(self.levels[i].reading, next)
+        };
+        match state {
+            None => (if n == 0 { 0 } else { top(n - 1) + 1 }, FULL_SCALE),
+            Some(0) => (0, top(0)),
+            Some(i) => (top(i - 1) + 1, top(i)),
+        }
+    }
+
+    /* State of a single reading without hysteresis */
+    fn classify(&self, reading: u16) -> Option<usize> {
+        (0..self.levels.len()).find(|&i| {
+            let (low, high) = self.window(Some(i));
+            (low..=high).contains(&reading)
+        })
+    }
+
+    /* Feed one sample */
+    pub fn sample(&mut self, reading: u16, now: u32) -> Option<Event<B>> {
+        /* Stay put while within hysteresis of the current window */
+        let (low, high) = self.window(self.state);
+        let raw = if reading >= low.saturating_sub(self.hysteresis)
+            && reading <= high.saturating_add(self.hysteresis)
+        {
+            self.state
+        } else {
+            self.classify(reading)
+        };
+
+        if raw != self.candidate {
+            self.candidate = raw;
+            self.since = now;
+        }
+        if self.candidate == self.state || now.wrapping_sub(self.since) < self.debounce_us {
+            return None;
+        }
+
+        match self.state {
+            /* Release the old button first, the new one is pressed on the next sample */
+            Some(old) => {
+                self.state = None;
+                Some(Event {
+                    button: self.levels[old].button,
+                    pressed: false,
+                    at: self.since,
+                })
+            }
+            None => {
+                self.state = self.candidate;
+                self.candidate.map(|new| Event {
+                    button: self.levels[new].button,
+                    pressed: true,
+                    at: self.since,
+                })
+            }
+        }
+    }
+
+    /* Debounced button held down, if any */
+    pub fn pressed(&self) -> Option<B> {
+        self.state.map(|i| self.levels[i].button)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Button {
+        Key,
+        Mode,
+        Menu,
+    }
+
+    const LEVELS: &[Level<Button>] = &[
+        Level { button: Button::Key, reading: 0 },
+        Level { button: Button::Mode, reading: 1365 },
+        Level { button: Button::Menu, reading: 2730 },
+    ];
+
+    const DEBOUNCE_US: u32 = 5_000;
+
+    fn ladder() -> Ladder<Button> {
+        Ladder::new(LEVELS, 100, DEBOUNCE_US)
+    }
+
+    fn press(button: Button, at: u32) -> Option<Event<Button>> {
+        Some(Event { button, pressed: true, at })
+    }
+
+    fn release(button: Button, at: u32) -> Option<Event<Button>> {
+        Some(Event { button, pressed: false, at })
+    }
+
+    #[test]
+    fn windows_split_at_midpoints() {
+        let ladder = ladder();
+        assert_eq!(ladder.window(Some(0)), (0, 682));
+        assert_eq!(ladder.window(Some(1)), (683, 2047));
+        assert_eq!(ladder.window(Some(2)), (2048, 3412));
+        assert_eq!(ladder.window(None), (3413, FULL_SCALE));
+    }
+
+    #[test]
+    fn press_and_release_after_debounce() {
+        let mut ladder = ladder();
+        assert_eq!(ladder.sample(4095, 0), None);
+        assert_eq!(ladder.sample(1400, 1_000), None);
+        assert_eq!(ladder.sample(1350, 3_000), None);
+        assert_eq!(ladder.sample(1380, 6_000), press(Button::Mode, 1_000));
+        assert_eq!(ladder.pressed(), Some(Button::Mode));
+        assert_eq!(ladder.sample(4000, 10_000), None);
+        assert_eq!(ladder.sample(4000, 15_000), release(Button::Mode, 10_000));
+        assert_eq!(ladder.pressed(), None);
+    }
+
+    #[test]
+    fn bounce_shorter_than_debounce_is_ignored() {
+        let mut ladder = ladder();
+        for t in (0..20_000).step_by(1_000) {
+            let reading = if t % 2_000 == 0 { 0 } else { 4095 };
+            assert_eq!(ladder.sample(reading, t), None);
+        }
+    }
+
+    #[test]
+    fn hysteresis_holds_at_a_boundary() {
+        let mut ladder = ladder();
+        ladder.sample(1365, 0);
+        assert_eq!(ladder.sample(1365, DEBOUNCE_US), press(Button::Mode, 0));
+        /* Just over the Mode/Menu boundary but within the hysteresis */
+        for t in 0..10 {
+            assert_eq!(ladder.sample(2047 + 90, 10_000 + t * 1_000), None);
+        }
+        assert_eq!(ladder.pressed(), Some(Button::Mode));
+        /* Past it */
+        ladder.sample(2300, 30_000);
+        assert_eq!(ladder.sample(2300, 35_000), release(Button::Mode, 30_000));
+    }
+
+    #[test]
+    fn sliding_to_another_button_releases_first() {
+        let mut ladder = ladder();
+        ladder.sample(0, 0);
+        assert_eq!(ladder.sample(0, DEBOUNCE_US), press(Button::Key, 0));
+        ladder.sample(2730, 10_000);
+        assert_eq!(ladder.sample(2730, 15_000), release(Button::Key, 10_000));
+        assert_eq!(ladder.sample(2730, 16_000), press(Button::Menu, 10_000));
+    }
+
+    #[test]
+    fn timestamps_wrap() {
+        let mut ladder = ladder();
+        let start = u32::MAX - 1_000;
+        ladder.sample(0, start);
+        assert_eq!(ladder.sample(0, start.wrapping_add(DEBOUNCE_US)), press(Button::Key, start));
+    }
+}
@@ -0,0 +1,27 @@
+#![cfg_attr(not(test), no_std)]
+
+// Hardware-independent Morse logic: code tables, encoder, decoder, timing,
+// keyer, sidetone envelope, button gestures, the resistor ladder button
+// decoder, the PIO assembler and the PIO keyer programs. No registers in
+// here, everything is plain integer maths so it can be tested on the host.
+
+pub mod morse;
+
+#[cfg(any(feature = "cyrillic", feature = "greek", feature = "wabun"))]
+pub mod charsets;
+
+pub mod timing;
+
+pub mod decoder;
+
+pub mod keyer;
+
+pub mod envelope;
+
+pub mod gesture;
+
+pub mod ladder;
+
+pub mod pio;
+
+pub mod pio_keyer;
@@ -0,0 +1,414 @@
+/* Morse code table and text encoder */
+/* Text is turned into a timeline of key-down / key-up elements.
+   Every element length is measured in dot units so the caller picks the speed:
+   1. Dit = 1 unit key down, Dah = 3 units key down
+   2. Gap between elements of one character = 1 unit key up
+   3. Gap between characters = 3 units, gap between words = 7 units */
+
+#[cfg(any(feature = "cyrillic", feature = "greek", feature = "wabun"))]
+use crate::charsets;
+
+/* One step of the keying timeline */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Element {
+    Dit,        /* Short mark */
+    Dah,        /* Long mark */
+    IntraGap,   /* Space between marks inside a character */
+    CharGap,    /* Space between characters */
+    WordGap,    /* Space between words */
+}
+
+impl Element {
+    /* Length of the element in dot units */
+    pub const fn units(self) -> u32 {
+        match self {
+            Element::Dit => 1,
+            Element::Dah => 3,
+            Element::IntraGap => 1,
+            Element::CharGap => 3,
+            Element::WordGap => 7,
+        }
+    }
+
+    /* True while the key (speaker and LED) should be on */
+    pub const fn is_key_down(self) -> bool {
+        matches!(self, Element::Dit | Element::Dah)
+    }
+}
+
+/* Character set used to turn received patterns back into text.
+   Encoding doesn't need it: every compiled-in table is searched. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    Latin,
+    #[cfg(feature = "cyrillic")]
+    Cyrillic,
+    #[cfg(feature = "greek")]
+    Greek,
+    #[cfg(feature = "wabun")]
+    Wabun,
+}
+
+/* A decoded pattern */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoded {
+    Char(char),
+    Prosign(&'static str),  /* Name without the angle brackets, e.g. "SK" */
+}
+
+/* ITU-R M.1677-1 letters plus accented Latin letters */
+/* Patterns are written with '.' for a dit and '-' for a dah */
+const LATIN: &[(char, &str)] = &[
+    ('A', ".-"),    ('B', "-..."),  ('C', "-.-."),  ('D', "-.."),
+    ('E', "."),     ('F', "..-."),  ('G', "--."),   ('H', "...."),
+    ('I', ".."),    ('J', ".---"),  ('K', "-.-"),   ('L', ".-.."),
+    ('M', "--"),    ('N', "-."),    ('O', "---"),   ('P', ".--."),
+    ('Q', "--.-"),  ('R', ".-."),   ('S', "..."),   ('T', "-"),
+    ('U', "..-"),   ('V', "...-"),  ('W', ".--"),   ('X', "-..-"),
+    ('Y', "-.--"),  ('Z', "--.."),
+    /* Accented */
+    ('É', "..-.."), ('Ä', ".-.-"),  ('Á', ".--.-"), ('À', ".--.-"),
+    ('Å', ".--.-"), ('Ç', "-.-.."), ('È', ".-..-"), ('Ñ', "--.--"),
+    ('Ö', "---."),  ('Ü', "..--"),  ('Ð', "..--."),
+];
+
+/* Figures and punctuation, shared by every character set */
+const COMMON: &[(char, &str)] = &[
+    /* Figures */
+    ('0', "-----"), ('1', ".----"), ('2', "..---"), ('3', "...--"),
+    ('4', "....-"), ('5', "....."), ('6', "-...."), ('7', "--..."),
+    ('8', "---.."), ('9', "----."),
+    /* ITU punctuation */
+    ('.', ".-.-.-"),  (',', "--..--"),  (':', "---..."),  ('?', "..--.."),
+    ('\'', ".----."), ('-', "-....-"),  ('/', "-..-."),   ('(', "-.--."),
+    (')', "-.--.-"),  ('"', ".-..-."),  ('=', "-...-"),   ('+', ".-.-."),
+    ('@', ".--.-."),
+    /* Common non-ITU punctuation */
+    ('!', "-.-.--"),  ('&', ".-..."),   (';', "-.-.-."),  ('_', "..--.-"),
+    ('$', "...-..-"),
+];
+
+/* Prosigns: letters run together without inter-character gaps, written <SK> in text.
+   The CH digraph is sent the same way, as one character. */
+const PROSIGNS: &[(&str, &str)] = &[
+    ("AR", ".-.-."),    ("AS", ".-..."),     ("BK", "-...-.-"), ("BT", "-...-"),
+    ("CL", "-.-..-.."), ("CT", "-.-.-"),     ("KN", "-.--."),   ("SK", "...-.-"),
+    ("SN", "...-."),    ("SOS", "...---..."), ("HH", "........"), ("CH", "----"),
+];
+
+impl Charset {
+    /* Every compiled-in character set */
+    pub const ALL: &'static [Charset] = &[
+        Charset::Latin,
+        #[cfg(feature = "cyrillic")]
+        Charset::Cyrillic,
+        #[cfg(feature = "greek")]
+        Charset::Greek,
+        #[cfg(feature = "wabun")]
+        Charset::Wabun,
+    ];
+
+    /* The one after this in ALL, wrapping round (for a mode button) */
+    pub fn next(self) -> Charset {
+        let index = Charset::ALL.iter().position(|&charset| charset == self).unwrap_or(0);
+        Charset::ALL[(index + 1) % Charset::ALL.len()]
+    }
+
+    /* Three-letter name for a display */
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Charset::Latin => "LAT",
+            #[cfg(feature = "cyrillic")]
+            Charset::Cyrillic => "CYR",
+            #[cfg(feature = "greek")]
+            Charset::Greek => "GRK",
+            #[cfg(feature = "wabun")]
+            Charset::Wabun => "JPN",
+        }
+    }
+
+    /* Letters of this character set */
+    fn letters(self) -> &'static [(char, &'static str)] {
+        match self {
+            Charset::Latin => LATIN,
+            #[cfg(feature = "cyrillic")]
+            Charset::Cyrillic => charsets::CYRILLIC,
+            #[cfg(feature = "greek")]
+            Charset::Greek => charsets::GREEK,
+            #[cfg(feature = "wabun")]
+            Charset::Wabun => charsets::WABUN,
+        }
+    }
+}
+
+/* Every table that can be used for encoding */
+const ENCODE_TABLES: &[&[(char, &str)]] = &[
+    LATIN,
+    COMMON,
+    #[cfg(feature = "cyrillic")]
+    charsets::CYRILLIC,
+    #[cfg(feature = "greek")]
+    charsets::GREEK,
+    #[cfg(feature = "wabun")]
+    charsets::WABUN,
+];
+
+fn find(table: &[(char, &'static str)], c: char) -> Option<&'static str> {
+    table.iter().find(|(ch, _)| *ch == c).map(|(_, pattern)| *pattern)
+}
+
+/* Look up the dit/dah pattern for a character (letters are case-insensitive) */
+pub fn lookup(c: char) -> Option<&'static str> {
+    let c = if c.is_ascii() {
+        c.to_ascii_uppercase()
+    } else {
+        c.to_uppercase().next().unwrap_or(c)
+    };
+    ENCODE_TABLES.iter().find_map(|table| find(table, c))
+}
+
+/* Pattern for a prosign name such as "SK" (case-insensitive) */
+pub fn prosign(name: &str) -> Option<&'static str> {
+    PROSIGNS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, pattern)| *pattern)
+}
+
+/* Reverse lookup used by the receiver: pattern of '.' and '-' to a character.
+   Letters of the chosen set win, then figures and punctuation, then prosigns
+   (so <AR> comes back as '+' and <BT> as '='). */
+pub fn decode(pattern: &str, charset: Charset) -> Option<Decoded> {
+    let by_pattern = |table: &[(char, &'static str)]| {
+        table.iter().find(|(_, p)| *p == pattern).map(|(ch, _)| Decoded::Char(*ch))
+    };
+    by_pattern(charset.letters())
+        .or_else(|| by_pattern(COMMON))
+        .or_else(|| {
+            PROSIGNS
+                .iter()
+                .find(|(_, p)| *p == pattern)
+                .map(|(name, _)| Decoded::Prosign(name))
+        })
+}
+
+/* Iterator over the keying timeline of a string */
+/* Characters missing from the tables are skipped, runs of spaces become one word gap
+   and no gap is emitted after the last mark.
+   Prosigns are written in angle brackets: a known name (<SK>, <SOS>, <CH>) is sent
+   from the prosign table, anything else (<VA>) has its letters run together.
+   The CH digraph must be written <CH> too, plain "CH" is sent as C then H. */
+pub struct Encoder<'a> {
+    chars: core::str::Chars<'a>,   /* Remaining input text */
+    pattern: &'static [u8],         /* Remaining marks of the current character */
+    gap: Option<Element>,           /* Gap to emit before the next mark */
+    joined: bool,                   /* Inside <...>: no gaps between characters */
+    joined_started: bool,           /* A letter inside <...> has been sent */
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Encoder {
+            chars: text.chars(),
+            pattern: &[],
+            gap: None,
+            joined: false,
+            joined_started: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Encoder<'a> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        loop {
+            /* Still sending a character: emit the pending gap first, then the mark */
+            if let Some((&mark, rest)) = self.pattern.split_first() {
+                if let Some(gap) = self.gap.take() {
+                    return Some(gap);
+                }
+                self.pattern = rest;
+                self.gap = Some(if rest.is_empty() { Element::CharGap } else { Element::IntraGap });
+                return Some(if mark == b'-' { Element::Dah } else { Element::Dit });
+            }
+
+            /* Fetch the next character, None here ends the timeline */
+            let c = self.chars.next()?;
+            match c {
+                ' ' => {
+                    /* Only widen the gap if something has already been sent */
+                    if self.gap.is_some() {
+                        self.gap = Some(Element::WordGap);
+                    }
+                    continue;
+                }
+                '<' => {
+                    /* Known prosign: send its pattern and skip past the '>' */
+                    let rest = self.chars.as_str();
+                    if let Some(end) = rest.find('>') {
+                        match prosign(&rest[..end]) {
+                            Some(pattern) => {
+                                self.pattern = pattern.as_bytes();
+                                self.chars = rest[end + 1..].chars();
+                            }
+                            None => {
+                                self.joined = true;
+                                self.joined_started = false;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                '>' => {
+                    self.joined = false;
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(pattern) = lookup(c) {
+                /* Letters of a prosign follow each other with only an element gap,
+                   the first one keeps the gap to whatever came before the '<' */
+                if self.joined {
+                    if self.joined_started && self.gap == Some(Element::CharGap) {
+                        self.gap = Some(Element::IntraGap);
+                    }
+                    self.joined_started = true;
+                }
+                self.pattern = pattern.as_bytes();
+            }
+        }
+    }
+}
+
+/* Convenience wrapper: encode(text) reads better at the call site */
+pub fn encode(text: &str) -> Encoder<'_> {
+    Encoder::new(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Timeline as text: '.' and '-' for marks, ' ' between characters, " / " between words */
+    fn timeline(text: &str) -> String {
+        encode(text)
+            .map(|e| match e {
+                Element::Dit => ".",
+                Element::Dah => "-",
+                Element::IntraGap => "",
+                Element::CharGap => " ",
+                Element::WordGap => " / ",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lookup_ignores_case() {
+        assert_eq!(lookup('a'), Some(".-"));
+        assert_eq!(lookup('A'), Some(".-"));
+        assert_eq!(lookup('é'), Some("..-.."));
+        assert_eq!(lookup('#'), None);
+    }
+
+    #[test]
+    fn encodes_characters_and_words() {
+        assert_eq!(timeline("SOS"), "... --- ...");
+        assert_eq!(timeline("E  T"), ". / -");
+        assert_eq!(timeline("  E "), ".");
+        assert_eq!(timeline("E#T"), ". -");
+        assert_eq!(timeline(""), "");
+    }
+
+    #[test]
+    fn gaps_only_between_marks() {
+        let elements: Vec<Element> = encode("HI THERE").collect();
+        assert!(elements.first().unwrap().is_key_down());
+        assert!(elements.last().unwrap().is_key_down());
+        for pair in elements.windows(2) {
+            assert!(pair[0].is_key_down() != pair[1].is_key_down());
+        }
+    }
+
+    #[test]
+    fn encodes_prosigns() {
+        assert_eq!(timeline("<SK>"), "...-.-");
+        assert_eq!(timeline("<sos>"), "...---...");
+        assert_eq!(timeline("E<AR>E"), ". .-.-. .");
+        /* Unknown name: letters are run together */
+        assert_eq!(timeline("<VA> K"), "...-.- / -.-");
+        assert_eq!(timeline("E<VA>"), ". ...-.-");
+        assert_eq!(timeline("CQ <VA>"), "-.-. --.- / ...-.-");
+        assert_eq!(timeline("CQ<VA>E"), "-.-. --.- ...-.- .");
+        /* No closing bracket: the '<' is dropped */
+        assert_eq!(timeline("<SK"), "... -.-");
+        /* CH is only one character when bracketed */
+        assert_eq!(timeline("E<CH>"), ". ----");
+        assert_eq!(timeline("ECH"), ". -.-. ....");
+    }
+
+    #[test]
+    fn decodes_with_character_table_first() {
+        assert_eq!(decode(".-", Charset::Latin), Some(Decoded::Char('A')));
+        assert_eq!(decode(".-.-.", Charset::Latin), Some(Decoded::Char('+')));
+        assert_eq!(decode("-...-", Charset::Latin), Some(Decoded::Char('=')));
+        assert_eq!(decode("...-.-", Charset::Latin), Some(Decoded::Prosign("SK")));
+        assert_eq!(decode("----", Charset::Latin), Some(Decoded::Prosign("CH")));
+        assert_eq!(decode(".-.-.-.-", Charset::Latin), None);
+        assert_eq!(decode("", Charset::Latin), None);
+    }
+
+    #[test]
+    fn prosign_names_ignore_case() {
+        assert_eq!(prosign("kn"), Some("-.--."));
+        assert_eq!(prosign("XX"), None);
+    }
+
+    #[test]
+    fn charset_next_cycles_through_all() {
+        let mut charset = Charset::Latin;
+        for _ in 0..Charset::ALL.len() {
+            charset = charset.next();
+        }
+        assert_eq!(charset, Charset::Latin);
+    }
+
+    /* Every letter of a table encodes to its pattern and decodes back to itself */
+    #[cfg(any(feature = "cyrillic", feature = "greek", feature = "wabun"))]
+    fn assert_round_trip(table: &[(char, &'static str)], charset: Charset) {
+        for &(c, pattern) in table {
+            assert_eq!(lookup(c), Some(pattern), "{c}");
+            assert_eq!(decode(pattern, charset), Some(Decoded::Char(c)), "{c}");
+        }
+    }
+
+    #[cfg(feature = "cyrillic")]
+    #[test]
+    fn cyrillic_round_trip() {
+        assert_round_trip(charsets::CYRILLIC, Charset::Cyrillic);
+        assert_eq!(lookup('я'), Some(".-.-"));
+        /* Latin A and Cyrillic А share a pattern, the charset picks the letter */
+        assert_eq!(decode(".-", Charset::Latin), Some(Decoded::Char('A')));
+        assert_eq!(decode(".-", Charset::Cyrillic), Some(Decoded::Char('А')));
+    }
+
+    #[cfg(feature = "greek")]
+    #[test]
+    fn greek_round_trip() {
+        assert_round_trip(charsets::GREEK, Charset::Greek);
+        assert_eq!(lookup('ω'), Some(".--"));
+        assert_eq!(timeline("ΣΟΣ"), "... --- ...");
+    }
+
+    #[cfg(feature = "wabun")]
+    #[test]
+    fn wabun_round_trip() {
+        assert_round_trip(charsets::WABUN, Charset::Wabun);
+        /* ン shares its pattern with '+' and <AR>: the Wabun letter wins */
+        assert_eq!(decode(".-.-.", Charset::Wabun), Some(Decoded::Char('ン')));
+        assert_eq!(decode(".-.-.", Charset::Latin), Some(Decoded::Char('+')));
+        assert_eq!(timeline("ン<AR>"), ".-.-. .-.-.");
+        /* Figures still come from the common table */
+        assert_eq!(decode("-----", Charset::Wabun), Some(Decoded::Char('0')));
+    }
+}
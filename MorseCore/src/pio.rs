@@ -0,0 +1,949 @@
+/* PIO assembler */
+/* Turns RP2040 PIO assembly (the pioasm syntax) into instruction words at
+   compile time: `const PROGRAM: Program = assemble("...");`, a mistake in the
+   source is a compile error. Two passes over the text, the first collects
+   labels and defines, the second encodes.
+   1. Instructions: jmp, wait, in, out, push, pull, mov, irq, set, nop, each with
+      an optional `side <n>` and `[delay]`. Values are numbers (decimal, 0x, 0b),
+      defines and labels, delays can add and subtract them: `[T1 - 1]`.
+   2. Directives: .program, .side_set <n> [opt] [pindirs], .wrap_target, .wrap,
+      .origin <n>, .define [public] <name> <value>. `public` labels are accepted.
+   3. Comments start with ';' or "//".
+   Jump targets are relative to the start of the program, the loader adds the
+   address it was loaded at. Wrap and side-set settings come along in Program
+   for the state machine configuration. */
+
+/* Instruction memory of one PIO block */
+pub const MAX_INSTRUCTIONS: usize = 32;
+
+const MAX_LABELS: usize = 32;
+const MAX_DEFINES: usize = 16;
+const MAX_TOKENS: usize = 12;
+
+/* Side-set settings from .side_set */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SideSet {
+    pub bits: u8,           /* Bits taken from the delay field, including the opt bit */
+    pub optional: bool,     /* opt: instructions without `side` leave the pins alone */
+    pub pindirs: bool,      /* Side-set drives pin directions instead of levels */
+}
+
+impl SideSet {
+    pub const NONE: SideSet = SideSet {
+        bits: 0,
+        optional: false,
+        pindirs: false,
+    };
+
+    /* Pins driven by side-set (the opt bit isn't a pin) */
+    pub const fn pins(&self) -> u8 {
+        self.bits - self.optional as u8
+    }
+}
+
+/* An assembled program */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Program {
+    code: [u16; MAX_INSTRUCTIONS],
+    len: usize,
+    pub origin: Option<u8>,     /* Fixed load address from .origin */
+    pub wrap_target: u8,        /* Where execution wraps to, program-relative */
+    pub wrap: u8,               /* Last instruction before wrapping */
+    pub side_set: SideSet,
+}
+
+impl Program {
+    pub fn instructions(&self) -> &[u16] {
+        &self.code[..self.len]
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/* Instruction fields */
+const OP_JMP: u16 = 0x0000;
+const OP_WAIT: u16 = 0x2000;
+const OP_IN: u16 = 0x4000;
+const OP_OUT: u16 = 0x6000;
+const OP_PUSH: u16 = 0x8000;
+const OP_PULL: u16 = 0x8080;
+const OP_MOV: u16 = 0xa000;
+const OP_IRQ: u16 = 0xc000;
+const OP_SET: u16 = 0xe000;
+
+/* mov y, y */
+pub const NOP: u16 = 0xa042;
+
+/* Opcode of a JMP, the loader relocates their address field */
+pub const fn is_jmp(instruction: u16) -> bool {
+    instruction & 0xe000 == OP_JMP
+}
+
+/* Assemble at compile time, panics (a compile error in a const) on bad source */
+pub const fn assemble(source: &str) -> Program {
+    let mut assembler = Assembler::new(source.as_bytes());
+    assembler.pass(1);
+    assembler.pass(2);
+    assembler.program
+}
+
+/* Part of the source text */
+#[derive(Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+const EMPTY: Span = Span { start: 0, end: 0 };
+
+/* A line split into tokens at whitespace and commas, the [delay] cut out */
+struct Line {
+    tokens: [Span; MAX_TOKENS],
+    count: usize,
+    delay: Option<Span>,
+}
+
+struct Assembler<'a> {
+    src: &'a [u8],
+    program: Program,
+    pass: u8,
+    labels: [(Span, u8); MAX_LABELS],
+    label_count: usize,
+    defines: [(Span, i32); MAX_DEFINES],
+    define_count: usize,
+    wrap_set: bool,
+}
+
+impl<'a> Assembler<'a> {
+    const fn new(src: &'a [u8]) -> Self {
+        Assembler {
+            src,
+            program: Program {
+                code: [0; MAX_INSTRUCTIONS],
+                len: 0,
+                origin: None,
+                wrap_target: 0,
+                wrap: 0,
+                side_set: SideSet::NONE,
+            },
+            pass: 1,
+            labels: [(EMPTY, 0); MAX_LABELS],
+            label_count: 0,
+            defines: [(EMPTY, 0); MAX_DEFINES],
+            define_count: 0,
+            wrap_set: false,
+        }
+    }
+
+    const fn pass(&mut self, pass: u8) {
+        self.pass = pass;
+        self.program.len = 0;
+        let mut pos = 0;
+        while pos < self.src.len() {
+            let mut end = pos;
+            while end < self.src.len() && self.src[end] != b'\n' {
+                end += 1;
+            }
+            let line = self.split(pos, end);
+            self.line(&line);
+            pos = end + 1;
+        }
+        if self.program.len == 0 {
+            panic!("pio: empty program");
+        }
+        if !self.wrap_set {
+            self.program.wrap = (self.program.len - 1) as u8;
+        }
+    }
+
+    /* Tokens of one line, comments dropped */
+    const fn split(&self, start: usize, end: usize) -> Line {
+        let src = self.src;
+        let mut end = end;
+        let mut i = start;
+        while i < end {
+            if src[i] == b';' || (src[i] == b'/' && i + 1 < end && src[i + 1] == b'/') {
+                end = i;
+            }
+            i += 1;
+        }
+
+        let mut line = Line {
+            tokens: [EMPTY; MAX_TOKENS],
+            count: 0,
+            delay: None,
+        };
+        let mut i = start;
+        let mut token_start = None;
+        while i <= end {
+            let c = if i < end { src[i] } else { b' ' };
+            if c == b'[' {
+                let mut close = i;
+                while close < end && src[close] != b']' {
+                    close += 1;
+                }
+                if close == end {
+                    panic!("pio: missing ]");
+                }
+                line.delay = Some(Span { start: i + 1, end: close });
+            }
+            let separator = c == b' ' || c == b'\t' || c == b'\r' || c == b',' || c == b'[';
+            match token_start {
+                Some(token) if separator => {
+                    if line.count == MAX_TOKENS {
+                        panic!("pio: line too long");
+                    }
+                    line.tokens[line.count] = Span { start: token, end: i };
+                    line.count += 1;
+                    token_start = None;
+                }
+                None if !separator => token_start = Some(i),
+                _ => {}
+            }
+            if c == b'[' {
+                /* Carry on after the ] */
+                while src[i] != b']' {
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+        line
+    }
+
+    const fn line(&mut self, line: &Line) {
+        let src = self.src;
+        if line.count == 0 {
+            if line.delay.is_some() {
+                panic!("pio: delay without an instruction");
+            }
+            return;
+        }
+        let mut first = 0;
+        let token = line.tokens[0];
+        if src[token.start] == b'.' {
+            self.directive(line);
+            return;
+        }
+        if is(src, token, "public") {
+            first = 1;
+        }
+        /* Label, possibly followed by an instruction */
+        if first < line.count {
+            let label = line.tokens[first];
+            if src[label.end - 1] == b':' {
+                self.label(Span { start: label.start, end: label.end - 1 });
+                first += 1;
+            }
+        }
+        if first == line.count {
+            if line.delay.is_some() {
+                panic!("pio: delay without an instruction");
+            }
+            return;
+        }
+        if self.program.len == MAX_INSTRUCTIONS {
+            panic!("pio: more than 32 instructions");
+        }
+        if self.pass == 2 {
+            self.program.code[self.program.len] = self.instruction(line, first);
+        }
+        self.program.len += 1;
+    }
+
+    const fn label(&mut self, name: Span) {
+        if self.pass != 1 {
+            return;
+        }
+        if self.symbol(name).is_some() {
+            panic!("pio: label defined twice");
+        }
+        if self.label_count == MAX_LABELS {
+            panic!("pio: too many labels");
+        }
+        self.labels[self.label_count] = (name, self.program.len as u8);
+        self.label_count += 1;
+    }
+
+    const fn directive(&mut self, line: &Line) {
+        let src = self.src;
+        let name = line.tokens[0];
+        if is(src, name, ".program") || is(src, name, ".lang_opt") {
+            return;
+        }
+        if is(src, name, ".wrap_target") {
+            self.program.wrap_target = self.program.len as u8;
+        } else if is(src, name, ".wrap") {
+            if self.program.len == 0 {
+                panic!("pio: .wrap before any instruction");
+            }
+            self.program.wrap = (self.program.len - 1) as u8;
+            self.wrap_set = true;
+        } else if is(src, name, ".origin") {
+            let origin = self.number_arg(line, 1, 31);
+            self.program.origin = Some(origin as u8);
+        } else if is(src, name, ".side_set") {
+            if self.program.len != 0 {
+                panic!("pio: .side_set after the first instruction");
+            }
+            let count = self.number_arg(line, 1, 5) as u8;
+            let mut side_set = SideSet {
+                bits: count,
+                optional: false,
+                pindirs: false,
+            };
+            let mut i = 2;
+            while i < line.count {
+                if is(src, line.tokens[i], "opt") {
+                    side_set.optional = true;
+                    side_set.bits += 1;
+                } else if is(src, line.tokens[i], "pindirs") {
+                    side_set.pindirs = true;
+                } else {
+                    panic!("pio: bad .side_set option");
+                }
+                i += 1;
+            }
+            if side_set.bits > 5 {
+                panic!("pio: .side_set opt takes at most 4 pins");
+            }
+            self.program.side_set = side_set;
+        } else if is(src, name, ".define") {
+            let mut i = 1;
+            if i < line.count && is(src, line.tokens[i], "public") {
+                i += 1;
+            }
+            if i + 1 >= line.count {
+                panic!("pio: .define needs a name and a value");
+            }
+            let name = line.tokens[i];
+            let value = Span { start: line.tokens[i + 1].start, end: line.tokens[line.count - 1].end };
+            self.define(name, value);
+        } else {
+            panic!("pio: unknown directive");
+        }
+    }
+
+    const fn define(&mut self, name: Span, value: Span) {
+        if self.pass != 1 {
+            return;
+        }
+        if self.symbol(name).is_some() {
+            panic!("pio: symbol defined twice");
+        }
+        if self.define_count == MAX_DEFINES {
+            panic!("pio: too many defines");
+        }
+        let value = self.expression(value);
+        self.defines[self.define_count] = (name, value);
+        self.define_count += 1;
+    }
+
+    /* Value of a define or label */
+    const fn symbol(&self, name: Span) -> Option<i32> {
+        let mut i = 0;
+        while i < self.define_count {
+            if same(self.src, self.defines[i].0, name) {
+                return Some(self.defines[i].1);
+            }
+            i += 1;
+        }
+        let mut i = 0;
+        while i < self.label_count {
+            if same(self.src, self.labels[i].0, name) {
+                return Some(self.labels[i].1 as i32);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /* Terms added and subtracted: 3, T1 - 1, 0x10 + OFFSET */
+    const fn expression(&self, span: Span) -> i32 {
+        let src = self.src;
+        let mut total = 0;
+        let mut sign = 1;
+        let mut expect_term = true;
+        let mut i = span.start;
+        while i < span.end {
+            let c = src[i];
+            if c == b' ' || c == b'\t' {
+                i += 1;
+            } else if c == b'+' || c == b'-' {
+                if !expect_term {
+                    expect_term = true;
+                    sign = 1;
+                }
+                if c == b'-' {
+                    sign = -sign;
+                }
+                i += 1;
+            } else {
+                if !expect_term {
+                    panic!("pio: operator missing in expression");
+                }
+                let mut end = i;
+                while end < span.end && is_word(src[end]) {
+                    end += 1;
+                }
+                if end == i {
+                    panic!("pio: bad character in expression");
+                }
+                total += sign * self.term(Span { start: i, end });
+                expect_term = false;
+                i = end;
+            }
+        }
+        if expect_term {
+            panic!("pio: value missing");
+        }
+        total
+    }
+
+    const fn term(&self, span: Span) -> i32 {
+        let src = self.src;
+        if src[span.start].is_ascii_digit() {
+            return number(src, span);
+        }
+        match self.symbol(span) {
+            Some(value) => value,
+            /* Labels further down aren't known in pass 1 */
+            None if self.pass == 1 => 0,
+            None => panic!("pio: unknown symbol"),
+        }
+    }
+
+    /* Expression in range 0..=max */
+    const fn value(&self, span: Span, max: i32) -> u16 {
+        let value = self.expression(span);
+        if value < 0 || value > max {
+            panic!("pio: value out of range");
+        }
+        value as u16
+    }
+
+    const fn number_arg(&self, line: &Line, index: usize, max: i32) -> u16 {
+        if index >= line.count {
+            panic!("pio: argument missing");
+        }
+        self.value(line.tokens[index], max)
+    }
+
+    /* Encode one instruction, tokens from `first` on */
+    const fn instruction(&self, line: &Line, first: usize) -> u16 {
+        let src = self.src;
+        /* Operands run up to `side` */
+        let mut end = first + 1;
+        while end < line.count && !is(src, line.tokens[end], "side") {
+            end += 1;
+        }
+        let side = if end < line.count {
+            if end + 1 == line.count {
+                panic!("pio: side needs a value");
+            }
+            Some(Span { start: line.tokens[end + 1].start, end: line.tokens[line.count - 1].end })
+        } else {
+            None
+        };
+        let ops = Operands { tokens: &line.tokens, first: first + 1, end };
+
+        let op = line.tokens[first];
+        let instruction = if is(src, op, "jmp") {
+            self.jmp(&ops)
+        } else if is(src, op, "wait") {
+            self.wait(&ops)
+        } else if is(src, op, "in") {
+            self.shift(&ops, OP_IN, &IN_SOURCES)
+        } else if is(src, op, "out") {
+            self.shift(&ops, OP_OUT, &OUT_DESTINATIONS)
+        } else if is(src, op, "push") {
+            self.push_pull(&ops, OP_PUSH, "iffull")
+        } else if is(src, op, "pull") {
+            self.push_pull(&ops, OP_PULL, "ifempty")
+        } else if is(src, op, "mov") {
+            self.mov(&ops)
+        } else if is(src, op, "irq") {
+            self.irq(&ops)
+        } else if is(src, op, "set") {
+            self.set(&ops)
+        } else if is(src, op, "nop") {
+            ops.count(0);
+            NOP
+        } else {
+            panic!("pio: unknown instruction");
+        };
+        instruction | self.delay_side_set(line.delay, side)
+    }
+
+    /* Bits 12:8: side-set value (with the opt enable bit on top) and delay */
+    const fn delay_side_set(&self, delay: Option<Span>, side: Option<Span>) -> u16 {
+        let side_set = self.program.side_set;
+        let delay_bits = 5 - side_set.bits;
+        let delay = match delay {
+            Some(span) => self.value(span, (1 << delay_bits) - 1),
+            None => 0,
+        };
+        let side = match side {
+            Some(span) => {
+                if side_set.bits == 0 {
+                    panic!("pio: side without .side_set");
+                }
+                let pins = side_set.pins();
+                let value = self.value(span, (1 << pins) - 1);
+                if side_set.optional {
+                    value | 1 << pins
+                } else {
+                    value
+                }
+            }
+            None if side_set.bits != 0 && !side_set.optional => {
+                panic!("pio: side missing (.side_set without opt)")
+            }
+            None => 0,
+        };
+        (side << delay_bits | delay) << 8
+    }
+
+    /* jmp [condition] target */
+    const fn jmp(&self, ops: &Operands) -> u16 {
+        let src = self.src;
+        let first = ops.get(0);
+        let mut condition = 0;
+        let mut i = 0;
+        while i < JMP_CONDITIONS.len() {
+            if is(src, first, JMP_CONDITIONS[i]) {
+                condition = i as u16 + 1;
+            }
+            i += 1;
+        }
+        /* The target is everything after the condition */
+        let target = if condition == 0 { ops.rest(0) } else { ops.rest(1) };
+        OP_JMP | condition << 5 | self.value(target, 31)
+    }
+
+    /* wait <polarity> gpio|pin|irq <index> [rel] */
+    const fn wait(&self, ops: &Operands) -> u16 {
+        let src = self.src;
+        let rel = ops.len() == 4 && is(src, ops.get(3), "rel");
+        if !rel {
+            ops.count(3);
+        }
+        let polarity = self.value(ops.get(0), 1);
+        let source = ops.get(1);
+        let (source, index) = if is(src, source, "gpio") {
+            (0, self.value(ops.get(2), 31))
+        } else if is(src, source, "pin") {
+            (1, self.value(ops.get(2), 31))
+        } else if is(src, source, "irq") {
+            (2, self.value(ops.get(2), 7))
+        } else {
+            panic!("pio: wait source is gpio, pin or irq")
+        };
+        if rel && source != 2 {
+            panic!("pio: rel only goes with irq");
+        }
+        OP_WAIT | polarity << 7 | source << 5 | (rel as u16) << 4 | index
+    }
+
+    /* in <source>, <bits> and out <destination>, <bits>, 32 bits encode as 0 */
+    const fn shift(&self, ops: &Operands, opcode: u16, names: &[&str; 8]) -> u16 {
+        let target = self.name(ops.get(0), names);
+        let bits = self.value(ops.rest(1), 32);
+        if bits == 0 {
+            panic!("pio: bit count is 1 to 32");
+        }
+        opcode | target << 5 | (bits & 31)
+    }
+
+    /* push [iffull] [block|noblock], pull [ifempty] [block|noblock] */
+    const fn push_pull(&self, ops: &Operands, opcode: u16, if_option: &str) -> u16 {
+        let src = self.src;
+        let mut if_bit = 0;
+        let mut block = 1;
+        let mut i = 0;
+        while i < ops.len() {
+            let option = ops.get(i);
+            if is(src, option, if_option) {
+                if_bit = 1;
+            } else if is(src, option, "block") {
+                block = 1;
+            } else if is(src, option, "noblock") {
+                block = 0;
+            } else {
+                panic!("pio: unknown push/pull option");
+            }
+            i += 1;
+        }
+        opcode | if_bit << 6 | block << 5
+    }
+
+    /* mov <destination>, [!|~|::]<source> */
+    const fn mov(&self, ops: &Operands) -> u16 {
+        let src = self.src;
+        let destination = self.name(ops.get(0), &MOV_DESTINATIONS);
+        let (operation, source) = match ops.len() {
+            2 => {
+                let source = ops.get(1);
+                if src[source.start] == b'!' || src[source.start] == b'~' {
+                    (1, Span { start: source.start + 1, end: source.end })
+                } else if src[source.start] == b':'
+                    && source.end - source.start > 2
+                    && src[source.start + 1] == b':'
+                {
+                    (2, Span { start: source.start + 2, end: source.end })
+                } else {
+                    (0, source)
+                }
+            }
+            3 => {
+                let operation = ops.get(1);
+                let operation = if is(src, operation, "!") || is(src, operation, "~") {
+                    1
+                } else if is(src, operation, "::") {
+                    2
+                } else {
+                    panic!("pio: mov operation is !, ~ or ::")
+                };
+                (operation, ops.get(2))
+            }
+            _ => panic!("pio: mov takes a destination and a source"),
+        };
+        let source = self.name(source, &MOV_SOURCES);
+        OP_MOV | destination << 5 | operation << 3 | source
+    }
+
+    /* irq [set|nowait|wait|clear] <index> [rel] */
+    const fn irq(&self, ops: &Operands) -> u16 {
+        let src = self.src;
+        let mut n = ops.len();
+        let rel = n > 0 && is(src, ops.get(n - 1), "rel");
+        if rel {
+            n -= 1;
+        }
+        let (mode, index) = match n {
+            1 => (0, ops.get(0)),
+            2 => {
+                let mode = ops.get(0);
+                let mode = if is(src, mode, "set") || is(src, mode, "nowait") {
+                    0
+                } else if is(src, mode, "wait") {
+                    1
+                } else if is(src, mode, "clear") {
+                    2
+                } else {
+                    panic!("pio: irq mode is set, nowait, wait or clear")
+                };
+                (mode, ops.get(1))
+            }
+            _ => panic!("pio: irq takes a mode and an index"),
+        };
+        OP_IRQ | mode << 5 | (rel as u16) << 4 | self.value(index, 7)
+    }
+
+    /* set <destination>, <value> */
+    const fn set(&self, ops: &Operands) -> u16 {
+        let destination = self.name(ops.get(0), &SET_DESTINATIONS);
+        OP_SET | destination << 5 | self.value(ops.rest(1), 31)
+    }
+
+    /* Index of a name in a table of operand names, "" marks reserved encodings */
+    const fn name(&self, span: Span, names: &[&str; 8]) -> u16 {
+        let mut i = 0;
+        while i < names.len() {
+            if !names[i].is_empty() && is(self.src, span, names[i]) {
+                return i as u16;
+            }
+            i += 1;
+        }
+        panic!("pio: unknown source or destination")
+    }
+}
+
+/* Operand tokens of an instruction */
+struct Operands<'t> {
+    tokens: &'t [Span; MAX_TOKENS],
+    first: usize,
+    end: usize,
+}
+
+impl Operands<'_> {
+    const fn len(&self) -> usize {
+        self.end - self.first
+    }
+
+    const fn get(&self, index: usize) -> Span {
+        if index >= self.len() {
+            panic!("pio: operand missing");
+        }
+        self.tokens[self.first + index]
+    }
+
+    /* Operand index and everything after it, for values written with spaces: T - 1 */
+    const fn rest(&self, index: usize) -> Span {
+        Span {
+            start: self.get(index).start,
+            end: self.tokens[self.end - 1].end,
+        }
+    }
+
+    const fn count(&self, count: usize) {
+        if self.len() != count {
+            panic!("pio: wrong number of operands");
+        }
+    }
+}
+
+/* Condition 1-7 */
+const JMP_CONDITIONS: [&str; 7] = ["!x", "x--", "!y", "y--", "x!=y", "pin", "!osre"];
+const IN_SOURCES: [&str; 8] = ["pins", "x", "y", "null", "", "", "isr", "osr"];
+const OUT_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "null", "pindirs", "pc", "isr", "exec"];
+const MOV_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "", "exec", "pc", "isr", "osr"];
+const MOV_SOURCES: [&str; 8] = ["pins", "x", "y", "null", "", "status", "isr", "osr"];
+const SET_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "", "pindirs", "", "", ""];
+
+/* Case-insensitive match of a token against a keyword */
+const fn is(src: &[u8], span: Span, keyword: &str) -> bool {
+    let keyword = keyword.as_bytes();
+    if span.end - span.start != keyword.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < keyword.len() {
+        if src[span.start + i].to_ascii_lowercase() != keyword[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/* Exact match of two tokens (symbol names are case sensitive) */
+const fn same(src: &[u8], a: Span, b: Span) -> bool {
+    if a.end - a.start != b.end - b.start {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.end - a.start {
+        if src[a.start + i] != src[b.start + i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/* Decimal, 0x hex or 0b binary */
+const fn number(src: &[u8], span: Span) -> i32 {
+    let mut i = span.start;
+    let mut radix = 10;
+    if span.end - span.start > 2 && src[i] == b'0' {
+        match src[i + 1] {
+            b'x' | b'X' => radix = 16,
+            b'b' | b'B' => radix = 2,
+            _ => {}
+        }
+        if radix != 10 {
+            i += 2;
+        }
+    }
+    let mut value: i32 = 0;
+    while i < span.end {
+        let digit = match src[i] {
+            c @ b'0'..=b'9' => (c - b'0') as i32,
+            c @ b'a'..=b'f' => (c - b'a' + 10) as i32,
+            c @ b'A'..=b'F' => (c - b'A' + 10) as i32,
+            _ => radix,
+        };
+        if digit >= radix {
+            panic!("pio: bad number");
+        }
+        value = match value.checked_mul(radix) {
+            Some(value) => value + digit,
+            None => panic!("pio: number too big"),
+        };
+        i += 1;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Single instructions, encodings as pioasm gives them */
+    #[test]
+    fn encodes_each_instruction() {
+        let cases: &[(&str, u16)] = &[
+            ("jmp 0", 0x0000),
+            ("jmp !x 5", 0x0025),
+            ("jmp x-- 3", 0x0043),
+            ("jmp !y 1", 0x0061),
+            ("jmp y-- 1", 0x0081),
+            ("jmp x!=y 2", 0x00a2),
+            ("jmp pin 7", 0x00c7),
+            ("jmp !osre 0", 0x00e0),
+            ("wait 1 gpio 5", 0x2085),
+            ("wait 0 pin 0", 0x2020),
+            ("wait 1 irq 2", 0x20c2),
+            ("wait 1 irq 2 rel", 0x20d2),
+            ("in pins, 32", 0x4000),
+            ("in x, 5", 0x4025),
+            ("in null, 1", 0x4061),
+            ("out pins, 1", 0x6001),
+            ("out pindirs, 2", 0x6082),
+            ("out exec, 16", 0x60f0),
+            ("push", 0x8020),
+            ("push block", 0x8020),
+            ("push iffull noblock", 0x8040),
+            ("pull", 0x80a0),
+            ("pull noblock", 0x8080),
+            ("pull ifempty block", 0x80e0),
+            ("mov x, osr", 0xa027),
+            ("mov pins, !x", 0xa009),
+            ("mov pins, ~ x", 0xa009),
+            ("mov isr, ::osr", 0xa0d7),
+            ("mov y, status", 0xa045),
+            ("irq 3", 0xc003),
+            ("irq set 3", 0xc003),
+            ("irq nowait 3", 0xc003),
+            ("irq wait 0 rel", 0xc030),
+            ("irq clear 1", 0xc041),
+            ("set pindirs, 1", 0xe081),
+            ("set pins, 0", 0xe000),
+            ("set x, 31", 0xe03f),
+            ("set y, 0x1f", 0xe05f),
+            ("nop", 0xa042),
+            ("nop [31]", 0xbf42),
+        ];
+        for &(source, word) in cases {
+            let program = assemble(source);
+            assert_eq!(program.instructions(), &[word], "{source}");
+        }
+    }
+
+    /* pico-examples squarewave.pio */
+    #[test]
+    fn squarewave() {
+        const SQUAREWAVE: Program = assemble(
+            "
+            .program squarewave
+                set pindirs, 1   ; Set pin to output
+            again:
+                set pins, 1 [1]  ; Drive pin high and then delay for one cycle
+                set pins, 0      ; Drive pin low
+                jmp again        ; Set PC to label `again`
+            ",
+        );
+        assert_eq!(SQUAREWAVE.instructions(), &[0xe081, 0xe101, 0xe000, 0x0001]);
+        assert_eq!((SQUAREWAVE.wrap_target, SQUAREWAVE.wrap), (0, 3));
+        assert_eq!(SQUAREWAVE.side_set, SideSet::NONE);
+        assert_eq!(SQUAREWAVE.origin, None);
+    }
+
+    /* pico-examples ws2812.pio: side-set, defines in delays, forward labels, wrap */
+    #[test]
+    fn ws2812() {
+        const WS2812: Program = assemble(
+            "
+            .program ws2812
+            .side_set 1
+
+            .define public T1 2
+            .define public T2 5
+            .define public T3 3
+
+            .wrap_target
+            bitloop:
+                out x, 1       side 0 [T3 - 1] ; Side-set still takes place when instruction stalls
+                jmp !x do_zero side 1 [T1 - 1] ; Branch on the bit we shifted out. Positive pulse
+            do_one:
+                jmp  bitloop   side 1 [T2 - 1] ; Continue driving high, for a long pulse
+            do_zero:
+                nop            side 0 [T2 - 1] ; Or drive low, for a short pulse
+            .wrap
+            ",
+        );
+        assert_eq!(WS2812.instructions(), &[0x6221, 0x1123, 0x1400, 0xa442]);
+        assert_eq!((WS2812.wrap_target, WS2812.wrap), (0, 3));
+        assert_eq!(
+            WS2812.side_set,
+            SideSet {
+                bits: 1,
+                optional: false,
+                pindirs: false
+            }
+        );
+    }
+
+    #[test]
+    fn optional_side_set_and_wrap() {
+        let program = assemble(
+            "
+            .side_set 1 opt pindirs
+            .origin 4
+                set x, 2
+            .wrap_target
+            loop:   nop side 1
+                    jmp x-- loop [3]
+            .wrap
+                    nop
+            ",
+        );
+        /* opt: bit 12 enables the side-set, bit 11 is the value, 3 delay bits */
+        assert_eq!(program.instructions(), &[0xe022, 0xb842, 0x0341, 0xa042]);
+        assert_eq!((program.wrap_target, program.wrap), (1, 2));
+        assert_eq!(program.origin, Some(4));
+        assert_eq!(program.side_set.pins(), 1);
+        assert!(program.side_set.optional && program.side_set.pindirs);
+    }
+
+    #[test]
+    fn comments_case_and_numbers() {
+        let program = assemble(
+            "
+            // a comment line
+            SET PINS, 0b101 // trailing comment
+            public entry: Jmp entry
+            .define OFFSET 0x2
+            jmp OFFSET + 1
+            set y, OFFSET - 1
+            ",
+        );
+        assert_eq!(program.instructions(), &[0xe005, 0x0001, 0x0003, 0xe041]);
+    }
+
+    #[test]
+    fn is_jmp_picks_out_jumps() {
+        assert!(is_jmp(0x0043));
+        assert!(!is_jmp(NOP));
+        assert!(!is_jmp(0xe081));
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown instruction")]
+    fn unknown_instruction_panics() {
+        assemble("jump 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "value out of range")]
+    fn delay_too_long_for_side_set_panics() {
+        assemble(".side_set 2\nnop side 0 [8]");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown symbol")]
+    fn unknown_label_panics() {
+        assemble("jmp nowhere");
+    }
+}
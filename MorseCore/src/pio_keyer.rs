@@ -0,0 +1,433 @@
+/* PIO keyer programs and their element encoding */
+/* A PIO state machine plays a message on its own once the elements have been
+   worked out: each element is one word in its TX FIFO, so a DMA channel can
+   feed a whole message and the CPU does nothing until it has been sent.
+   1. The state machine runs at CLOCK_HZ (one cycle per microsecond) and counts
+      time in units. The first word pushed is the unit length as a delay (see
+      delay()), kept in ISR for as long as the program runs.
+   2. Every following word is one element: bit 0 is the key, bits 31:1 the
+      number of units minus one (see word()). An element lasts units * unit
+      cycles plus a few cycles (ELEMENT_OVERHEAD) to fetch the next word.
+   3. The key drives the OUT pin (the LED) and the side-set pin (the speaker).
+      TONE toggles the speaker every unit while the key is down, a unit being
+      half a tone period, KEY_LINE holds it high instead.
+   4. When the FIFO is empty at the end of an element the program raises IRQ
+      flag 0 (relative to the state machine): the message has been sent.
+   The state machine needs STATUS set to "TX FIFO level below 1" for that, and
+   the OUT shift to the right. */
+
+use crate::morse::{self, Element};
+use crate::pio::{assemble, Program};
+use crate::timing::Timing;
+
+/* State machine clock: one cycle per microsecond */
+pub const CLOCK_HZ: u32 = 1_000_000;
+
+/* Cycles a unit takes on top of its delay count */
+pub const UNIT_OVERHEAD: u32 = 3;
+
+/* Cycles between elements: checking the FIFO and taking the next word */
+pub const ELEMENT_OVERHEAD: u32 = 8;
+
+/* Shortest unit the loop can count */
+pub const MIN_UNIT_US: u32 = UNIT_OVERHEAD;
+
+/* Unit for KEY_LINE, only the element lengths need it */
+pub const KEY_LINE_UNIT_US: u32 = 100;
+
+/* Longest element in units */
+pub const MAX_UNITS: u32 = 1 << 31;
+
+/* Key bit of an element word */
+const KEY_DOWN: u32 = 1;
+
+/* Speaker on the side-set pin toggles at the tone frequency while the key is down */
+pub const TONE: Program = assemble(
+    "
+    .program morse_tone
+    .side_set 1 opt                 ; Speaker
+        pull block                  ; Unit delay, kept in ISR
+        mov isr, osr
+    .wrap_target
+    next:
+        mov y, status               ; All ones when the TX FIFO is empty
+        jmp !y element
+        irq nowait 0 rel            ; Nothing left: message sent
+    element:
+        pull block
+        out y, 1                    ; Key
+        mov pins, y                 ; LED follows the key
+        out x, 31                   ; Units - 1
+        jmp !y space
+    high:
+        mov y, isr          side 1  ; Half a tone period high ...
+    high_wait:
+        jmp y-- high_wait
+        jmp x-- low
+        jmp next            side 0
+    low:
+        mov y, isr          side 0  ; ... and half low
+    low_wait:
+        jmp y-- low_wait
+        jmp x-- high
+        jmp next
+    space:
+        mov y, isr
+    space_wait:
+        jmp y-- space_wait
+        jmp x-- space
+    .wrap
+    ",
+);
+
+/* Speaker on the side-set pin is a plain key line, high while the key is down */
+pub const KEY_LINE: Program = assemble(
+    "
+    .program morse_key_line
+    .side_set 1 opt                 ; Key line
+        pull block                  ; Unit delay, kept in ISR
+        mov isr, osr
+    .wrap_target
+    next:
+        mov y, status               ; All ones when the TX FIFO is empty
+        jmp !y element
+        irq nowait 0 rel            ; Nothing left: message sent
+    element:
+        pull block
+        out y, 1                    ; Key
+        mov pins, y                 ; LED follows the key
+        out x, 31                   ; Units - 1
+        jmp !y space
+    mark:
+        mov y, isr          side 1
+    mark_wait:
+        jmp y-- mark_wait
+        jmp x-- mark
+        jmp next            side 0
+    space:
+        mov y, isr
+    space_wait:
+        jmp y-- space_wait
+        jmp x-- space
+    .wrap
+    ",
+);
+
+/* Unit for TONE: half a period of freq_hz, rounded (0 Hz is taken as 1 Hz like pwm's
+   set_freq). 64-bit maths: 2 * freq_hz overflows u32 for the largest frequencies. */
+pub const fn tone_unit_us(freq_hz: u32) -> u32 {
+    let freq = if freq_hz == 0 { 1 } else { freq_hz as u64 };
+    let unit = ((CLOCK_HZ as u64 + freq) / (2 * freq)) as u32;
+    if unit < MIN_UNIT_US {
+        MIN_UNIT_US
+    } else {
+        unit
+    }
+}
+
+/* Tone frequency a unit actually gives */
+pub const fn tone_hz(unit_us: u32) -> u32 {
+    CLOCK_HZ / (2 * unit_us)
+}
+
+/* First word for the state machine: the delay count for a unit of unit_us */
+pub const fn delay(unit_us: u32) -> u32 {
+    assert!(unit_us >= MIN_UNIT_US);
+    unit_us - UNIT_OVERHEAD
+}
+
+/* Element word: key up or down for units (1 to MAX_UNITS) */
+pub const fn word(key_down: bool, units: u32) -> u32 {
+    let units = if units == 0 { 1 } else { units };
+    ((units - 1) << 1) | if key_down { KEY_DOWN } else { 0 }
+}
+
+pub const fn is_key_down(word: u32) -> bool {
+    word & KEY_DOWN != 0
+}
+
+pub const fn units(word: u32) -> u32 {
+    (word >> 1) + 1
+}
+
+/* Word for an element, its length rounded to whole units */
+pub const fn element_word(element: Element, timing: &Timing, unit_us: u32) -> u32 {
+    let units = (timing.duration_us(element) + unit_us / 2) / unit_us;
+    word(element.is_key_down(), units)
+}
+
+/* Words for a message followed by a word gap, like tx::enqueue_text() queues it */
+pub fn words(text: &str, timing: Timing, unit_us: u32) -> impl Iterator<Item = u32> + '_ {
+    morse::encode(text)
+        .chain(core::iter::once(Element::WordGap))
+        .map(move |element| element_word(element, &timing, unit_us))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::Spacing;
+    use std::collections::VecDeque;
+
+    /* Just enough of a state machine to run the keyer programs: side-set
+       "1 opt", OUT shifting right, STATUS = TX FIFO empty, no delays */
+    struct Machine {
+        program: Program,
+        pc: usize,
+        x: u32,
+        y: u32,
+        isr: u32,
+        osr: u32,
+        fifo: VecDeque<u32>,
+        irq: bool,
+        cycle: u64,
+        led: bool,
+        speaker: bool,
+        led_edges: Vec<(u64, bool)>,
+        speaker_edges: Vec<(u64, bool)>,
+    }
+
+    impl Machine {
+        fn new(program: Program, words: impl IntoIterator<Item = u32>) -> Self {
+            Machine {
+                program,
+                pc: 0,
+                x: 0,
+                y: 0,
+                isr: 0,
+                osr: 0,
+                fifo: words.into_iter().collect(),
+                irq: false,
+                cycle: 0,
+                led: false,
+                speaker: false,
+                led_edges: Vec::new(),
+                speaker_edges: Vec::new(),
+            }
+        }
+
+        fn step(&mut self) {
+            let instruction = self.program.instructions()[self.pc];
+            if instruction & 0x1000 != 0 {
+                let level = instruction & 0x0800 != 0;
+                if level != self.speaker {
+                    self.speaker = level;
+                    self.speaker_edges.push((self.cycle, level));
+                }
+            }
+            self.cycle += 1;
+            let mut next = if self.pc == self.program.wrap as usize {
+                self.program.wrap_target as usize
+            } else {
+                self.pc + 1
+            };
+            let field = (instruction >> 5) & 7;
+            match instruction >> 13 {
+                0 => {
+                    let taken = match field {
+                        0 => true,
+                        1 => self.x == 0,
+                        2 => {
+                            let taken = self.x != 0;
+                            self.x = self.x.wrapping_sub(1);
+                            taken
+                        }
+                        3 => self.y == 0,
+                        4 => {
+                            let taken = self.y != 0;
+                            self.y = self.y.wrapping_sub(1);
+                            taken
+                        }
+                        _ => panic!("jmp condition not simulated"),
+                    };
+                    if taken {
+                        next = (instruction & 31) as usize;
+                    }
+                }
+                3 => {
+                    let count = instruction as u32 & 31;
+                    let value = self.osr & ((1u64 << count) - 1) as u32;
+                    self.osr = ((self.osr as u64) >> count) as u32;
+                    match field {
+                        1 => self.x = value,
+                        2 => self.y = value,
+                        _ => panic!("out destination not simulated"),
+                    }
+                }
+                4 => match self.fifo.pop_front() {
+                    Some(word) => self.osr = word,
+                    None => return, /* Stalled */
+                },
+                5 => {
+                    let value = match instruction & 7 {
+                        2 => self.y,
+                        5 => if self.fifo.is_empty() { u32::MAX } else { 0 },
+                        6 => self.isr,
+                        7 => self.osr,
+                        _ => panic!("mov source not simulated"),
+                    };
+                    match field {
+                        0 => {
+                            let level = value & 1 != 0;
+                            if level != self.led {
+                                self.led = level;
+                                self.led_edges.push((self.cycle, level));
+                            }
+                        }
+                        2 => self.y = value,
+                        6 => self.isr = value,
+                        _ => panic!("mov destination not simulated"),
+                    }
+                }
+                6 => self.irq = true,
+                _ => panic!("instruction not simulated"),
+            }
+            self.pc = next;
+        }
+
+        /* Run until the end-of-message IRQ */
+        fn run(&mut self) {
+            while !self.irq {
+                self.step();
+                assert!(self.cycle < 100_000_000, "program never finished");
+            }
+        }
+    }
+
+    #[test]
+    fn word_encoding() {
+        assert_eq!(word(true, 1), 0b1);
+        assert_eq!(word(false, 1), 0b0);
+        assert_eq!(word(true, 3), 0b101);
+        assert_eq!(word(false, 0), word(false, 1));
+        assert_eq!(word(true, MAX_UNITS), u32::MAX);
+        for units_in in [1, 2, 112, 1000, MAX_UNITS] {
+            for down in [false, true] {
+                let w = word(down, units_in);
+                assert_eq!(is_key_down(w), down);
+                assert_eq!(units(w), units_in);
+            }
+        }
+    }
+
+    #[test]
+    fn units_and_tone() {
+        assert_eq!(tone_unit_us(700), 714);
+        assert_eq!(tone_hz(714), 700);
+        assert_eq!(tone_unit_us(1000), 500);
+        assert_eq!(tone_unit_us(u32::MAX / 4), MIN_UNIT_US);
+        assert_eq!(tone_unit_us(u32::MAX), MIN_UNIT_US);
+        /* 0 Hz is taken as 1 Hz rather than dividing by zero */
+        assert_eq!(tone_unit_us(0), 500_000);
+        assert_eq!(tone_unit_us(0), tone_unit_us(1));
+        assert_eq!(delay(714), 711);
+    }
+
+    #[test]
+    fn elements_round_to_units() {
+        let timing = Timing::standard(15); /* 80 ms dot */
+        let dit = element_word(Element::Dit, &timing, 714);
+        assert!(is_key_down(dit));
+        assert_eq!(units(dit), 112); /* 80000 / 714 = 112.04 */
+        let gap = element_word(Element::WordGap, &timing, KEY_LINE_UNIT_US);
+        assert!(!is_key_down(gap));
+        assert_eq!(units(gap), 5600);
+    }
+
+    #[test]
+    fn paris_is_fifty_units() {
+        let timing = Timing::new(20, 20, Spacing::Standard); /* 60 ms dot */
+        let total: u32 = words("PARIS", timing, KEY_LINE_UNIT_US).map(units).sum();
+        assert_eq!(total * KEY_LINE_UNIT_US, 50 * 60_000);
+        let marks = words("PARIS", timing, KEY_LINE_UNIT_US).filter(|&w| is_key_down(w)).count();
+        assert_eq!(marks, 14);
+    }
+
+    #[test]
+    fn programs_fit_together() {
+        assert_eq!(TONE.len(), 21);
+        assert_eq!(KEY_LINE.len(), 17);
+        assert_eq!(TONE.side_set.pins(), 1);
+        assert_eq!(TONE.side_set, KEY_LINE.side_set);
+        assert_eq!(TONE.side_set.bits, 2);
+        assert_eq!(TONE.wrap_target, 2);
+        assert_eq!(TONE.wrap as usize, TONE.len() - 1);
+        /* First words: pull block, mov isr, osr, mov y, status */
+        assert_eq!(&TONE.instructions()[..3], &[0x80a0, 0xa0c7, 0xa045]);
+        assert_eq!(&KEY_LINE.instructions()[..3], &[0x80a0, 0xa0c7, 0xa045]);
+    }
+
+    /* Key-down stretches from each LED edge: (start cycle, length) */
+    fn marks(machine: &Machine) -> Vec<(u64, u64)> {
+        machine
+            .led_edges
+            .chunks(2)
+            .map(|pair| (pair[0].0, pair[1].0 - pair[0].0))
+            .collect()
+    }
+
+    #[test]
+    fn tone_keys_exact_lengths() {
+        let timing = Timing::standard(15);
+        let unit = tone_unit_us(700);
+        let mut machine = Machine::new(
+            TONE,
+            core::iter::once(delay(unit)).chain(words("EA", timing, unit)),
+        );
+        machine.run();
+        assert!(!machine.led && !machine.speaker);
+
+        /* E, then A: dit, dah. Each mark is its units plus the element overhead. */
+        let (unit, overhead) = (unit as u64, ELEMENT_OVERHEAD as u64);
+        let (dit, dah) = (112 * unit + overhead, 336 * unit + overhead);
+        let marks = marks(&machine);
+        assert_eq!(marks.iter().map(|m| m.1).collect::<Vec<_>>(), [dit, dit, dah]);
+
+        /* The speaker toggles every unit during a mark and rests low between them */
+        for &(start, length) in &marks {
+            let edges: Vec<u64> = machine
+                .speaker_edges
+                .iter()
+                .filter(|e| e.0 >= start && e.0 <= start + length)
+                .map(|e| e.0)
+                .collect();
+            assert_eq!(edges.len() as u64, length / unit);
+            assert!(edges.windows(2).all(|w| w[1] - w[0] == unit));
+        }
+
+        /* Spaces between the marks: E's char gap, then A's intra gap. A space
+           takes one cycle less than a mark, it runs into the wrap. */
+        let space = |i: usize| marks[i + 1].0 - (marks[i].0 + marks[i].1);
+        assert_eq!(space(0), 336 * unit + overhead - 1);
+        assert_eq!(space(1), 112 * unit + overhead - 1);
+    }
+
+    #[test]
+    fn key_line_holds_the_pin() {
+        let timing = Timing::standard(20);
+        let mut machine = Machine::new(
+            KEY_LINE,
+            core::iter::once(delay(KEY_LINE_UNIT_US)).chain(words("T", timing, KEY_LINE_UNIT_US)),
+        );
+        machine.run();
+        /* One dah of 180 ms: a single high stretch on both pins */
+        assert_eq!(machine.speaker_edges.len(), 2);
+        assert_eq!(machine.led_edges.len(), 2);
+        let speaker = machine.speaker_edges[1].0 - machine.speaker_edges[0].0;
+        assert_eq!(speaker, 180_000);
+        assert_eq!(marks(&machine), [(machine.led_edges[0].0, 180_000 + ELEMENT_OVERHEAD as u64)]);
+    }
+
+    #[test]
+    fn irq_only_when_the_fifo_runs_dry() {
+        let timing = Timing::standard(20);
+        let mut machine = Machine::new(
+            KEY_LINE,
+            core::iter::once(delay(KEY_LINE_UNIT_US)).chain(words("E", timing, KEY_LINE_UNIT_US)),
+        );
+        machine.run();
+        /* Dit and word gap played out before the IRQ */
+        let played = (1 + 7) * 60_000;
+        assert!(machine.cycle >= played && machine.cycle < played + 100);
+    }
+}
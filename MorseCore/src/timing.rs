@@ -0,0 +1,189 @@
+/* Morse element timing in integer microseconds */
+/* Standard timing uses the PARIS word (50 dot units): one dot lasts 1200 ms / WPM.
+   For learners the characters can be sent at a high character speed while the
+   gaps are stretched so the overall (effective) speed is lower:
+   1. Farnsworth: inter-character and inter-word gaps are both stretched (ARRL formula)
+   2. Wordsworth: only the inter-word gap is stretched
+   Speeds are at least 1 WPM, 0 is taken as 1. */
+
+use crate::morse::Element;
+
+/* How gaps are stretched when the effective speed is below the character speed */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spacing {
+    Standard,     /* Ignore the effective speed */
+    Farnsworth,
+    Wordsworth,
+}
+
+/* Length of every element in microseconds */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timing {
+    pub dot_us: u32,
+    pub dah_us: u32,
+    pub intra_gap_us: u32,
+    pub char_gap_us: u32,
+    pub word_gap_us: u32,
+}
+
+/* Speeds below 1 WPM are taken as 1 WPM, a speed of 0 would divide by zero */
+const fn at_least_1(wpm: u32) -> u32 {
+    if wpm == 0 {
+        1
+    } else {
+        wpm
+    }
+}
+
+/* Dot length in microseconds (PARIS standard: 1200 ms / WPM) */
+pub const fn dot_us(wpm: u32) -> u32 {
+    1_200_000 / at_least_1(wpm)
+}
+
+impl Timing {
+    /* Everything at one speed: 1, 3, 1, 3 and 7 dot units */
+    pub const fn standard(wpm: u32) -> Self {
+        let dot = dot_us(wpm);
+        Timing {
+            dot_us: dot,
+            dah_us: 3 * dot,
+            intra_gap_us: dot,
+            char_gap_us: 3 * dot,
+            word_gap_us: 7 * dot,
+        }
+    }
+
+    /* Characters at char_wpm, gaps stretched for an overall effective_wpm.
+       ARRL: ta = (60c - 37.2s) / (sc) seconds of total delay per word,
+       tc = 3ta / 19 between characters, tw = 7ta / 19 between words.
+       Falls back to standard timing when effective_wpm >= char_wpm. */
+    pub const fn farnsworth(char_wpm: u32, effective_wpm: u32) -> Self {
+        let (char_wpm, effective_wpm) = (at_least_1(char_wpm), at_least_1(effective_wpm));
+        if effective_wpm >= char_wpm {
+            return Timing::standard(char_wpm);
+        }
+        let c = char_wpm as u64;
+        let s = effective_wpm as u64;
+        /* ta * 19 * sc in microseconds, divided once per gap to keep rounding below 1 us */
+        let ta_num = 60_000_000 * c - 37_200_000 * s;
+        let den = 19 * s * c;
+        let mut timing = Timing::standard(char_wpm);
+        timing.char_gap_us = (3 * ta_num / den) as u32;
+        timing.word_gap_us = (7 * ta_num / den) as u32;
+        timing
+    }
+
+    /* Characters and character gaps at char_wpm, only the word gap is stretched
+       so that one PARIS word (43 units of characters, 7 units of word gap)
+       takes 60 s / effective_wpm */
+    pub const fn wordsworth(char_wpm: u32, effective_wpm: u32) -> Self {
+        let (char_wpm, effective_wpm) = (at_least_1(char_wpm), at_least_1(effective_wpm));
+        if effective_wpm >= char_wpm {
+            return Timing::standard(char_wpm);
+        }
+        let mut timing = Timing::standard(char_wpm);
+        timing.word_gap_us = 60_000_000 / effective_wpm - 43 * timing.dot_us;
+        timing
+    }
+
+    pub const fn new(char_wpm: u32, effective_wpm: u32, spacing: Spacing) -> Self {
+        match spacing {
+            Spacing::Standard => Timing::standard(char_wpm),
+            Spacing::Farnsworth => Timing::farnsworth(char_wpm, effective_wpm),
+            Spacing::Wordsworth => Timing::wordsworth(char_wpm, effective_wpm),
+        }
+    }
+
+    /* How long to play an element for */
+    pub const fn duration_us(&self, element: Element) -> u32 {
+        match element {
+            Element::Dit => self.dot_us,
+            Element::Dah => self.dah_us,
+            Element::IntraGap => self.intra_gap_us,
+            Element::CharGap => self.char_gap_us,
+            Element::WordGap => self.word_gap_us,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse;
+
+    /* ARRL Farnsworth formula in floating point, gaps in microseconds */
+    fn arrl(c: f64, s: f64) -> (f64, f64) {
+        let ta = (60.0 * c - 37.2 * s) / (s * c);
+        (3.0 * ta / 19.0 * 1e6, 7.0 * ta / 19.0 * 1e6)
+    }
+
+    /* Total length of a message in microseconds */
+    fn message_us(text: &str, timing: &Timing) -> u64 {
+        morse::encode(text).map(|e| timing.duration_us(e) as u64).sum()
+    }
+
+    #[test]
+    fn standard_paris_is_fifty_units() {
+        let timing = Timing::standard(20);
+        assert_eq!(timing.dot_us, 60_000);
+        /* PARIS plus its trailing word gap */
+        let word = message_us("PARIS", &timing) + timing.word_gap_us as u64;
+        assert_eq!(word, 50 * 60_000);
+    }
+
+    #[test]
+    fn farnsworth_matches_arrl_formula() {
+        for c in [13u32, 15, 18, 20, 25, 30] {
+            for s in 5..c {
+                let timing = Timing::farnsworth(c, s);
+                let (tc, tw) = arrl(c as f64, s as f64);
+                assert!((timing.char_gap_us as f64 - tc).abs() <= 1.0, "c={} s={}", c, s);
+                assert!((timing.word_gap_us as f64 - tw).abs() <= 1.0, "c={} s={}", c, s);
+                assert_eq!(timing.dot_us, dot_us(c));
+            }
+        }
+    }
+
+    #[test]
+    fn farnsworth_paris_runs_at_effective_speed() {
+        let timing = Timing::farnsworth(18, 5);
+        let word = message_us("PARIS", &timing) + timing.word_gap_us as u64;
+        let expected = 60_000_000 / 5;
+        assert!((word as i64 - expected as i64).abs() < 100, "word = {} us", word);
+    }
+
+    #[test]
+    fn wordsworth_only_stretches_word_gap() {
+        let timing = Timing::wordsworth(20, 10);
+        assert_eq!(timing.char_gap_us, 3 * timing.dot_us);
+        let word = message_us("PARIS", &timing) + timing.word_gap_us as u64;
+        assert_eq!(word, 60_000_000 / 10);
+    }
+
+    #[test]
+    fn effective_above_char_speed_is_standard() {
+        assert_eq!(Timing::farnsworth(15, 20), Timing::standard(15));
+        assert_eq!(Timing::wordsworth(15, 15), Timing::standard(15));
+    }
+
+    #[test]
+    fn zero_speed_is_one_wpm() {
+        assert_eq!(dot_us(0), dot_us(1));
+        assert_eq!(Timing::standard(0), Timing::standard(1));
+        assert_eq!(Timing::new(20, 0, Spacing::Farnsworth), Timing::farnsworth(20, 1));
+        assert_eq!(Timing::new(20, 0, Spacing::Wordsworth), Timing::wordsworth(20, 1));
+        assert_eq!(Timing::farnsworth(0, 0), Timing::standard(1));
+    }
+
+    #[test]
+    fn decoder_start_speed_is_clamped() {
+        use crate::decoder::Decoder;
+        /* 0 and 1 WPM start at the slowest estimate (400 ms, 3 WPM) */
+        assert_eq!(Decoder::new(0).dot_us(), 400_000);
+        assert_eq!(Decoder::new(1).wpm(), 3);
+        /* dot_us() rounds to 0 above 1_200_000 WPM, held at 10 ms (120 WPM) */
+        assert_eq!(Decoder::new(200).dot_us(), 10_000);
+        assert_eq!(Decoder::new(2_000_000).wpm(), 120);
+        assert_eq!(Decoder::new(20).dot_us(), dot_us(20));
+    }
+}
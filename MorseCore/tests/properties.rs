@@ -0,0 +1,143 @@
+/* Property tests for the Morse logic, run on the host with `cargo test` */
+
+use morse_core::decoder::{Decoder, Symbol};
+use morse_core::morse::{self, Charset, Decoded, Element};
+use morse_core::timing::{dot_us, Timing};
+use proptest::prelude::*;
+
+/* Characters whose pattern decodes back to the same character */
+const ROUND_TRIP: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789.,:?'-/()\"=+@!&;_$";
+
+fn word() -> impl Strategy<Value = String> {
+    let chars: Vec<char> = ROUND_TRIP.chars().collect();
+    prop::collection::vec(prop::sample::select(chars), 1..7).prop_map(|c| c.into_iter().collect())
+}
+
+fn words() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec(word(), 1..5)
+}
+
+/* Key text into a decoder, stretching every element by jitter[i] percent */
+fn key(decoder: &mut Decoder, text: &str, timing: &Timing, jitter: &[i32], start: u32) -> u32 {
+    let mut t = start;
+    for (i, element) in morse::encode(text).enumerate() {
+        let nominal = timing.duration_us(element) as i64;
+        let length = (nominal * (100 + jitter[i % jitter.len()]) as i64 / 100) as u32;
+        if element.is_key_down() {
+            decoder.key_down(t);
+            t = t.wrapping_add(length);
+            decoder.key_up(t);
+        } else {
+            t = t.wrapping_add(length);
+        }
+    }
+    t
+}
+
+fn read_all(decoder: &mut Decoder) -> String {
+    let mut text = String::new();
+    while let Some(symbol) = decoder.read() {
+        match symbol {
+            Symbol::Char(c) => text.push(c),
+            Symbol::Prosign(name) => text.push_str(name),
+            Symbol::Unknown => text.push('?'),
+            Symbol::Space => text.push(' '),
+        }
+    }
+    text
+}
+
+/* Decode at most 15 characters at a time so the 16 symbol queue never overflows */
+fn send_and_decode(words: &[String], timing: &Timing, guess_wpm: u32, jitter: &[i32], start: u32) -> String {
+    let mut decoder = Decoder::new(guess_wpm);
+    let mut t = start;
+    let mut text = String::new();
+    for word in words {
+        t = key(&mut decoder, word, timing, jitter, t);
+        t = t.wrapping_add(timing.word_gap_us);
+        decoder.poll(t);
+        text.push_str(&read_all(&mut decoder));
+    }
+    text
+}
+
+proptest! {
+    #[test]
+    fn table_round_trips(c in prop::sample::select(ROUND_TRIP.chars().collect::<Vec<_>>())) {
+        let pattern = morse::lookup(c).unwrap();
+        prop_assert_eq!(morse::decode(pattern, Charset::Latin), Some(Decoded::Char(c)));
+        prop_assert_eq!(morse::lookup(c.to_ascii_lowercase()), Some(pattern));
+    }
+
+    #[test]
+    fn timeline_alternates_marks_and_gaps(text in "\\PC{0,40}") {
+        let elements: Vec<Element> = morse::encode(&text).collect();
+        if let (Some(first), Some(last)) = (elements.first(), elements.last()) {
+            prop_assert!(first.is_key_down());
+            prop_assert!(last.is_key_down());
+        }
+        for pair in elements.windows(2) {
+            prop_assert!(pair[0].is_key_down() != pair[1].is_key_down());
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip(words in words(), wpm in 5u32..=40, start in any::<u32>()) {
+        let timing = Timing::standard(wpm);
+        let text = send_and_decode(&words, &timing, wpm, &[0], start);
+        prop_assert_eq!(text, words.join(" ") + " ");
+    }
+
+    #[test]
+    fn tolerates_timing_jitter(
+        words in words(),
+        wpm in 5u32..=40,
+        jitter in prop::collection::vec(-15i32..=15, 1..64),
+    ) {
+        let timing = Timing::standard(wpm);
+        let text = send_and_decode(&words, &timing, wpm, &jitter, 0);
+        prop_assert_eq!(text, words.join(" ") + " ");
+    }
+
+    #[test]
+    fn tolerates_a_wrong_speed_guess(words in words(), wpm in 8u32..=30, guess in 80u32..=120) {
+        let timing = Timing::standard(wpm);
+        let guess_wpm = wpm * guess / 100;
+        let text = send_and_decode(&words, &timing, guess_wpm, &[0], 0);
+        prop_assert_eq!(text, words.join(" ") + " ");
+    }
+
+    /* The decoder measures gaps in dots of the mark speed, so a character gap
+       stretched past 5 dots reads as a word gap. Below that it must still decode. */
+    #[test]
+    fn decodes_farnsworth_spacing(words in words(), c in 10u32..=30, s in 5u32..=30) {
+        let timing = Timing::farnsworth(c, s.min(c));
+        prop_assume!(timing.char_gap_us < 5 * timing.dot_us);
+        let text = send_and_decode(&words, &timing, c, &[0], 0);
+        prop_assert_eq!(text, words.join(" ") + " ");
+    }
+
+    #[test]
+    fn stretched_gaps_never_shrink(c in 5u32..=60, s in 1u32..=60) {
+        let standard = Timing::standard(c);
+        for timing in [Timing::farnsworth(c, s), Timing::wordsworth(c, s)] {
+            prop_assert_eq!(timing.dot_us, dot_us(c));
+            prop_assert_eq!(timing.dah_us, standard.dah_us);
+            prop_assert_eq!(timing.intra_gap_us, standard.intra_gap_us);
+            prop_assert!(timing.char_gap_us >= standard.char_gap_us);
+            prop_assert!(timing.word_gap_us >= standard.word_gap_us);
+        }
+    }
+
+    #[test]
+    fn paris_runs_at_effective_speed(c in 5u32..=60, s in 5u32..=60) {
+        let s = s.min(c);
+        for timing in [Timing::farnsworth(c, s), Timing::wordsworth(c, s)] {
+            let word: u64 = morse::encode("PARIS").map(|e| timing.duration_us(e) as u64).sum::<u64>()
+                + timing.word_gap_us as u64;
+            let expected = 60_000_000 / s as u64;
+            /* Integer dot rounding: at most a microsecond per unit */
+            prop_assert!(word.abs_diff(expected) <= 50, "c={} s={} word={}", c, s, word);
+        }
+    }
+}
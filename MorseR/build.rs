@@ -87,85 +87,159 @@ fn main() {
         crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]
     );
 
+    // Shared Morse logic, linked into both images
+    println!("Compiling morse-core...");
+    compile_core(&out_dir, &project_dir);
+    println!("morse-core compiled successfully.");
+
     println!("Compiling startup...");
+    compile_object(&out_dir, &project_dir, "startup", &["startup"]);
+    println!("Startup compiled successfully.");
+
+    // Create the correct sections in the final ELF by using a proper memory.x linker script
+    // Make sure the linker script is properly passed
+    println!("cargo:rustc-link-search={}", project_dir);
+
+    // One image per board, both sharing boot2.o and startup.o
+    build_image(&out_dir, &project_dir, "transmit", "transmitter");
+    build_image(&out_dir, &project_dir, "receive", "receiver");
+
+    // Tell Cargo to rebuild if the source files change
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../MorseCore/src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=memory.x");
+}
+
+// Optional cargo features that are passed through to morse-core
+const OPTION_FEATURES: &[&str] = &["cyrillic", "greek", "wabun"];
+
+// Compile ../MorseCore into libmorse_core.rlib for the application objects to use
+fn compile_core(out_dir: &str, project_dir: &str) {
+    let mut args: Vec<String> = vec![
+        "--crate-type=rlib".into(),
+        "--crate-name=morse_core".into(),
+        "--edition=2021".into(),
+        "--target=thumbv6m-none-eabi".into(),
+        "-C".into(), "opt-level=s".into(),
+        "-C".into(), "panic=abort".into(),
+        "-C".into(), "debuginfo=2".into(),
+    ];
+    for option in OPTION_FEATURES {
+        if env::var_os(format!("CARGO_FEATURE_{}", option.to_uppercase())).is_some() {
+            args.push("--cfg".into());
+            args.push(format!("feature=\"{}\"", option));
+        }
+    }
+    args.push("-o".into());
+    args.push(format!("{}/libmorse_core.rlib", out_dir));
+    args.push(format!("{}/../MorseCore/src/lib.rs", project_dir));
+
     let status = Command::new("rustc")
-        .args(&[
-            "--crate-type=lib",
-            "--emit=obj",
-            "--target=thumbv6m-none-eabi",
-            "-C", "opt-level=s",
-            "-C", "link-arg=-nostartfiles",
-            "-C", "panic=abort",
-            "-C", "debuginfo=2",
-            "--cfg", "feature=\"startup\"",  
-            "-o", &format!("{}/startup.o", out_dir),
-            &format!("{}/src/lib.rs", project_dir),
-        ])
+        .args(&args)
         .status()
-        .expect("Failed to compile startup");
+        .expect("Failed to compile morse-core");
 
     if !status.success() {
-        panic!("Failed to compile startup");
+        panic!("Failed to compile morse-core");
     }
-    println!("Startup compiled successfully.");
+}
+
+// Compile src/lib.rs into <name>.o with the given feature flags enabled
+fn compile_object(out_dir: &str, project_dir: &str, name: &str, features: &[&str]) {
+    let mut args: Vec<String> = vec![
+        "--crate-type=lib".into(),
+        "--emit=obj".into(),
+        "--edition=2021".into(),
+        "--target=thumbv6m-none-eabi".into(),
+        "-C".into(), "opt-level=s".into(),
+        "-C".into(), "link-arg=-nostartfiles".into(),
+        "-C".into(), "panic=abort".into(),
+        "-C".into(), "debuginfo=2".into(),
+    ];
+    for feature in features {
+        args.push("--cfg".into());
+        args.push(format!("feature=\"{}\"", feature));
+    }
+    args.push("--extern".into());
+    args.push(format!("morse_core={}/libmorse_core.rlib", out_dir));
+    args.push("-o".into());
+    args.push(format!("{}/{}.o", out_dir, name));
+    args.push(format!("{}/src/lib.rs", project_dir));
 
-    println!("Compiling transmit...");
     let status = Command::new("rustc")
-        .args(&[
-            "--crate-type=lib",
-            "--emit=obj",
-            "--target=thumbv6m-none-eabi",
-            "-C", "opt-level=s",
-            "-C", "link-arg=-nostartfiles",
-            "-C", "panic=abort",
-            "-C", "debuginfo=2",
-            "--cfg", "feature=\"transmit\"",  // Add transmit feature flag
-            "-o", &format!("{}/transmit.o", out_dir),
-            &format!("{}/src/lib.rs", project_dir),
-        ])
+        .args(&args)
         .status()
-        .expect("Failed to compile transmit");
+        .unwrap_or_else(|_| panic!("Failed to compile {}", name));
 
     if !status.success() {
-        panic!("Failed to compile transmit");
+        panic!("Failed to compile {}", name);
     }
-    println!("Transmit compiled successfully.");
+}
 
-    // Create the correct sections in the final ELF by using a proper memory.x linker script
-    // Make sure the linker script is properly passed
-    println!("cargo:rustc-link-search={}", project_dir);
+// libcore and compiler_builtins for the target. The objects above are built without
+// cargo, so division helpers (__aeabi_uidiv), memcpy/memcmp and the core panic
+// paths have to come straight from the sysroot rlibs.
+fn target_rlibs() -> Vec<String> {
+    let output = Command::new("rustc")
+        .args(&["--print", "sysroot"])
+        .output()
+        .expect("Failed to query rustc sysroot");
+    let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let lib_dir = format!("{}/lib/rustlib/thumbv6m-none-eabi/lib", sysroot);
+
+    let mut rlibs = Vec::new();
+    for entry in fs::read_dir(&lib_dir).expect("thumbv6m-none-eabi target is not installed") {
+        let name = entry.unwrap().file_name().to_string_lossy().to_string();
+        if (name.starts_with("libcore-") || name.starts_with("libcompiler_builtins-"))
+            && name.ends_with(".rlib")
+        {
+            rlibs.push(format!("{}/{}", lib_dir, name));
+        }
+    }
+    rlibs
+}
+
+// Compile the application object for `feature`, link it with boot2 and startup
+// into <image>.elf and convert that to <image>.uf2 in the project directory
+fn build_image(out_dir: &str, project_dir: &str, feature: &str, image: &str) {
+    println!("Compiling {}...", feature);
+    compile_object(out_dir, project_dir, feature, &[feature]);
+    println!("{} compiled successfully.", feature);
 
     // Link all object files into a final ELF file
-    println!("Linking ELF...");
+    println!("Linking {}.elf...", image);
     let status = Command::new("arm-none-eabi-gcc")
         .args(&[
-            "-mcpu=cortex-m0plus", 
+            "-mcpu=cortex-m0plus",
             "-nostdlib",
             "-g",
             &format!("-T{}/memory.x", project_dir),  // Full path to memory.x
-            "-o", &format!("{}/transmitter.elf", out_dir),
+            "-o", &format!("{}/{}.elf", out_dir, image),
             &format!("{}/boot2.o", out_dir),
             &format!("{}/startup.o", out_dir),
-            &format!("{}/transmit.o", out_dir),
-            "-Wl,-Map=output.map",
-            "-Wl,--allow-multiple-definition"  
+            &format!("{}/{}.o", out_dir, feature),
+            &format!("-Wl,-Map={}.map", image),
+            "-Wl,--allow-multiple-definition",
+            &format!("{}/libmorse_core.rlib", out_dir),
         ])
+        .args(&target_rlibs())
         .status()
         .expect("Failed to link ELF file");
 
     if !status.success() {
-        panic!("Failed to link ELF file");
+        panic!("Failed to link {}.elf", image);
     }
     println!("ELF linked successfully.");
 
     // Convert ELF to binary first
     println!("Converting ELF to binary...");
-    let transmitter_bin = format!("{}/transmitter.bin", out_dir);
+    let image_bin = format!("{}/{}.bin", out_dir, image);
     let status = Command::new("arm-none-eabi-objcopy")
         .args(&[
             "-O", "binary",
-            &format!("{}/transmitter.elf", out_dir),
-            &transmitter_bin,
+            &format!("{}/{}.elf", out_dir, image),
+            &image_bin,
         ])
         .status()
         .expect("Failed to convert ELF to binary");
@@ -178,27 +252,22 @@ fn main() {
     println!("Generating UF2 file...");
     let __ = Command::new("python3")
         .args(&[
-            "tools/uf2/utils/uf2conv.py", 
+            "tools/uf2/utils/uf2conv.py",
             "-b", "0x10000000",  // Base address for RP2040
             "-f", "0xe48bff56",  // RP2040 family ID
             "-c",  // Specify that input is a BIN file
-            &transmitter_bin, 
-            "-o", &format!("{}/transmitter.uf2", out_dir)
+            &image_bin,
+            "-o", &format!("{}/{}.uf2", out_dir, image)
         ])
         .output()
         .expect("Failed to convert to UF2");
 
     // Copy the final UF2 file to the project directory
-    let final_uf2 = format!("{}/transmitter.uf2", out_dir);
-    
+    let final_uf2 = format!("{}/{}.uf2", out_dir, image);
+
     // Use fs instead of the cp command for better cross-platform compatibility
-    fs::copy(&final_uf2, format!("{}/transmitter.uf2", project_dir))
+    fs::copy(&final_uf2, format!("{}/{}.uf2", project_dir, image))
         .expect("Failed to copy UF2 file");
 
-    println!("UF2 file copied to project directory.");
-
-    // Tell Cargo to rebuild if the source files change
-    println!("cargo:rerun-if-changed=src");
-    println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=memory.x");
-}
\ No newline at end of file
+    println!("{}.uf2 copied to project directory.", image);
+}
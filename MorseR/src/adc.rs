@@ -0,0 +1,284 @@
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::dma::{self, Target};
+use crate::gpio::{Analog, Pin};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+
+/* ADC (12-bit SAR, 500 ksample/s) */
+/* Inputs 0-3 are GPIO26-29, input 4 is the on-chip temperature sensor.
+   1. read() is a one-shot conversion of one input, about 2 us
+   2. start_free_running() converts back to back, or at the rate set with
+      set_sample_rate(). With a round-robin mask the input advances after each
+      conversion, so several inputs are sampled in turn.
+   3. Free-running results go into a 4-deep FIFO. The FIFO interrupt fires once
+      it holds `threshold` samples and calls the handler set with set_handler(),
+      which should drain it with fifo_pop().
+   Samples carry an error flag: the conversion was out of spec.
+   4. Input 4 is the temperature sensor, switched on with set_temperature_sensor().
+      read_temperature() averages TEMPERATURE_SAMPLES one-shot conversions of it.
+   With the FIFO enabled, one-shot results go into it as well. */
+
+#[repr(C)]
+pub struct AdcHw {
+    pub cs: RwAlias,        /* Control and status */
+    pub result: Ro,         /* Result of the latest conversion */
+    pub fcs: RwAlias,       /* FIFO control and status */
+    pub fifo: Ro,           /* Reading pops the FIFO */
+    pub div: RwAlias,       /* Sample rate divider, 16.8 fixed point */
+    pub intr: Ro,           /* Raw FIFO interrupt */
+    pub inte: RwAlias,
+    pub intf: RwAlias,
+    pub ints: Ro,
+}
+
+const ADC_BASE: u32 = 0x4004c000;
+const RESET_ADC: u32 = 0;
+pub const ADC_IRQ_FIFO: u32 = 22;
+
+#[inline(always)]
+fn adc() -> &'static AdcHw {
+    block(ADC_BASE)
+}
+
+/* CS fields */
+const CS_EN: Field<bool> = bit(0);
+const CS_TS_EN: Field<bool> = bit(1);
+const CS_START_ONCE: Field<bool> = bit(2);
+const CS_START_MANY: Field<bool> = bit(3);
+const CS_READY: Field<bool> = bit(8);
+const CS_AINSEL: Field = Field::new(12, 3);
+const CS_RROBIN: Field = Field::new(16, 5);
+
+/* FCS fields */
+const FCS_EN: Field<bool> = bit(0);
+const FCS_ERR: Field<bool> = bit(2);
+const FCS_DREQ_EN: Field<bool> = bit(3);
+const FCS_EMPTY: Field<bool> = bit(8);
+const FCS_UNDER: Field<bool> = bit(10);
+const FCS_OVER: Field<bool> = bit(11);
+const FCS_LEVEL: Field = Field::new(16, 4);
+const FCS_THRESH: Field = Field::new(24, 4);
+
+/* FIFO fields */
+const FIFO_VAL: Field = Field::new(0, 12);
+const FIFO_ERR: Field<bool> = bit(15);
+
+/* INTE */
+const INT_FIFO: Field<bool> = bit(0);
+
+/* DIV fields */
+const DIV_FRAC: Field = Field::new(0, 8);
+const DIV_INT: Field = Field::new(8, 16);
+
+/* A conversion takes 96 ADC clocks */
+const CYCLES_PER_SAMPLE: u32 = 96;
+
+/* Longest period, INT and FRAC both full, in 1/256 cycles */
+const MAX_DIV256: u64 = 0x00ff_ffff + 256;
+
+pub const FIFO_DEPTH: usize = 4;
+pub const TEMPERATURE_INPUT: u8 = 4;
+
+/* Conversions averaged per temperature reading */
+pub const TEMPERATURE_SAMPLES: u32 = 16;
+
+/* Temperature sensor (datasheet 4.9.5): 706 mV at 27 C, falling 1.721 mV per
+   degree, read against the 3.3 V supply as reference */
+const VREF_UV: i64 = 3_300_000;
+const TS_27C_UV: i64 = 706_000;
+const TS_SLOPE_NV_PER_C: i64 = 1_721_000;
+
+/* Sensor reading to millidegrees Celsius */
+pub const fn millicelsius(reading: u16) -> i32 {
+    let uv = reading as i64 * VREF_UV / 4096;
+    (27_000 - (uv - TS_27C_UV) * 1_000_000 / TS_SLOPE_NV_PER_C) as i32
+}
+
+/* Millidegrees rounded to whole degrees, halves away from zero */
+pub const fn celsius(millicelsius: i32) -> i32 {
+    if millicelsius >= 0 {
+        (millicelsius + 500) / 1000
+    } else {
+        (millicelsius - 500) / 1000
+    }
+}
+
+/* A free-running result */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub value: u16,
+    pub error: bool,
+}
+
+/* Called from the ADC FIFO interrupt */
+pub type Handler = fn();
+
+static mut HANDLER: Option<Handler> = None;
+
+pub struct Adc {
+    _private: (),
+}
+
+/* Compile-time pin check: only GPIO26-29 have ADC inputs */
+struct PinCheck<const N: u32>;
+
+impl<const N: u32> PinCheck<N> {
+    const OK: () = assert!(N >= 26 && N <= 29, "only GPIO26-29 are ADC inputs");
+}
+
+/* ADC input number of an analog pin */
+pub fn input<const N: u32>(_pin: &Pin<N, Analog>) -> u8 {
+    let _: () = PinCheck::<N>::OK;
+    (N - 26) as u8
+}
+
+impl Adc {
+    /* Take the ADC out of reset and power it up, clk_adc must be running (48 MHz) */
+    pub fn new() -> Self {
+        reset(RESET_ADC);
+        unreset(RESET_ADC);
+        adc().cs.write(CS_EN.bits(true));
+        while !adc().cs.read_field(CS_READY) {}
+        Adc { _private: () }
+    }
+
+    /* Input converted next */
+    pub fn select(&mut self, input: u8) {
+        adc().cs.write_field_atomic(CS_AINSEL, input as u32);
+    }
+
+    /* One-shot conversion */
+    pub fn read(&mut self, input: u8) -> u16 {
+        self.select(input);
+        adc().cs.set(CS_START_ONCE.mask());
+        while !adc().cs.read_field(CS_READY) {}
+        adc().result.read() as u16
+    }
+
+    /* Average of several one-shot conversions (at least one) */
+    pub fn read_average(&mut self, input: u8, samples: u32) -> u16 {
+        let samples = samples.max(1);
+        let mut sum = 0;
+        for _ in 0..samples {
+            sum += self.read(input) as u32;
+        }
+        ((sum + samples / 2) / samples) as u16
+    }
+
+    /* Temperature sensor bias on or off. Give it a moment to settle before the first reading. */
+    pub fn set_temperature_sensor(&mut self, enabled: bool) {
+        adc().cs.write_field_atomic(CS_TS_EN, enabled);
+    }
+
+    /* Chip temperature in millidegrees Celsius, the sensor must be on.
+       The sensor is only accurate to a few degrees without calibration. */
+    pub fn read_temperature(&mut self) -> i32 {
+        millicelsius(self.read_average(TEMPERATURE_INPUT, TEMPERATURE_SAMPLES))
+    }
+
+    /* Inputs to cycle through while free-running, bit n = input n, 0 = just the selected one */
+    pub fn set_round_robin(&mut self, mask: u8) {
+        adc().cs.write_field_atomic(CS_RROBIN, mask as u32);
+    }
+
+    /* Free-running rate in samples per second (shared by all round-robin inputs).
+       The divider counts clk_adc cycles between conversion starts, anything
+       under 96 cycles just means back to back, over 65536 is the slowest it goes.
+       A rate of 0 is taken as 1, like spi's set_baud(). Returns the rate actually set. */
+    pub fn set_sample_rate(&mut self, rate: u32) -> u32 {
+        let clk = clocks::adc_hz();
+        let div256 = (clk as u64 * 256 / rate.max(1) as u64).min(MAX_DIV256) as u32;
+        if div256 < CYCLES_PER_SAMPLE * 256 {
+            adc().div.write(0);
+            return clk / CYCLES_PER_SAMPLE;
+        }
+        /* The period is INT + 1 + FRAC/256 cycles */
+        let div256 = div256 - 256;
+        adc().div.write(DIV_INT.bits(div256 >> 8) | DIV_FRAC.bits(div256 & 0xff));
+        (clk as u64 * 256 / (div256 + 256) as u64) as u32
+    }
+
+    /* FIFO on, interrupt level threshold (1-4), old samples and errors discarded */
+    pub fn fifo_enable(&mut self, threshold: u8) {
+        adc().fcs.write(
+            FCS_EN.bits(true) | FCS_ERR.bits(true) | FCS_THRESH.bits(threshold as u32),
+        );
+        self.fifo_drain();
+    }
+
+    pub fn fifo_level(&self) -> usize {
+        adc().fcs.read_field(FCS_LEVEL) as usize
+    }
+
+    pub fn fifo_pop(&mut self) -> Option<Sample> {
+        if adc().fcs.read_field(FCS_EMPTY) {
+            return None;
+        }
+        let fifo = adc().fifo.read();
+        Some(Sample {
+            value: FIFO_VAL.get(fifo) as u16,
+            error: FIFO_ERR.get(fifo),
+        })
+    }
+
+    /* Empty the FIFO and clear the sticky under/overflow flags */
+    pub fn fifo_drain(&mut self) {
+        while self.fifo_pop().is_some() {}
+        adc().fcs.modify(|fcs| fcs | FCS_UNDER.mask() | FCS_OVER.mask());
+    }
+
+    /* Samples were lost since the last drain */
+    pub fn fifo_overflowed(&self) -> bool {
+        adc().fcs.read_field(FCS_OVER)
+    }
+
+    pub fn start_free_running(&mut self) {
+        adc().cs.set(CS_START_MANY.mask());
+    }
+
+    /* Stops after the conversion in progress */
+    pub fn stop(&mut self) {
+        adc().cs.clear(CS_START_MANY.mask());
+        while !adc().cs.read_field(CS_READY) {}
+    }
+
+    /* DMA out of the FIFO (enable it first): halfword transfers give the
+       sample with the error flag in bit 15, one per conversion */
+    pub fn dma_target(&mut self) -> Target {
+        adc().fcs.set(FCS_DREQ_EN.mask());
+        Target {
+            addr: &adc().fifo as *const Ro as u32,
+            dreq: dma::DREQ_ADC,
+        }
+    }
+
+    /* FIFO interrupt handler, None to switch the interrupt off */
+    pub fn set_handler(&mut self, handler: Option<Handler>) {
+        free(|| unsafe { *addr_of_mut!(HANDLER) = handler });
+        if handler.is_some() {
+            adc().inte.set(INT_FIFO.mask());
+            nvic_enable(ADC_IRQ_FIFO);
+        } else {
+            adc().inte.clear(INT_FIFO.mask());
+            nvic_disable(ADC_IRQ_FIFO);
+        }
+    }
+}
+
+impl Default for Adc {
+    fn default() -> Self {
+        Adc::new()
+    }
+}
+
+/* Interrupt handler for ADC_IRQ_FIFO. The interrupt is the FIFO level itself,
+   it clears once the handler has drained below the threshold. */
+#[no_mangle]
+pub extern "C" fn adcIrqFifo() {
+    match unsafe { *addr_of_mut!(HANDLER) } {
+        Some(handler) => handler(),
+        None => adc().inte.clear(INT_FIFO.mask()),
+    }
+}
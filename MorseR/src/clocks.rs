@@ -0,0 +1,284 @@
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+
+/* Clock tree */
+/* At reset everything runs from the ring oscillator (ROSC, ~6 MHz and different
+   on every chip). init() is called by startup before main and sets up:
+   1. XOSC: the 12 MHz crystal
+   2. PLL_SYS: SYS_PLL below, 125 MHz by default
+   3. PLL_USB: 48 MHz
+   4. clk_ref = XOSC (12 MHz), clk_sys = PLL_SYS, clk_peri = clk_sys,
+      clk_usb = clk_adc = PLL_USB (48 MHz)
+   clk_ref and clk_sys have glitchless muxes and are switched on the fly, the
+   others are stopped while their source changes. Drivers get the resulting
+   frequencies from ref_hz(), sys_hz() and so on instead of hard-coding them. */
+
+/* Crystal on the Pico board */
+pub const XOSC_HZ: u32 = 12_000_000;
+
+/* Nominal ring oscillator frequency, what the clocks run at before init() */
+pub const ROSC_HZ: u32 = 6_000_000;
+
+/* PLL settings: VCO = XOSC / refdiv * fbdiv, output = VCO / (postdiv1 * postdiv2) */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PllConfig {
+    pub refdiv: u32,
+    pub fbdiv: u32,
+    pub postdiv1: u32,
+    pub postdiv2: u32,
+}
+
+impl PllConfig {
+    /* Limits from the datasheet, checked at compile time for constants */
+    pub const fn new(refdiv: u32, fbdiv: u32, postdiv1: u32, postdiv2: u32) -> Self {
+        assert!(refdiv >= 1 && refdiv <= 63);
+        assert!(XOSC_HZ / refdiv >= 5_000_000, "PLL reference below 5 MHz");
+        assert!(fbdiv >= 16 && fbdiv <= 320);
+        assert!(postdiv1 >= 1 && postdiv1 <= 7 && postdiv2 >= 1 && postdiv2 <= 7);
+        let vco_hz = XOSC_HZ / refdiv * fbdiv;
+        assert!(vco_hz >= 750_000_000 && vco_hz <= 1_600_000_000, "VCO out of range");
+        PllConfig { refdiv, fbdiv, postdiv1, postdiv2 }
+    }
+
+    pub const fn vco_hz(&self) -> u32 {
+        XOSC_HZ / self.refdiv * self.fbdiv
+    }
+
+    pub const fn out_hz(&self) -> u32 {
+        self.vco_hz() / (self.postdiv1 * self.postdiv2)
+    }
+}
+
+/* clk_sys: 1500 MHz / 6 / 2 = 125 MHz. Other examples:
+   PllConfig::new(1, 133, 6, 2)  133 MHz
+   PllConfig::new(1, 100, 6, 4)   50 MHz (less power) */
+pub const SYS_PLL: PllConfig = PllConfig::new(1, 125, 6, 2);
+
+/* clk_usb and clk_adc: 1200 MHz / 5 / 5 = 48 MHz, both need exactly this */
+pub const USB_PLL: PllConfig = PllConfig::new(1, 100, 5, 5);
+
+/* Clock generator registers */
+#[repr(C)]
+pub struct ClockHw {
+    pub ctrl: RwAlias,      /* Source select and enable */
+    pub div: RwAlias,       /* Divider, integer part from bit 8 */
+    pub selected: Ro,       /* One-hot glitchless source currently selected */
+}
+
+#[repr(C)]
+pub struct ClocksHw {
+    pub clk: [ClockHw; 10],         /* gpout0-3, ref, sys, peri, usb, adc, rtc */
+    pub sys_resus_ctrl: RwAlias,    /* Restart clk_sys if it stops */
+    pub sys_resus_status: Ro,
+}
+
+/* Crystal oscillator registers */
+#[repr(C)]
+pub struct XoscHw {
+    pub ctrl: RwAlias,      /* Frequency range and enable */
+    pub status: RwAlias,    /* Stable flag */
+    pub dormant: RwAlias,   /* Write a magic value to stop until a wake event */
+    pub startup: RwAlias,   /* Startup delay in units of 256 crystal cycles */
+}
+
+/* PLL registers (PLL_SYS and PLL_USB are identical) */
+#[repr(C)]
+pub struct PllHw {
+    pub cs: RwAlias,        /* Lock status and reference divider */
+    pub pwr: RwAlias,       /* Power downs, everything starts powered down */
+    pub fbdiv_int: RwAlias, /* Feedback divider */
+    pub prim: RwAlias,      /* Post dividers */
+}
+
+const CLOCKS_BASE: u32 = 0x40008000;
+const XOSC_BASE: u32 = 0x40024000;
+const PLL_SYS_BASE: u32 = 0x40028000;
+const PLL_USB_BASE: u32 = 0x4002c000;
+
+#[inline(always)]
+fn clocks() -> &'static ClocksHw {
+    block(CLOCKS_BASE)
+}
+
+#[inline(always)]
+fn xosc() -> &'static XoscHw {
+    block(XOSC_BASE)
+}
+
+/* Index into ClocksHw.clk */
+const CLK_REF: usize = 4;
+const CLK_SYS: usize = 5;
+const CLK_PERI: usize = 6;
+const CLK_USB: usize = 7;
+const CLK_ADC: usize = 8;
+
+/* CLK_x_CTRL fields */
+const CTRL_SRC: Field = Field::new(0, 2);       /* Glitchless mux (clk_ref, clk_sys only) */
+const CTRL_AUXSRC: Field = Field::new(5, 3);    /* Aux mux */
+const CTRL_ENABLE: Field<bool> = bit(11);       /* Aux-only clocks */
+
+/* Glitchless and aux sources */
+const REF_SRC_ROSC: u32 = 0;
+const REF_SRC_XOSC: u32 = 2;
+const SYS_SRC_CLK_REF: u32 = 0;
+const SYS_SRC_AUX: u32 = 1;
+const SYS_AUX_PLL_SYS: u32 = 0;
+const PERI_AUX_CLK_SYS: u32 = 0;
+const USB_AUX_PLL_USB: u32 = 0;
+const ADC_AUX_PLL_USB: u32 = 0;
+
+/* Divide by 1 (integer part in bits 8 and up) */
+const DIV_ONE: u32 = 1 << 8;
+
+/* XOSC fields and magic values */
+const XOSC_CTRL_FREQ_RANGE: Field = Field::new(0, 12);
+const XOSC_CTRL_ENABLE: Field = Field::new(12, 12);
+const XOSC_FREQ_1_15MHZ: u32 = 0xaa0;
+const XOSC_ENABLE: u32 = 0xfab;
+const XOSC_STATUS_STABLE: Field<bool> = bit(31);
+/* ~1 ms startup delay, in units of 256 crystal cycles */
+const XOSC_STARTUP_DELAY: u32 = (XOSC_HZ / 1000).div_ceil(256);
+
+/* PLL fields */
+const PLL_CS_REFDIV: Field = Field::new(0, 6);
+const PLL_CS_LOCK: Field<bool> = bit(31);
+const PLL_PWR_PD: Field<bool> = bit(0);
+const PLL_PWR_POSTDIVPD: Field<bool> = bit(3);
+const PLL_PWR_VCOPD: Field<bool> = bit(5);
+const PLL_PRIM_POSTDIV2: Field = Field::new(12, 3);
+const PLL_PRIM_POSTDIV1: Field = Field::new(16, 3);
+
+/* Resulting frequencies, ROSC until init() has run */
+struct Frequencies {
+    ref_hz: u32,
+    sys_hz: u32,
+    peri_hz: u32,
+    usb_hz: u32,
+    adc_hz: u32,
+}
+
+static mut FREQUENCIES: Frequencies = Frequencies {
+    ref_hz: ROSC_HZ,
+    sys_hz: ROSC_HZ,
+    peri_hz: 0,
+    usb_hz: 0,
+    adc_hz: 0,
+};
+
+#[inline(always)]
+fn frequencies() -> &'static Frequencies {
+    unsafe { &*addr_of!(FREQUENCIES) }
+}
+
+/* clk_ref: watchdog tick (timer), SysTick reference */
+pub fn ref_hz() -> u32 {
+    frequencies().ref_hz
+}
+
+/* clk_sys: processor, bus, PWM, PIO */
+pub fn sys_hz() -> u32 {
+    frequencies().sys_hz
+}
+
+/* clk_peri: UART and SPI, 0 while stopped */
+pub fn peri_hz() -> u32 {
+    frequencies().peri_hz
+}
+
+pub fn usb_hz() -> u32 {
+    frequencies().usb_hz
+}
+
+pub fn adc_hz() -> u32 {
+    frequencies().adc_hz
+}
+
+/* Start the crystal and wait until it is stable */
+fn xosc_start() {
+    xosc().ctrl.write(XOSC_CTRL_FREQ_RANGE.bits(XOSC_FREQ_1_15MHZ));
+    xosc().startup.write(XOSC_STARTUP_DELAY);
+    xosc().ctrl.set(XOSC_CTRL_ENABLE.bits(XOSC_ENABLE));
+    while !xosc().status.read_field(XOSC_STATUS_STABLE) {}
+}
+
+/* Bring up a PLL from a clean reset:
+   1. Reference divider and feedback divider
+   2. Power up the PLL and VCO, wait for lock
+   3. Post dividers, then power them up */
+fn pll_start(pll: &PllHw, reset_bit: u32, config: &PllConfig) {
+    reset(reset_bit);
+    unreset(reset_bit);
+    pll.cs.write(PLL_CS_REFDIV.bits(config.refdiv));
+    pll.fbdiv_int.write(config.fbdiv);
+    pll.pwr.clear(PLL_PWR_PD.mask() | PLL_PWR_VCOPD.mask());
+    while !pll.cs.read_field(PLL_CS_LOCK) {}
+    pll.prim.write(PLL_PRIM_POSTDIV1.bits(config.postdiv1) | PLL_PRIM_POSTDIV2.bits(config.postdiv2));
+    pll.pwr.clear(PLL_PWR_POSTDIVPD.mask());
+}
+
+/* Switch a glitchless mux and wait until the new source is running */
+fn select_glitchless(clock: &ClockHw, src: u32) {
+    clock.ctrl.write_field(CTRL_SRC, src);
+    while clock.selected.read() != 1 << src {}
+}
+
+/* Change the source of an aux-only clock. There is no glitchless mux, so:
+   1. Stop the clock
+   2. Wait for the stop to get through the old source, it takes up to 3 of its
+      cycles (ROSC at worst, ~60 cycles of a 125 MHz clk_sys)
+   3. Select the new source, divide by 1 and start it again */
+fn select_aux(clock: &ClockHw, auxsrc: u32) {
+    clock.ctrl.clear(CTRL_ENABLE.mask());
+    delay(100);
+    clock.ctrl.write_field(CTRL_AUXSRC, auxsrc);
+    clock.div.write(DIV_ONE);
+    clock.ctrl.set(CTRL_ENABLE.mask());
+}
+
+/* Bring up the clock tree, see the top of this file */
+pub fn init() {
+    let clk = &clocks().clk;
+
+    /* No resuscitation while clk_sys is moved around */
+    clocks().sys_resus_ctrl.write(0);
+
+    xosc_start();
+
+    /* Off the aux muxes before the PLLs under them are reset */
+    select_glitchless(&clk[CLK_SYS], SYS_SRC_CLK_REF);
+    select_glitchless(&clk[CLK_REF], REF_SRC_ROSC);
+
+    pll_start(block(PLL_SYS_BASE), RESET_PLL_SYS, &SYS_PLL);
+    pll_start(block(PLL_USB_BASE), RESET_PLL_USB, &USB_PLL);
+
+    /* clk_ref: crystal, undivided */
+    clk[CLK_REF].div.write(DIV_ONE);
+    select_glitchless(&clk[CLK_REF], REF_SRC_XOSC);
+
+    /* clk_sys: PLL_SYS through the aux mux */
+    clk[CLK_SYS].div.write(DIV_ONE);
+    clk[CLK_SYS].ctrl.write_field(CTRL_AUXSRC, SYS_AUX_PLL_SYS);
+    select_glitchless(&clk[CLK_SYS], SYS_SRC_AUX);
+
+    select_aux(&clk[CLK_PERI], PERI_AUX_CLK_SYS);
+    select_aux(&clk[CLK_USB], USB_AUX_PLL_USB);
+    select_aux(&clk[CLK_ADC], ADC_AUX_PLL_USB);
+
+    unsafe {
+        *addr_of_mut!(FREQUENCIES) = Frequencies {
+            ref_hz: XOSC_HZ,
+            sys_hz: SYS_PLL.out_hz(),
+            peri_hz: SYS_PLL.out_hz(),
+            usb_hz: USB_PLL.out_hz(),
+            adc_hz: USB_PLL.out_hz(),
+        };
+    }
+}
+
+/* Called by startup before main */
+#[no_mangle]
+pub extern "C" fn clocksInit() {
+    init();
+}
@@ -0,0 +1,478 @@
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+
+/* DMA controller */
+/* Twelve channels, each moving `count` bytes, halfwords or words from a read
+   address to a write address:
+   1. Channels are claimed, claim() hands out the lowest free one. Drivers that
+      can be fed by DMA give out a Target: the data register to read or write
+      and the DREQ that paces it (Uart::dma_tx(), Adc::dma_target(), Pwm::dma_target() ...)
+   2. send() moves a buffer to a peripheral, receive() fills one from it, copy()
+      is memory to memory as fast as the bus allows. The buffers are 'static:
+      the transfer goes on after the call returns. start() takes a full Config
+      and raw addresses for everything else.
+   3. A transfer goes at the rate of its DREQ. Without one it can be paced by one
+      of the four pacing timers: clk_sys * X / Y requests per second.
+   4. Ring: the read or write address wraps on a 2^n byte boundary, the buffer
+      must be aligned to it. Ring on read with a huge count replays a buffer forever.
+   5. Chaining: a channel triggers chain_to when it finishes, so a second
+      channel can reload the first one's addresses for endless double buffering.
+   6. Completion calls the handler set with set_handler(), from DMA_IRQ_0 or
+      DMA_IRQ_1, unless the config has `quiet` set. */
+
+/* One channel. Each register has four aliases in different orders, writing
+   the last register of an alias starts the channel, only alias 0 and 1 are used. */
+#[repr(C)]
+pub struct ChannelHw {
+    pub read_addr: RwAlias,
+    pub write_addr: RwAlias,
+    pub trans_count: RwAlias,           /* Reads back the transfers left */
+    pub ctrl_trig: RwAlias,             /* Control, writing it starts the channel */
+    pub al1_ctrl: RwAlias,              /* Control without the trigger */
+    pub al1_read_addr: RwAlias,
+    pub al1_write_addr: RwAlias,
+    pub al1_trans_count_trig: RwAlias,  /* Count, writing it starts the channel */
+    _aliases: [u32; 8],
+}
+
+#[repr(C)]
+pub struct DmaHw {
+    pub ch: [ChannelHw; 12],
+    _reserved0: [u32; 64],
+    pub intr: RwAlias,          /* Raw completion interrupts, write 1 to clear */
+    pub inte0: RwAlias,         /* Channels routed to DMA_IRQ_0 */
+    pub intf0: RwAlias,
+    pub ints0: RwAlias,         /* Masked status, write 1 to clear */
+    _reserved1: u32,
+    pub inte1: RwAlias,         /* Channels routed to DMA_IRQ_1 */
+    pub intf1: RwAlias,
+    pub ints1: RwAlias,
+    pub timer: [RwAlias; 4],    /* Pacing timers: X in bits 16-31, Y in bits 0-15 */
+    pub multi_chan_trigger: RwAlias,
+    pub sniff_ctrl: RwAlias,
+    pub sniff_data: RwAlias,
+    _reserved2: u32,
+    pub fifo_levels: Ro,
+    pub chan_abort: RwAlias,    /* Write 1 to abort, reads 1 until it has stopped */
+    pub n_channels: Ro,
+}
+
+const DMA_BASE: u32 = 0x50000000;
+const RESET_DMA: u32 = 2;
+pub const DMA_IRQ: [u32; 2] = [11, 12];
+
+#[inline(always)]
+fn dma() -> &'static DmaHw {
+    block(DMA_BASE)
+}
+
+/* CTRL fields */
+const CTRL_EN: Field<bool> = bit(0);
+const CTRL_HIGH_PRIORITY: Field<bool> = bit(1);
+const CTRL_DATA_SIZE: Field = Field::new(2, 2);
+const CTRL_INCR_READ: Field<bool> = bit(4);
+const CTRL_INCR_WRITE: Field<bool> = bit(5);
+const CTRL_RING_SIZE: Field = Field::new(6, 4);
+const CTRL_RING_SEL: Field<bool> = bit(10);
+const CTRL_CHAIN_TO: Field = Field::new(11, 4);
+const CTRL_TREQ_SEL: Field = Field::new(15, 6);
+const CTRL_IRQ_QUIET: Field<bool> = bit(21);
+const CTRL_BSWAP: Field<bool> = bit(22);
+const CTRL_BUSY: Field<bool> = bit(24);
+const CTRL_WRITE_ERROR: Field<bool> = bit(29);
+const CTRL_READ_ERROR: Field<bool> = bit(30);
+const CTRL_AHB_ERROR: Field<bool> = bit(31);
+
+/* TIMER fields */
+const TIMER_Y: Field = Field::new(0, 16);
+const TIMER_X: Field = Field::new(16, 16);
+
+pub const CHANNELS: usize = 12;
+pub const PACING_TIMERS: usize = 4;
+
+/* Transfer request sources (TREQ_SEL) */
+pub const DREQ_PIO_TX: [u8; 2] = [0, 8];    /* + state machine */
+pub const DREQ_PIO_RX: [u8; 2] = [4, 12];
+pub const DREQ_SPI_TX: [u8; 2] = [16, 18];
+pub const DREQ_SPI_RX: [u8; 2] = [17, 19];
+pub const DREQ_UART_TX: [u8; 2] = [20, 22];
+pub const DREQ_UART_RX: [u8; 2] = [21, 23];
+pub const DREQ_PWM_WRAP: u8 = 24;           /* + slice */
+pub const DREQ_I2C_TX: [u8; 2] = [32, 34];
+pub const DREQ_I2C_RX: [u8; 2] = [33, 35];
+pub const DREQ_ADC: u8 = 36;
+pub const DREQ_TIMER: u8 = 59;              /* + pacing timer */
+pub const DREQ_PERMANENT: u8 = 63;          /* Unpaced, as fast as possible */
+
+/* Transfer size */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    Byte = 0,
+    HalfWord = 1,
+    Word = 2,
+}
+
+/* Buffer element types and their transfer size */
+pub trait Word: Copy {
+    const SIZE: Size;
+}
+
+impl Word for u8 {
+    const SIZE: Size = Size::Byte;
+}
+
+impl Word for u16 {
+    const SIZE: Size = Size::HalfWord;
+}
+
+impl Word for u32 {
+    const SIZE: Size = Size::Word;
+}
+
+/* Address wrapping, the number is the ring size in bits (1-15, 2^n bytes) */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ring {
+    None,
+    Read(u8),
+    Write(u8),
+}
+
+/* A peripheral register a channel can feed or drain, and the DREQ that paces it */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub addr: u32,
+    pub dreq: u8,
+}
+
+/* Channel settings */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub size: Size,
+    pub read_increment: bool,
+    pub write_increment: bool,
+    pub dreq: u8,                   /* DREQ_*, DREQ_PERMANENT for unpaced */
+    pub ring: Ring,
+    pub chain_to: Option<usize>,    /* Channel to start when this one finishes */
+    pub high_priority: bool,        /* Served first in each round of the bus scheduler */
+    pub quiet: bool,                /* No interrupt on completion */
+    pub byte_swap: bool,            /* Reverse the bytes of each halfword or word */
+}
+
+impl Config {
+    /* Memory to memory, unpaced */
+    pub const fn new(size: Size) -> Self {
+        Config {
+            size,
+            read_increment: true,
+            write_increment: true,
+            dreq: DREQ_PERMANENT,
+            ring: Ring::None,
+            chain_to: None,
+            high_priority: false,
+            quiet: false,
+            byte_swap: false,
+        }
+    }
+
+    /* Buffer to a peripheral data register, paced by its DREQ */
+    pub const fn to_peripheral(size: Size, dreq: u8) -> Self {
+        let mut config = Config::new(size);
+        config.write_increment = false;
+        config.dreq = dreq;
+        config
+    }
+
+    /* Peripheral data register to a buffer, paced by its DREQ */
+    pub const fn from_peripheral(size: Size, dreq: u8) -> Self {
+        let mut config = Config::new(size);
+        config.read_increment = false;
+        config.dreq = dreq;
+        config
+    }
+
+    /* CTRL value, the channel's own number stands for "no chaining" */
+    fn ctrl(&self, channel: usize) -> u32 {
+        let (ring_size, ring_write) = match self.ring {
+            Ring::None => (0, false),
+            Ring::Read(bits) => (bits as u32, false),
+            Ring::Write(bits) => (bits as u32, true),
+        };
+        CTRL_EN.bits(true)
+            | CTRL_HIGH_PRIORITY.bits(self.high_priority)
+            | CTRL_DATA_SIZE.bits(self.size as u32)
+            | CTRL_INCR_READ.bits(self.read_increment)
+            | CTRL_INCR_WRITE.bits(self.write_increment)
+            | CTRL_RING_SIZE.bits(ring_size)
+            | CTRL_RING_SEL.bits(ring_write)
+            | CTRL_CHAIN_TO.bits(self.chain_to.unwrap_or(channel) as u32)
+            | CTRL_TREQ_SEL.bits(self.dreq as u32)
+            | CTRL_IRQ_QUIET.bits(self.quiet)
+            | CTRL_BSWAP.bits(self.byte_swap)
+    }
+}
+
+/* Called from the DMA interrupt with the channel that finished */
+pub type Handler = fn(channel: usize);
+
+static mut HANDLERS: [Option<Handler>; CHANNELS] = [None; CHANNELS];
+
+/* Claimed channels and pacing timers, one bit each */
+static mut CHANNELS_CLAIMED: u32 = 0;
+static mut TIMERS_CLAIMED: u32 = 0;
+
+/* Claim a bit in mask: the lowest free one, or `index` if given and free */
+fn claim_bit(claimed: *mut u32, count: usize, index: Option<usize>) -> Option<usize> {
+    free(|| unsafe {
+        let free_bit = match index {
+            Some(index) => Some(index).filter(|&index| index < count && *claimed & (1 << index) == 0),
+            None => (0..count).find(|&index| *claimed & (1 << index) == 0),
+        };
+        if let Some(index) = free_bit {
+            *claimed |= 1 << index;
+        }
+        free_bit
+    })
+}
+
+fn release_bit(claimed: *mut u32, index: usize) {
+    free(|| unsafe { *claimed &= !(1 << index) });
+}
+
+pub struct Channel {
+    index: usize,
+}
+
+/* Lowest free channel, None when all twelve are in use */
+pub fn claim() -> Option<Channel> {
+    claim_channel_index(None)
+}
+
+/* A particular channel, None if it is taken */
+pub fn claim_channel(index: usize) -> Option<Channel> {
+    claim_channel_index(Some(index))
+}
+
+fn claim_channel_index(index: Option<usize>) -> Option<Channel> {
+    let index = claim_bit(addr_of_mut!(CHANNELS_CLAIMED), CHANNELS, index)?;
+    unreset(RESET_DMA);
+    Some(Channel { index })
+}
+
+impl Channel {
+    #[inline(always)]
+    fn hw(&self) -> &'static ChannelHw {
+        &dma().ch[self.index]
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /* Settings without starting (for a channel another one chains to) */
+    pub fn configure(&mut self, config: &Config) {
+        self.hw().al1_ctrl.write(config.ctrl(self.index));
+    }
+
+    /// Addresses and count without starting (for a channel another one chains to).
+    ///
+    /// # Safety
+    /// read_addr and write_addr must be valid for count transfers of the
+    /// configured size and stay valid until the transfer has finished: the
+    /// channel writes to write_addr whatever is there, once it is triggered.
+    pub unsafe fn set_transfer(&mut self, read_addr: u32, write_addr: u32, count: u32) {
+        self.hw().read_addr.write(read_addr);
+        self.hw().write_addr.write(write_addr);
+        self.hw().trans_count.write(count);
+    }
+
+    /// Configure and start a transfer of count elements of config.size.
+    ///
+    /// # Safety
+    /// read_addr and write_addr must be valid for count transfers of
+    /// config.size and stay valid (not freed, not reused) until the transfer
+    /// has finished or been aborted.
+    pub unsafe fn start(&mut self, config: &Config, read_addr: u32, write_addr: u32, count: u32) {
+        self.configure(config);
+        self.hw().read_addr.write(read_addr);
+        self.hw().write_addr.write(write_addr);
+        self.hw().al1_trans_count_trig.write(count);
+    }
+
+    /* Start with the addresses and count already set */
+    pub fn trigger(&mut self) {
+        dma().multi_chan_trigger.write(1 << self.index);
+    }
+
+    /* Buffer to a peripheral, paced by its DREQ */
+    pub fn send<W: Word>(&mut self, data: &'static [W], target: Target) {
+        let config = Config::to_peripheral(W::SIZE, target.dreq);
+        unsafe { self.start(&config, data.as_ptr() as u32, target.addr, data.len() as u32) };
+    }
+
+    /* Fill a buffer from a peripheral, paced by its DREQ */
+    pub fn receive<W: Word>(&mut self, target: Target, buffer: &'static mut [W]) {
+        let config = Config::from_peripheral(W::SIZE, target.dreq);
+        let count = buffer.len() as u32;
+        unsafe { self.start(&config, target.addr, buffer.as_mut_ptr() as u32, count) };
+    }
+
+    /* Memory to memory, unpaced */
+    pub fn copy<W: Word>(&mut self, from: &'static [W], to: &'static mut [W]) {
+        let count = from.len().min(to.len()) as u32;
+        let config = Config::new(W::SIZE);
+        unsafe { self.start(&config, from.as_ptr() as u32, to.as_mut_ptr() as u32, count) };
+    }
+
+    /* Still transferring (or waiting for its DREQ) */
+    #[inline(always)]
+    pub fn is_busy(&self) -> bool {
+        self.hw().ctrl_trig.read_field(CTRL_BUSY)
+    }
+
+    pub fn wait(&self) {
+        while self.is_busy() {}
+    }
+
+    /* Transfers still to go */
+    pub fn remaining(&self) -> u32 {
+        self.hw().trans_count.read()
+    }
+
+    /* Where the channel reads and writes next */
+    pub fn read_addr(&self) -> u32 {
+        self.hw().read_addr.read()
+    }
+
+    pub fn write_addr(&self) -> u32 {
+        self.hw().write_addr.read()
+    }
+
+    /* Stop now. The channel may still finish the transfer in flight. A channel
+       chained to this one could be started by the abort, abort it as well. */
+    pub fn abort(&mut self) {
+        let mask = 1 << self.index;
+        /* Keep the abort from raising a completion interrupt */
+        let (inte0, inte1) = (dma().inte0.read() & mask, dma().inte1.read() & mask);
+        dma().inte0.clear(mask);
+        dma().inte1.clear(mask);
+        dma().chan_abort.write(mask);
+        while dma().chan_abort.read() & mask != 0 {}
+        dma().intr.write(mask);
+        dma().inte0.set(inte0);
+        dma().inte1.set(inte1);
+    }
+
+    /* A bus error stopped the transfer. READ_ERROR and WRITE_ERROR are write-1-to-clear,
+       so they are cleared by writing them through AL1_CTRL's SET alias: a write to
+       CTRL_TRIG would start the channel again. AHB_ERROR is their OR and clears with them. */
+    pub fn error(&mut self) -> bool {
+        let ctrl = self.hw().ctrl_trig.read();
+        if !CTRL_AHB_ERROR.get(ctrl) {
+            return false;
+        }
+        self.hw().al1_ctrl.set(CTRL_READ_ERROR.mask() | CTRL_WRITE_ERROR.mask());
+        true
+    }
+
+    /* Completion handler on DMA_IRQ_<irq> (0 or 1), None to stop calling one */
+    pub fn set_handler(&mut self, irq: usize, handler: Option<Handler>) {
+        let mask = 1 << self.index;
+        free(|| unsafe {
+            (*addr_of_mut!(HANDLERS))[self.index] = handler;
+            dma().inte0.clear(mask);
+            dma().inte1.clear(mask);
+            dma().intr.write(mask);
+            if handler.is_some() {
+                inte(irq).set(mask);
+            }
+        });
+        if handler.is_some() {
+            nvic_enable(DMA_IRQ[irq]);
+        }
+    }
+
+    /* Stop the channel and give it back */
+    pub fn release(mut self) {
+        self.abort();
+        self.set_handler(0, None);
+        self.hw().al1_ctrl.write(0);
+        release_bit(addr_of_mut!(CHANNELS_CLAIMED), self.index);
+    }
+}
+
+#[inline(always)]
+fn inte(irq: usize) -> &'static RwAlias {
+    if irq == 0 {
+        &dma().inte0
+    } else {
+        &dma().inte1
+    }
+}
+
+/* Pacing timer, a DREQ at clk_sys * X / Y */
+pub struct PacingTimer {
+    index: usize,
+}
+
+/* Lowest free pacing timer */
+pub fn claim_timer() -> Option<PacingTimer> {
+    let index = claim_bit(addr_of_mut!(TIMERS_CLAIMED), PACING_TIMERS, None)?;
+    unreset(RESET_DMA);
+    Some(PacingTimer { index })
+}
+
+impl PacingTimer {
+    /* DREQ to put in a Config */
+    pub fn dreq(&self) -> u8 {
+        DREQ_TIMER + self.index as u8
+    }
+
+    /* Rate clk_sys * x / y, x <= y */
+    pub fn set_fraction(&mut self, x: u16, y: u16) {
+        dma().timer[self.index].write(TIMER_X.bits(x as u32) | TIMER_Y.bits(y as u32));
+    }
+
+    /* Requests per second: Y = clk_sys / rate with X = 1, so anything from
+       clk_sys / 65535 (1.9 kHz at 125 MHz) up. Returns the rate actually set. */
+    pub fn set_rate(&mut self, rate: u32) -> u32 {
+        let clk = clocks::sys_hz();
+        let y = ((clk + rate.max(1) / 2) / rate.max(1)).clamp(1, 0xffff);
+        self.set_fraction(1, y as u16);
+        clk / y
+    }
+
+    pub fn release(self) {
+        dma().timer[self.index].write(0);
+        release_bit(addr_of_mut!(TIMERS_CLAIMED), self.index);
+    }
+}
+
+/* Interrupt: acknowledge the channels that finished and call their handlers */
+fn dma_irq(irq: usize) {
+    let ints = if irq == 0 { &dma().ints0 } else { &dma().ints1 };
+    let status = ints.read();
+    ints.write(status);
+    for channel in 0..CHANNELS {
+        if status & (1 << channel) == 0 {
+            continue;
+        }
+        match unsafe { (*addr_of_mut!(HANDLERS))[channel] } {
+            Some(handler) => handler(channel),
+            None => inte(irq).clear(1 << channel),
+        }
+    }
+}
+
+/* Interrupt handlers for DMA_IRQ_0 and DMA_IRQ_1 */
+#[no_mangle]
+pub extern "C" fn dmaIrq0() {
+    dma_irq(0);
+}
+
+#[no_mangle]
+pub extern "C" fn dmaIrq1() {
+    dma_irq(1);
+}
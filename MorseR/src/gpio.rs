@@ -0,0 +1,264 @@
+use core::marker::PhantomData;
+
+use crate::hw::*;
+use crate::reg::RwAlias;
+
+/* Typestate GPIO pins */
+/* Every pin is a Pin<N, Mode> value, N is the GPIO number and Mode what it
+   is configured as, so the compiler checks how a pin is used:
+   1. Pins::take() hands out all 30 pins once, each one is owned by a single place
+   2. into_input(), into_output()/into_output_high(), into_function() and
+      into_disabled() consume the pin and return it in the new mode
+   3. is_high()/is_low() only exist on inputs, set_high()/set_low()/toggle() only
+      on outputs, so a disabled or wrong-direction pin can't be read or driven
+   The pad (pulls, drive strength, slew rate, Schmitt trigger) can be set in any mode,
+   its input buffer and output driver only on disabled and analog pins: turning
+   them off under an input or output would leave the pin reading or driving nothing. */
+
+/* Pin modes */
+pub struct Disabled;    /* Function NULL, nothing drives the pin (reset state) */
+pub struct Input;       /* SIO input, input buffer on, output driver off */
+pub struct Output;      /* SIO output, driven by set_high()/set_low() */
+pub struct Alternate;   /* Owned by a peripheral (PWM, UART, I2C, SPI, PIO ...) */
+pub struct Analog;      /* ADC input (GPIO26-29), digital input and output off */
+
+/* Pad pull resistors */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+    BusKeep,    /* Both enabled: weakly holds the last driven level */
+}
+
+/* Pad output drive strength */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drive {
+    Ma2 = 0,
+    Ma4 = 1,
+    Ma8 = 2,
+    Ma12 = 3,
+}
+
+/* Pad output slew rate */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slew {
+    Slow,
+    Fast,
+}
+
+pub struct Pin<const N: u32, MODE> {
+    mode: PhantomData<MODE>,
+}
+
+impl<const N: u32, MODE> Pin<N, MODE> {
+    /* Only Pins::take() creates pins */
+    const fn new() -> Self {
+        Pin { mode: PhantomData }
+    }
+
+    /* GPIO number */
+    pub const fn id(&self) -> u32 {
+        N
+    }
+
+    #[inline(always)]
+    fn pad(&self) -> &'static RwAlias {
+        &pads().gpio[N as usize]
+    }
+
+    #[inline(always)]
+    fn set_function(&self, function: Function) {
+        io().gpio[N as usize].ctrl.write(GPIO_CTRL_FUNCSEL.bits(function));
+    }
+
+    /* Pull resistors */
+    pub fn set_pull(&mut self, pull: Pull) {
+        let (up, down) = match pull {
+            Pull::None => (false, false),
+            Pull::Up => (true, false),
+            Pull::Down => (false, true),
+            Pull::BusKeep => (true, true),
+        };
+        self.pad().write_field_atomic(PADS_PUE, up);
+        self.pad().write_field_atomic(PADS_PDE, down);
+    }
+
+    pub fn set_drive(&mut self, drive: Drive) {
+        self.pad().write_field_atomic(PADS_DRIVE, drive as u32);
+    }
+
+    pub fn set_slew(&mut self, slew: Slew) {
+        self.pad().write_field_atomic(PADS_SLEWFAST, slew == Slew::Fast);
+    }
+
+    pub fn set_schmitt(&mut self, enabled: bool) {
+        self.pad().write_field_atomic(PADS_SCHMITT, enabled);
+    }
+
+    /* Pad input buffer and output driver, the into_*() calls set these to suit
+       the mode. Only pins nothing reads or drives get the public setters. */
+    fn pad_input_enable(&mut self, enabled: bool) {
+        self.pad().write_field_atomic(PADS_IE, enabled);
+    }
+
+    fn pad_output_disable(&mut self, disabled: bool) {
+        self.pad().write_field_atomic(PADS_OD, disabled);
+    }
+
+    /* SIO input: input buffer on, output driver disabled in the pad and in SIO */
+    pub fn into_input(self) -> Pin<N, Input> {
+        sio().gpio_oe_clr.write(1u32 << N);
+        self.pad().set(PADS_IE.mask() | PADS_OD.mask());
+        self.set_function(Function::Sio);
+        Pin::new()
+    }
+
+    /* SIO output, starts low. The input buffer is switched off. */
+    pub fn into_output(self) -> Pin<N, Output> {
+        sio().gpio_out_clr.write(1u32 << N);
+        self.enable_output()
+    }
+
+    /* SIO output that starts high: the level is set before the driver is
+       enabled, so an active-low line (a chip select) never glitches low */
+    pub fn into_output_high(self) -> Pin<N, Output> {
+        sio().gpio_out_set.write(1u32 << N);
+        self.enable_output()
+    }
+
+    fn enable_output(self) -> Pin<N, Output> {
+        sio().gpio_oe_set.write(1u32 << N);
+        self.pad().clear(PADS_IE.mask() | PADS_OD.mask());
+        self.set_function(Function::Sio);
+        Pin::new()
+    }
+
+    /* Hand the pin to a peripheral, input and output both enabled in the pad
+       (the peripheral decides the direction) */
+    pub fn into_function(self, function: Function) -> Pin<N, Alternate> {
+        self.pad().set(PADS_IE.mask());
+        self.pad().clear(PADS_OD.mask());
+        self.set_function(function);
+        Pin::new()
+    }
+
+    /* ADC input: the digital input buffer would leak current at mid-rail
+       voltages, so it is switched off along with the output. Pulls are kept,
+       a resistor ladder needs the pull-up. */
+    pub fn into_analog(self) -> Pin<N, Analog> {
+        self.set_function(Function::Null);
+        sio().gpio_oe_clr.write(1u32 << N);
+        self.pad().clear(PADS_IE.mask());
+        self.pad().set(PADS_OD.mask());
+        Pin::new()
+    }
+
+    /* Back to the reset state: no function, buffers off */
+    pub fn into_disabled(self) -> Pin<N, Disabled> {
+        self.set_function(Function::Null);
+        sio().gpio_oe_clr.write(1u32 << N);
+        self.pad().clear(PADS_IE.mask());
+        self.pad().set(PADS_OD.mask());
+        Pin::new()
+    }
+}
+
+impl<const N: u32> Pin<N, Disabled> {
+    pub fn set_input_enable(&mut self, enabled: bool) {
+        self.pad_input_enable(enabled);
+    }
+
+    pub fn set_output_disable(&mut self, disabled: bool) {
+        self.pad_output_disable(disabled);
+    }
+}
+
+impl<const N: u32> Pin<N, Analog> {
+    pub fn set_input_enable(&mut self, enabled: bool) {
+        self.pad_input_enable(enabled);
+    }
+
+    pub fn set_output_disable(&mut self, disabled: bool) {
+        self.pad_output_disable(disabled);
+    }
+}
+
+impl<const N: u32> Pin<N, Input> {
+    #[inline(always)]
+    pub fn is_high(&self) -> bool {
+        sio().gpio_in.read() & (1u32 << N) != 0
+    }
+
+    #[inline(always)]
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl<const N: u32> Pin<N, Output> {
+    #[inline(always)]
+    pub fn set_high(&mut self) {
+        sio().gpio_out_set.write(1u32 << N);
+    }
+
+    #[inline(always)]
+    pub fn set_low(&mut self) {
+        sio().gpio_out_clr.write(1u32 << N);
+    }
+
+    #[inline(always)]
+    pub fn toggle(&mut self) {
+        sio().gpio_out_xor.write(1u32 << N);
+    }
+
+    /* Level we are driving (the output register, not the pad) */
+    #[inline(always)]
+    pub fn is_set_high(&self) -> bool {
+        sio().gpio_out.read() & (1u32 << N) != 0
+    }
+}
+
+/* All user GPIOs, one field per pin */
+macro_rules! pins {
+    ($($name:ident: $n:literal),* $(,)?) => {
+        pub struct Pins {
+            $(pub $name: Pin<$n, Disabled>,)*
+        }
+
+        impl Pins {
+            const fn new() -> Self {
+                Pins {
+                    $($name: Pin::new(),)*
+                }
+            }
+        }
+    };
+}
+
+pins!(
+    gpio0: 0, gpio1: 1, gpio2: 2, gpio3: 3, gpio4: 4, gpio5: 5, gpio6: 6, gpio7: 7,
+    gpio8: 8, gpio9: 9, gpio10: 10, gpio11: 11, gpio12: 12, gpio13: 13, gpio14: 14, gpio15: 15,
+    gpio16: 16, gpio17: 17, gpio18: 18, gpio19: 19, gpio20: 20, gpio21: 21, gpio22: 22, gpio23: 23,
+    gpio24: 24, gpio25: 25, gpio26: 26, gpio27: 27, gpio28: 28, gpio29: 29,
+);
+
+static mut TAKEN: bool = false;
+
+impl Pins {
+    /* Take IO Bank 0 and the pads out of reset and hand out the pins.
+       Only the first call gets them, later calls return None. */
+    pub fn take() -> Option<Pins> {
+        let taken = free(|| unsafe {
+            let taken = TAKEN;
+            TAKEN = true;
+            taken
+        });
+        if taken {
+            return None;
+        }
+        unreset(RESET_IO_BANK0);
+        unreset(RESET_PADS_BANK0);
+        Some(Pins::new())
+    }
+}
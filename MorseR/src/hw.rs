@@ -0,0 +1,307 @@
+use core::arch::asm;
+
+use crate::reg::{bit, block, Field, FieldValue, Ro, Rw, RwAlias, Wo};
+
+/* Hardware Register Structures */
+/* Shared by the transmitter and receiver images */
+/* SIO (Single-cycle IO) registers for fast GPIO access */
+/* SIO has its own set/clear/xor registers instead of the atomic aliases */
+#[repr(C)]
+pub struct SioHw {
+    pub cpuid: Ro,          /* Processor core identifier */
+    pub gpio_in: Ro,        /* Input values for GPIO 0-29 */
+    pub gpio_hi_in: Ro,     /* Input values for GPIO 30-35 */
+    pub unused: u32,        /* Reserved */
+    pub gpio_out: Rw,       /* GPIO output values */
+    pub gpio_out_set: Wo,   /* Set GPIO output bits */
+    pub gpio_out_clr: Wo,   /* Clear GPIO output bits */
+    pub gpio_out_xor: Wo,   /* XOR GPIO output bits */
+    pub gpio_oe: Rw,        /* GPIO output enable */
+    pub gpio_oe_set: Wo,    /* Set GPIO output enable bits */
+    pub gpio_oe_clr: Wo,    /* Clear GPIO output enable bits */
+    pub gpio_oe_xor: Wo,    /* XOR GPIO output enable bits */
+}
+
+/* IO Bank 0 registers for GPIO configuration and interrupts */
+/* Each structure has two 32-bit values: status and ctrl */
+#[repr(C)]
+pub struct IoBank0Hw {
+    pub gpio: [GpioCtrl; 30],       /* We repeated this 30 times for each GPIO */
+    pub intr: [RwAlias; 4],         /* Raw interrupts, write 1 to clear an edge */
+    pub proc0_inte: [RwAlias; 4],   /* Interrupt enable for processor 0 */
+    pub proc0_intf: [RwAlias; 4],   /* Interrupt force for processor 0 */
+    pub proc0_ints: [Ro; 4],        /* Interrupt status for processor 0 */
+    pub proc1_inte: [RwAlias; 4],   /* Same again for processor 1 */
+    pub proc1_intf: [RwAlias; 4],
+    pub proc1_ints: [Ro; 4],
+    pub dormant_wake_inte: [RwAlias; 4],  /* Events that wake the chip from DORMANT */
+    pub dormant_wake_intf: [RwAlias; 4],
+    pub dormant_wake_ints: [Ro; 4],
+}
+
+#[repr(C)]
+pub struct GpioCtrl {
+    pub status: Ro,         /* GPIO status */
+    pub ctrl: RwAlias,      /* GPIO control including function selection */
+}
+
+/* Pad control registers for GPIO electrical properties */
+#[repr(C)]
+pub struct PadsBank0Hw {
+    pub voltage_select: RwAlias,  /* Voltage select */
+    pub gpio: [RwAlias; 30],      /* Pad control register for each GPIO */
+    pub swclk: RwAlias,           /* Pad control register for SWCLK */
+    pub swd: RwAlias,             /* Pad control register for SWD */
+}
+
+/* Peripheral reset control */
+#[repr(C)]
+pub struct ResetsHw {
+    pub reset: RwAlias,       /* 1 = held in reset */
+    pub wdsel: RwAlias,       /* Reset when the watchdog fires */
+    pub reset_done: Ro,       /* 1 = out of reset and ready */
+}
+
+/* 64-bit microsecond timer with four alarms */
+#[repr(C)]
+pub struct TimerHw {
+    pub timehw: Wo,           /* Write high word after low word to set the time */
+    pub timelw: Wo,
+    pub timehr: Ro,           /* Read low word first, it latches the high word */
+    pub timelr: Ro,
+    pub alarm: [Rw; 4],       /* Arm an alarm by writing the low 32 bits of the target time */
+    pub armed: RwAlias,       /* Write 1 to disarm */
+    pub timerawh: Ro,         /* Raw reads, no latching */
+    pub timerawl: Ro,
+    pub dbgpause: RwAlias,
+    pub pause: RwAlias,
+    pub intr: RwAlias,        /* Raw alarm interrupts, write 1 to clear */
+    pub inte: RwAlias,
+    pub intf: RwAlias,
+    pub ints: Ro,
+}
+
+/* Watchdog, which also generates the 1 us tick for the timer */
+#[repr(C)]
+pub struct WatchdogHw {
+    pub ctrl: RwAlias,
+    pub load: Wo,
+    pub reason: Ro,
+    pub scratch: [RwAlias; 8],
+    pub tick: RwAlias,
+}
+
+/* Cortex-M0+ NVIC (no atomic aliases, the set/clear registers do that job) */
+#[repr(C)]
+pub struct NvicHw {
+    pub iser: Rw,             /* Write 1 to enable an interrupt */
+    reserved0: [u32; 31],
+    pub icer: Rw,             /* Write 1 to disable an interrupt */
+    reserved1: [u32; 31],
+    pub ispr: Rw,             /* Write 1 to make an interrupt pending */
+    reserved2: [u32; 31],
+    pub icpr: Rw,             /* Write 1 to clear a pending interrupt */
+    reserved3: [u32; 95],
+    pub ipr: [Rw; 8],         /* Priorities, 8 bits per interrupt (top 2 used) */
+}
+
+/* Cortex-M0+ SysTick (counts processor clocks) */
+#[repr(C)]
+pub struct SysTickHw {
+    pub csr: Rw,              /* Control and status */
+    pub rvr: Rw,              /* Reload value */
+    pub cvr: Rw,              /* Current value, any write clears it */
+    pub calib: Ro,
+}
+
+/* Base addresses for hardware registers */
+pub const SIO_BASE: u32 = 0xd0000000;
+pub const IO_BANK0_BASE: u32 = 0x40014000;
+pub const PADS_BANK0_BASE: u32 = 0x4001c000;
+pub const RESETS_BASE: u32 = 0x4000c000;
+pub const TIMER_BASE: u32 = 0x40054000;
+pub const WATCHDOG_BASE: u32 = 0x40058000;
+pub const NVIC_BASE: u32 = 0xe000e100;
+pub const SYSTICK_BASE: u32 = 0xe000e010;
+
+/* Register access */
+/* This means:
+   1. Take base address
+   2. Cast it to a reference to the struct
+   3. Access registers through the volatile cells (see reg.rs) */
+#[inline(always)]
+pub fn sio() -> &'static SioHw {
+    block(SIO_BASE)
+}
+
+#[inline(always)]
+pub fn io() -> &'static IoBank0Hw {
+    block(IO_BANK0_BASE)
+}
+
+#[inline(always)]
+pub fn pads() -> &'static PadsBank0Hw {
+    block(PADS_BANK0_BASE)
+}
+
+#[inline(always)]
+pub fn resets() -> &'static ResetsHw {
+    block(RESETS_BASE)
+}
+
+#[inline(always)]
+pub fn timer() -> &'static TimerHw {
+    block(TIMER_BASE)
+}
+
+#[inline(always)]
+pub fn watchdog() -> &'static WatchdogHw {
+    block(WATCHDOG_BASE)
+}
+
+#[inline(always)]
+pub fn nvic() -> &'static NvicHw {
+    block(NVIC_BASE)
+}
+
+#[inline(always)]
+pub fn systick() -> &'static SysTickHw {
+    block(SYSTICK_BASE)
+}
+
+/* GPIO function select (IO_BANK0 GPIOx_CTRL.FUNCSEL) */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Spi = 1,
+    Uart = 2,
+    I2c = 3,
+    Pwm = 4,      /* PWM slice output */
+    Sio = 5,      /* SIO function for GPIO */
+    Pio0 = 6,
+    Pio1 = 7,
+    Clock = 8,
+    Usb = 9,
+    Null = 0x1f,  /* Disconnected (reset state) */
+}
+
+impl FieldValue for Function {
+    fn into_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        match bits {
+            1 => Function::Spi,
+            2 => Function::Uart,
+            3 => Function::I2c,
+            4 => Function::Pwm,
+            5 => Function::Sio,
+            6 => Function::Pio0,
+            7 => Function::Pio1,
+            8 => Function::Clock,
+            9 => Function::Usb,
+            _ => Function::Null,
+        }
+    }
+}
+
+/* GPIOx_CTRL fields */
+pub const GPIO_CTRL_FUNCSEL: Field<Function> = Field::new(0, 5);
+pub const GPIO_CTRL_OUTOVER: Field = Field::new(8, 2);
+pub const GPIO_CTRL_OEOVER: Field = Field::new(12, 2);
+pub const GPIO_CTRL_INOVER: Field = Field::new(16, 2);
+pub const GPIO_CTRL_IRQOVER: Field = Field::new(28, 2);
+
+/* PADS_BANK0 GPIOx fields */
+pub const PADS_SLEWFAST: Field<bool> = bit(0);
+pub const PADS_SCHMITT: Field<bool> = bit(1);
+pub const PADS_PDE: Field<bool> = bit(2);       /* Pull-down enable */
+pub const PADS_PUE: Field<bool> = bit(3);       /* Pull-up enable */
+pub const PADS_DRIVE: Field = Field::new(4, 2); /* 0 = 2 mA, 1 = 4 mA, 2 = 8 mA, 3 = 12 mA */
+pub const PADS_IE: Field<bool> = bit(6);        /* Input enable */
+pub const PADS_OD: Field<bool> = bit(7);        /* Output disable */
+
+/* Interrupt configuration (4 bits per pin in INTR/INTE/INTF/INTS) */
+pub const GPIO_INT_LEVEL_LOW: u32 = 0x1;
+pub const GPIO_INT_LEVEL_HIGH: u32 = 0x2;
+pub const GPIO_INT_EDGE_LOW: u32 = 0x4;
+pub const GPIO_INT_EDGE_HIGH: u32 = 0x8;
+pub const TIMER_IRQ_0: u32 = 0;        /* Timer alarms 0-3 are IRQ 0-3 */
+pub const IO_BANK0_IRQ: u32 = 13;      /* IO Bank 0 interrupt number */
+
+/* SysTick CSR fields */
+const SYST_CSR_ENABLE: Field<bool> = bit(0);
+const SYST_CSR_TICKINT: Field<bool> = bit(1);
+const SYST_CSR_CLKSOURCE: Field<bool> = bit(2);
+const SYST_RVR_RELOAD: Field = Field::new(0, 24);
+
+/* Fire sysTickHandler every `cycles` processor clocks (24-bit counter) */
+pub fn systick_start(cycles: u32) {
+    let systick = systick();
+    systick.rvr.write(SYST_RVR_RELOAD.bits(cycles - 1));
+    systick.cvr.write(0);
+    systick.csr.write(
+        SYST_CSR_ENABLE.bits(true) | SYST_CSR_TICKINT.bits(true) | SYST_CSR_CLKSOURCE.bits(true),
+    );
+}
+
+pub fn systick_stop() {
+    systick().csr.write(0);
+}
+
+/* Bit numbers in RESETS.RESET */
+pub const RESET_IO_BANK0: u32 = 5;
+pub const RESET_PADS_BANK0: u32 = 8;
+pub const RESET_PLL_SYS: u32 = 12;
+pub const RESET_PLL_USB: u32 = 13;
+pub const RESET_PWM: u32 = 14;
+pub const RESET_TIMER: u32 = 21;
+
+/* Take a peripheral out of reset and wait until it is ready:
+   1. Clear its bit in RESETS.RESET (atomic CLR alias, other peripherals are untouched)
+   2. Wait for the same bit to be set in RESETS.RESET_DONE */
+pub fn unreset(bit: u32) {
+    resets().reset.clear(1u32 << bit);
+    while (resets().reset_done.read() & (1u32 << bit)) == 0 {}
+}
+
+/* Put a peripheral back into reset */
+pub fn reset(bit: u32) {
+    resets().reset.set(1u32 << bit);
+}
+
+/* Enable an interrupt in the NVIC (Nested Vectored Interrupt Controller) */
+pub fn nvic_enable(irq: u32) {
+    nvic().iser.write(1u32 << irq);
+}
+
+pub fn nvic_disable(irq: u32) {
+    nvic().icer.write(1u32 << irq);
+}
+
+/* Run f with interrupts masked, PRIMASK is restored afterwards so this nests */
+pub fn free<R>(f: impl FnOnce() -> R) -> R {
+    let primask: u32;
+    unsafe {
+        asm!("mrs {}, PRIMASK", out(reg) primask);
+        asm!("cpsid i");
+    }
+    let result = f();
+    if primask & 1 == 0 {
+        unsafe { asm!("cpsie i") };
+    }
+    result
+}
+
+/* Simple delay function using NOP instructions, length depends on clk_sys.
+   Only for a few cycles where the timer can't be used (clock switching),
+   timer::delay_us()/delay_ms() for anything else */
+#[inline(always)]
+pub fn delay(count: u32) {
+    /* Loop to create delay with NOP instructions */
+    for _ in 0..count {
+        unsafe {
+            asm!("nop");
+        }
+    }
+}
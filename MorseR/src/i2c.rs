@@ -0,0 +1,311 @@
+use crate::clocks;
+use crate::gpio::{Disabled, Pin, Pull};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+use crate::timer::time_us;
+
+/* I2C master (Synopsys DW_apb_i2c) */
+/* I2c<N> owns I2C block N and its SDA/SCL pins:
+   1. new() takes the block out of reset, muxes the pins with pull-ups and sets
+      master mode, 7-bit addressing and the SCL rate (100 kHz standard mode or
+      400 kHz fast mode, anything up to 1 MHz works).
+      SDA is an even GPIO and SCL the one after it: I2C0 on GPIO 0/1, 4/5, 8/9 ...,
+      I2C1 on GPIO 2/3, 6/7, 10/11 ..., checked at compile time
+   2. write()/read()/write_read() are blocking transfers. Each byte goes through
+      IC_DATA_CMD, the last one with STOP, the first read after a write with RESTART.
+   3. A NAK or lost arbitration makes the block abort the transfer, flush its
+      FIFO and send STOP by itself. The reason is read from IC_TX_ABRT_SOURCE and
+      returned as an Error. A bus that stops moving ends in Error::Timeout. */
+
+#[repr(C)]
+pub struct I2cHw {
+    pub con: RwAlias,           /* Mode, speed, addressing */
+    pub tar: RwAlias,           /* Target address */
+    pub sar: RwAlias,           /* Own slave address */
+    _reserved0: u32,
+    pub data_cmd: RwAlias,      /* Data plus CMD (read), STOP and RESTART bits */
+    pub ss_scl_hcnt: RwAlias,   /* SCL high/low counts, standard mode */
+    pub ss_scl_lcnt: RwAlias,
+    pub fs_scl_hcnt: RwAlias,   /* SCL high/low counts, fast mode */
+    pub fs_scl_lcnt: RwAlias,
+    _reserved1: [u32; 2],
+    pub intr_stat: Ro,
+    pub intr_mask: RwAlias,
+    pub raw_intr_stat: Ro,
+    pub rx_tl: RwAlias,         /* RX FIFO threshold */
+    pub tx_tl: RwAlias,         /* TX FIFO threshold */
+    pub clr_intr: Ro,           /* Reading a CLR_ register clears that interrupt */
+    pub clr_rx_under: Ro,
+    pub clr_rx_over: Ro,
+    pub clr_tx_over: Ro,
+    pub clr_rd_req: Ro,
+    pub clr_tx_abrt: Ro,
+    pub clr_rx_done: Ro,
+    pub clr_activity: Ro,
+    pub clr_stop_det: Ro,
+    pub clr_start_det: Ro,
+    pub clr_gen_call: Ro,
+    pub enable: RwAlias,
+    pub status: Ro,
+    pub txflr: Ro,              /* TX FIFO level */
+    pub rxflr: Ro,              /* RX FIFO level */
+    pub sda_hold: RwAlias,
+    pub tx_abrt_source: Ro,     /* Why the last transfer was aborted */
+    pub slv_data_nack_only: RwAlias,
+    pub dma_cr: RwAlias,
+    pub dma_tdlr: RwAlias,
+    pub dma_rdlr: RwAlias,
+    pub sda_setup: RwAlias,
+    pub ack_general_call: RwAlias,
+    pub enable_status: Ro,
+    pub fs_spklen: RwAlias,     /* Spike suppression, in clk_sys cycles */
+}
+
+const I2C_BASE: [u32; 2] = [0x40044000, 0x40048000];
+const RESET_I2C: [u32; 2] = [3, 4];
+
+#[inline(always)]
+fn i2c(index: usize) -> &'static I2cHw {
+    block(I2C_BASE[index])
+}
+
+/* IC_CON fields */
+const CON_MASTER_MODE: Field<bool> = bit(0);
+const CON_SPEED: Field = Field::new(1, 2);
+const CON_RESTART_EN: Field<bool> = bit(5);
+const CON_SLAVE_DISABLE: Field<bool> = bit(6);
+const CON_TX_EMPTY_CTRL: Field<bool> = bit(8);
+const SPEED_FAST: u32 = 2;      /* Uses the FS counts, good for any rate */
+
+/* IC_DATA_CMD fields */
+const DATA_CMD_DAT: Field = Field::new(0, 8);
+const DATA_CMD_READ: Field<bool> = bit(8);
+const DATA_CMD_STOP: Field<bool> = bit(9);
+const DATA_CMD_RESTART: Field<bool> = bit(10);
+
+/* IC_RAW_INTR_STAT fields */
+const INTR_TX_EMPTY: Field<bool> = bit(4);
+const INTR_STOP_DET: Field<bool> = bit(9);
+
+/* IC_SDA_HOLD */
+const SDA_HOLD_TX: Field = Field::new(0, 16);
+
+/* Shortest SCL high or low count the block can use (pico-sdk asserts the same) */
+const MIN_SCL_COUNT: u32 = 8;
+
+/* IC_ENABLE */
+const ENABLE: Field<bool> = bit(0);
+
+/* IC_STATUS */
+const STATUS_TFNF: Field<bool> = bit(1);
+
+/* IC_TX_ABRT_SOURCE */
+const ABRT_7B_ADDR_NOACK: u32 = 1 << 0;
+const ABRT_TXDATA_NOACK: u32 = 1 << 3;
+const ABRT_LOST: u32 = 1 << 12;
+
+/* Longest wait for one byte or for the STOP, a byte at 100 kHz takes 90 us */
+const TIMEOUT_US: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidAddress,     /* Reserved address (0x00-0x07, 0x78-0x7f) or more than 7 bits */
+    AddressNak,         /* Nobody answered at this address */
+    DataNak,            /* The device refused a data byte */
+    ArbitrationLost,    /* Another master on the bus */
+    Abort(u32),         /* Any other abort, IC_TX_ABRT_SOURCE */
+    Timeout,            /* SCL held low or the block stopped responding */
+}
+
+pub struct I2c<const N: usize> {
+    _private: (),
+}
+
+/* Compile-time pin check, see the top of this file */
+struct PinCheck<const N: usize, const SDA: u32, const SCL: u32>;
+
+impl<const N: usize, const SDA: u32, const SCL: u32> PinCheck<N, SDA, SCL> {
+    const OK: () = {
+        assert!(N < 2, "there are two I2C blocks");
+        assert!(SDA.is_multiple_of(2) && SCL == SDA + 1, "SDA must be an even GPIO and SCL the pin after it");
+        assert!((SDA / 2) % 2 == N as u32, "pins belong to the other I2C block");
+    };
+}
+
+/* Wait for cond, Err(Timeout) if it takes longer than TIMEOUT_US */
+fn wait(cond: impl Fn() -> bool) -> Result<(), Error> {
+    let start = time_us();
+    while !cond() {
+        if time_us().wrapping_sub(start) > TIMEOUT_US {
+            return Err(Error::Timeout);
+        }
+    }
+    Ok(())
+}
+
+impl<const N: usize> I2c<N> {
+    /* The pins stay with the I2C block for good. The internal pull-ups (~50k)
+       are enough for short wires at 100 kHz, use external ones for 400 kHz. */
+    pub fn new<const SDA: u32, const SCL: u32>(
+        sda: Pin<SDA, Disabled>,
+        scl: Pin<SCL, Disabled>,
+        baud: u32,
+    ) -> Self {
+        let _: () = PinCheck::<N, SDA, SCL>::OK;
+
+        reset(RESET_I2C[N]);
+        unreset(RESET_I2C[N]);
+
+        let mut i2c = I2c { _private: () };
+        i2c.hw().enable.write(0);
+        i2c.hw().con.write(
+            CON_MASTER_MODE.bits(true)
+                | CON_SPEED.bits(SPEED_FAST)
+                | CON_RESTART_EN.bits(true)
+                | CON_SLAVE_DISABLE.bits(true)
+                | CON_TX_EMPTY_CTRL.bits(true),
+        );
+        i2c.hw().tx_tl.write(0);
+        i2c.hw().rx_tl.write(0);
+        i2c.set_baud(baud);
+        i2c.hw().enable.write(ENABLE.bits(true));
+
+        let mut sda = sda.into_function(Function::I2c);
+        let mut scl = scl.into_function(Function::I2c);
+        sda.set_pull(Pull::Up);
+        scl.set_pull(Pull::Up);
+        i2c
+    }
+
+    #[inline(always)]
+    fn hw(&self) -> &'static I2cHw {
+        i2c(N)
+    }
+
+    /* SCL timing from clk_sys (the block is disabled while it changes):
+       1. Period in clk_sys cycles, 40% high and 60% low, each at least
+          MIN_SCL_COUNT (a baud of 0 is taken as 1, like spi's set_baud())
+       2. Spike filter of 1/16 of the low time
+       3. SDA changes 300 ns after SCL falls
+       Returns the rate actually set */
+    pub fn set_baud(&mut self, baud: u32) -> u32 {
+        let clk = clocks::sys_hz();
+        let baud = baud.max(1);
+        let period = (clk + baud / 2) / baud;
+        let lcnt = (period * 3 / 5).max(MIN_SCL_COUNT);
+        let hcnt = (period - period * 3 / 5).max(MIN_SCL_COUNT);
+        let was_enabled = self.hw().enable.read_field(ENABLE);
+        self.hw().enable.write(0);
+        self.hw().fs_scl_hcnt.write(hcnt);
+        self.hw().fs_scl_lcnt.write(lcnt);
+        self.hw().fs_spklen.write(if lcnt < 16 { 1 } else { lcnt / 16 });
+        self.hw().sda_hold.write_field(SDA_HOLD_TX, clk * 3 / 10_000_000 + 1);
+        self.hw().enable.write(ENABLE.bits(was_enabled));
+        clk / (hcnt + lcnt)
+    }
+
+    /* Address the next transfer goes to (the block must be disabled to change it) */
+    fn set_target(&mut self, address: u8) -> Result<(), Error> {
+        if !(0x08..0x78).contains(&address) {
+            return Err(Error::InvalidAddress);
+        }
+        self.hw().enable.write(0);
+        self.hw().tar.write(address as u32);
+        self.hw().enable.write(ENABLE.bits(true));
+        Ok(())
+    }
+
+    /* Abort reason of the transfer in progress, clearing it */
+    fn check_abort(&self) -> Result<(), Error> {
+        let source = self.hw().tx_abrt_source.read();
+        if source == 0 {
+            return Ok(());
+        }
+        self.hw().clr_tx_abrt.read();
+        /* The block sends STOP by itself after an abort, wait for it */
+        let _ = wait(|| self.hw().raw_intr_stat.read_field(INTR_STOP_DET));
+        self.hw().clr_stop_det.read();
+        Err(if source & ABRT_7B_ADDR_NOACK != 0 {
+            Error::AddressNak
+        } else if source & ABRT_TXDATA_NOACK != 0 {
+            Error::DataNak
+        } else if source & ABRT_LOST != 0 {
+            Error::ArbitrationLost
+        } else {
+            Error::Abort(source)
+        })
+    }
+
+    /* Push one command, waiting for FIFO space */
+    fn command(&mut self, cmd: u32) -> Result<(), Error> {
+        wait(|| self.hw().status.read_field(STATUS_TFNF))?;
+        self.hw().data_cmd.write(cmd);
+        Ok(())
+    }
+
+    fn send(&mut self, bytes: &[u8], restart: bool, stop: bool) -> Result<(), Error> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let last = i == bytes.len() - 1;
+            self.command(
+                DATA_CMD_DAT.bits(byte as u32)
+                    | DATA_CMD_RESTART.bits(restart && i == 0)
+                    | DATA_CMD_STOP.bits(stop && last),
+            )?;
+            /* Wait for the byte to go out, then see whether it was acknowledged */
+            wait(|| self.hw().raw_intr_stat.read_field(INTR_TX_EMPTY))?;
+            self.check_abort()?;
+        }
+        if stop {
+            wait(|| self.hw().raw_intr_stat.read_field(INTR_STOP_DET))?;
+            self.hw().clr_stop_det.read();
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, buffer: &mut [u8], restart: bool) -> Result<(), Error> {
+        let len = buffer.len();
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            self.command(
+                DATA_CMD_READ.bits(true)
+                    | DATA_CMD_RESTART.bits(restart && i == 0)
+                    | DATA_CMD_STOP.bits(i == len - 1),
+            )?;
+            wait(|| self.hw().rxflr.read() != 0 || self.hw().tx_abrt_source.read() != 0)?;
+            self.check_abort()?;
+            *byte = DATA_CMD_DAT.get(self.hw().data_cmd.read()) as u8;
+        }
+        wait(|| self.hw().raw_intr_stat.read_field(INTR_STOP_DET))?;
+        self.hw().clr_stop_det.read();
+        Ok(())
+    }
+
+    /* START, address + W, bytes, STOP */
+    pub fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.set_target(address)?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.send(bytes, false, true)
+    }
+
+    /* START, address + R, bytes, STOP */
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.set_target(address)?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.receive(buffer, false)
+    }
+
+    /* Register read: START, address + W, bytes, RESTART, address + R, buffer, STOP */
+    pub fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.set_target(address)?;
+        if !bytes.is_empty() {
+            self.send(bytes, false, buffer.is_empty())?;
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.receive(buffer, !bytes.is_empty())
+    }
+}
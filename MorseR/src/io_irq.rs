@@ -0,0 +1,97 @@
+use core::ptr::addr_of_mut;
+
+use crate::hw::*;
+
+/* IO Bank 0 interrupt dispatcher */
+/* All 30 GPIOs share one interrupt (IRQ13), this gives each pin its own handler:
+   1. register() installs a handler for a pin and enables the events it wants
+      (GPIO_INT_LEVEL_LOW, GPIO_INT_LEVEL_HIGH, GPIO_INT_EDGE_LOW, GPIO_INT_EDGE_HIGH
+      or'ed together) on processor 0
+   2. ioIrqBank0 goes through the four status registers (8 pins, 4 bits each).
+      The pending edges of a whole register are acknowledged before any handler
+      runs, so an edge that arrives during a handler is latched again and taken
+      on the next entry instead of being cleared unseen
+   3. Each handler gets its pin number and the events that fired
+   4. Level events can't be acknowledged, they stay pending while the level holds:
+      the handler has to remove the cause or unregister, otherwise the interrupt
+      is taken again straight away
+   5. set_dormant_wake() picks the events that wake the chip from DORMANT,
+      separately from the processor interrupt enables */
+
+pub type Handler = fn(pin: u32, events: u32);
+
+pub const NUM_PINS: usize = 30;
+
+/* All four event bits of one pin */
+const EVENTS_ALL: u32 = 0xF;
+
+/* Edge bits of every pin in a status register, the only ones INTR can clear */
+const EDGES_ALL: u32 = 0xCCCC_CCCC;
+
+static mut HANDLERS: [Option<Handler>; NUM_PINS] = [None; NUM_PINS];
+
+/* Register index and bit offset of a pin's events. Checked here, before any
+   caller enters a critical section with it. */
+#[inline(always)]
+fn position(pin: u32) -> (usize, u32) {
+    assert!((pin as usize) < NUM_PINS, "io_irq: GPIO0-29 only");
+    ((pin / 8) as usize, 4 * (pin % 8))
+}
+
+/* Call handler on events for pin, replacing any earlier registration.
+   Stale edges are cleared first so the handler only sees new ones. */
+pub fn register(pin: u32, events: u32, handler: Handler) {
+    let (index, offset) = position(pin);
+    free(|| unsafe {
+        (*addr_of_mut!(HANDLERS))[pin as usize] = Some(handler);
+        io().proc0_inte[index].clear(EVENTS_ALL << offset);
+        io().intr[index].write(EVENTS_ALL << offset);
+        io().proc0_inte[index].set((events & EVENTS_ALL) << offset);
+    });
+    nvic_enable(IO_BANK0_IRQ);
+}
+
+/* Stop interrupts from pin and forget its handler */
+pub fn unregister(pin: u32) {
+    let (index, offset) = position(pin);
+    free(|| unsafe {
+        io().proc0_inte[index].clear(EVENTS_ALL << offset);
+        (*addr_of_mut!(HANDLERS))[pin as usize] = None;
+    });
+}
+
+/* Events on pin that wake the chip from DORMANT, 0 for none */
+pub fn set_dormant_wake(pin: u32, events: u32) {
+    let (index, offset) = position(pin);
+    io().dormant_wake_inte[index].clear(EVENTS_ALL << offset);
+    io().intr[index].write(EVENTS_ALL << offset);
+    io().dormant_wake_inte[index].set((events & EVENTS_ALL) << offset);
+}
+
+/* Interrupt handler for IO Bank 0 */
+#[no_mangle]
+pub extern "C" fn ioIrqBank0() {
+    for index in 0..4 {
+        let status = io().proc0_ints[index].read();
+        if status == 0 {
+            continue;
+        }
+
+        /* Acknowledge before dispatch */
+        io().intr[index].write(status & EDGES_ALL);
+
+        let mut pending = status;
+        while pending != 0 {
+            let offset = pending.trailing_zeros() & !3;
+            let events = (pending >> offset) & EVENTS_ALL;
+            pending &= !(EVENTS_ALL << offset);
+
+            let pin = index as u32 * 8 + offset / 4;
+            match unsafe { (*addr_of_mut!(HANDLERS))[pin as usize] } {
+                Some(handler) => handler(pin, events),
+                /* Enabled without a handler: switch it off rather than spin on a level */
+                None => io().proc0_inte[index].clear(EVENTS_ALL << offset),
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use core::fmt;
+
+use crate::i2c::{Error, I2c};
+use crate::timer::{delay_ms, delay_us};
+
+/* HD44780 character LCD (LCD1602) behind a PCF8574 I2C backpack */
+/* The PCF8574 is an 8-bit port expander wired to the LCD in 4-bit mode:
+   P0 = RS, P1 = RW, P2 = E, P3 = backlight, P4-P7 = D4-D7.
+   Every byte for the LCD is sent as two nibbles, each one latched by
+   writing the port with E high and then low.
+   1. new() runs the 4-bit initialisation by instruction from the HD44780
+      datasheet (the controller may be in any state after power-up)
+   2. Cursor position, cursor/blink, display on/off and backlight can be set
+   3. Eight custom 5x8 glyphs live in CGRAM, written as character codes 0-7
+   4. LineView keeps the last COLS decoded characters for a line that scrolls left */
+
+/* Backpack address, 0x27 for the PCF8574, 0x3f for the PCF8574A */
+pub const ADDRESS: u8 = 0x27;
+
+pub const COLS: usize = 16;
+pub const ROWS: usize = 2;
+
+/* PCF8574 port bits */
+const RS: u8 = 1 << 0;          /* 0 = instruction, 1 = data */
+const EN: u8 = 1 << 2;
+const BACKLIGHT: u8 = 1 << 3;
+
+/* Instructions */
+const CLEAR: u8 = 0x01;
+const HOME: u8 = 0x02;
+const ENTRY_MODE: u8 = 0x04;
+const ENTRY_INCREMENT: u8 = 0x02;
+const DISPLAY_CONTROL: u8 = 0x08;
+const DISPLAY_ON: u8 = 0x04;
+const CURSOR_ON: u8 = 0x02;
+const BLINK_ON: u8 = 0x01;
+const SHIFT: u8 = 0x10;
+const SHIFT_DISPLAY: u8 = 0x08;
+const SHIFT_RIGHT: u8 = 0x04;
+const FUNCTION_SET: u8 = 0x20;
+const FUNCTION_8BIT: u8 = 0x10;
+const FUNCTION_2LINE: u8 = 0x08;
+const SET_CGRAM: u8 = 0x40;
+const SET_DDRAM: u8 = 0x80;
+
+/* DDRAM address of the start of each row */
+const ROW_OFFSET: [u8; ROWS] = [0x00, 0x40];
+
+pub struct Lcd<const N: usize> {
+    i2c: I2c<N>,
+    address: u8,
+    backlight: u8,  /* BACKLIGHT or 0, sent with every port write */
+    control: u8,    /* DISPLAY_CONTROL flags */
+}
+
+impl<const N: usize> Lcd<N> {
+    /* Initialise the display: on, cursor off, cleared, backlight on */
+    pub fn new(i2c: I2c<N>, address: u8) -> Result<Self, Error> {
+        let mut lcd = Lcd {
+            i2c,
+            address,
+            backlight: BACKLIGHT,
+            control: DISPLAY_ON,
+        };
+
+        /* Initialisation by instruction:
+           1. Wait >40 ms after power-up
+           2. Function set 8-bit three times (4.1 ms, then 100 us apart), this
+              gets the controller into 8-bit mode from any state
+           3. Function set 4-bit, from here on bytes go as two nibbles
+           4. 2 lines 5x8 font, display on, clear, cursor moves right */
+        delay_ms(50);
+        lcd.port(0)?;
+        for wait_us in [4_100, 100, 100] {
+            lcd.nibble((FUNCTION_SET | FUNCTION_8BIT) >> 4, 0)?;
+            delay_us(wait_us);
+        }
+        lcd.nibble(FUNCTION_SET >> 4, 0)?;
+        delay_us(100);
+
+        lcd.command(FUNCTION_SET | FUNCTION_2LINE)?;
+        lcd.command(DISPLAY_CONTROL | lcd.control)?;
+        lcd.clear()?;
+        lcd.command(ENTRY_MODE | ENTRY_INCREMENT)?;
+        Ok(lcd)
+    }
+
+    /* Write the expander port */
+    fn port(&mut self, bits: u8) -> Result<(), Error> {
+        self.i2c.write(self.address, &[bits | self.backlight])
+    }
+
+    /* Latch 4 bits: E high then low with the data held (one I2C write each,
+       ~90 us at 100 kHz, well over the 450 ns E pulse) */
+    fn nibble(&mut self, nibble: u8, rs: u8) -> Result<(), Error> {
+        let bits = (nibble << 4) | rs | self.backlight;
+        self.i2c.write(self.address, &[bits | EN, bits])
+    }
+
+    fn byte(&mut self, byte: u8, rs: u8) -> Result<(), Error> {
+        self.nibble(byte >> 4, rs)?;
+        self.nibble(byte & 0x0f, rs)
+    }
+
+    /* Instructions take 37 us, less than the I2C write that follows them */
+    fn command(&mut self, command: u8) -> Result<(), Error> {
+        self.byte(command, 0)
+    }
+
+    /* Clear and home take 1.52 ms */
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.command(CLEAR)?;
+        delay_us(2_000);
+        Ok(())
+    }
+
+    pub fn home(&mut self) -> Result<(), Error> {
+        self.command(HOME)?;
+        delay_us(2_000);
+        Ok(())
+    }
+
+    /* Move the cursor, col and row from 0 (clamped to the last column and row) */
+    pub fn set_cursor(&mut self, col: usize, row: usize) -> Result<(), Error> {
+        let col = col.min(COLS - 1);
+        let row = row.min(ROWS - 1);
+        self.command(SET_DDRAM | (ROW_OFFSET[row] + col as u8))
+    }
+
+    fn set_control(&mut self, flag: u8, on: bool) -> Result<(), Error> {
+        if on {
+            self.control |= flag;
+        } else {
+            self.control &= !flag;
+        }
+        self.command(DISPLAY_CONTROL | self.control)
+    }
+
+    /* Display on or off, the contents are kept */
+    pub fn set_display(&mut self, on: bool) -> Result<(), Error> {
+        self.set_control(DISPLAY_ON, on)
+    }
+
+    /* Underline cursor */
+    pub fn set_cursor_visible(&mut self, on: bool) -> Result<(), Error> {
+        self.set_control(CURSOR_ON, on)
+    }
+
+    /* Blinking block cursor */
+    pub fn set_blink(&mut self, on: bool) -> Result<(), Error> {
+        self.set_control(BLINK_ON, on)
+    }
+
+    /* Shift the whole display one column, the cursor goes with it */
+    pub fn scroll(&mut self, right: bool) -> Result<(), Error> {
+        self.command(SHIFT | SHIFT_DISPLAY | if right { SHIFT_RIGHT } else { 0 })
+    }
+
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), Error> {
+        self.backlight = if on { BACKLIGHT } else { 0 };
+        self.port(0)
+    }
+
+    /* Define custom character code 0-7, one byte per row (5 low bits), top row first.
+       The cursor is moved to the top left afterwards, the DDRAM address is lost. */
+    pub fn create_char(&mut self, code: u8, rows: &[u8; 8]) -> Result<(), Error> {
+        self.command(SET_CGRAM | ((code & 7) << 3))?;
+        for &row in rows {
+            self.byte(row & 0x1f, RS)?;
+        }
+        self.set_cursor(0, 0)
+    }
+
+    /* Character at the cursor, codes 0-7 are the custom glyphs */
+    pub fn write_char(&mut self, c: u8) -> Result<(), Error> {
+        self.byte(c, RS)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        bytes.iter().try_for_each(|&c| self.write_char(c))
+    }
+
+    /* Replace a whole row, padded with spaces */
+    pub fn write_line(&mut self, row: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.set_cursor(0, row)?;
+        (0..COLS).try_for_each(|col| self.write_char(bytes.get(col).copied().unwrap_or(b' ')))
+    }
+}
+
+/* write!(lcd, ...) at the cursor, non-ASCII shows as '?' */
+impl<const N: usize> fmt::Write for Lcd<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars()
+            .try_for_each(|c| self.write_char(if c.is_ascii() { c as u8 } else { b'?' }))
+            .map_err(|_| fmt::Error)
+    }
+}
+
+/* One display line of decoded text: new characters come in on the right,
+   the oldest drops off the left */
+pub struct LineView {
+    line: [u8; COLS],
+    dirty: bool,    /* Changed since the last draw */
+}
+
+impl Default for LineView {
+    fn default() -> Self {
+        LineView::new()
+    }
+}
+
+impl LineView {
+    pub const fn new() -> Self {
+        LineView {
+            line: [b' '; COLS],
+            dirty: true,
+        }
+    }
+
+    pub fn push(&mut self, c: u8) {
+        self.line.copy_within(1.., 0);
+        self.line[COLS - 1] = c;
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.line = [b' '; COLS];
+        self.dirty = true;
+    }
+
+    pub fn as_bytes(&self) -> &[u8; COLS] {
+        &self.line
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /* Redraw the row if anything changed */
+    pub fn draw<const N: usize>(&mut self, lcd: &mut Lcd<N>, row: usize) -> Result<(), Error> {
+        if self.dirty {
+            lcd.write_line(row, &self.line)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
@@ -4,7 +4,8 @@
 
 use core::panic::PanicInfo;
 
-#[cfg(not(any(feature = "boot2", feature = "startup", feature = "transmit")))]
+// Each image links exactly one application object, which brings the panic handler
+#[cfg(not(any(feature = "boot2", feature = "startup")))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
@@ -16,5 +17,66 @@ pub mod boot_stage2;
 #[cfg(feature = "startup")]
 pub mod startup;
 
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod reg;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod hw;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod clocks;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod timer;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod watchdog;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod dma;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod pio;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod uart;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod i2c;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod spi;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod adc;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod gpio;
+
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub mod io_irq;
+
+// Hardware-independent logic lives in the morse-core crate (../MorseCore)
+#[cfg(any(feature = "transmit", feature = "receive"))]
+pub use morse_core::{decoder, envelope, gesture, keyer, ladder, morse, pio_keyer, timing};
+
+#[cfg(feature = "transmit")]
+pub mod pwm;
+
+#[cfg(feature = "transmit")]
+pub mod sidetone;
+
 #[cfg(feature = "transmit")]
-pub mod transmit;
\ No newline at end of file
+pub mod tx;
+
+#[cfg(feature = "transmit")]
+pub mod pio_tx;
+
+#[cfg(feature = "transmit")]
+pub mod transmit;
+
+#[cfg(feature = "receive")]
+pub mod lcd;
+
+#[cfg(feature = "receive")]
+pub mod receive;
\ No newline at end of file
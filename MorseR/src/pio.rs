@@ -0,0 +1,520 @@
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::dma::{self, Target};
+use crate::gpio::{Alternate, Disabled, Pin};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias, Wo};
+
+/* The assembler lives in morse-core so its tests run on the host */
+pub use morse_core::pio::{assemble, Program, SideSet};
+use morse_core::pio::{is_jmp, MAX_INSTRUCTIONS};
+
+/* PIO (programmable I/O) */
+/* Two PIO blocks, each with 32 words of instruction memory shared by four
+   state machines. Pio<N> owns block N:
+   1. load() finds room for an assembled Program (at its .origin if it has
+      one), writes it and moves its jumps to where it landed. unload() frees it.
+   2. claim() hands out a free state machine. init() sets it up for a loaded
+      program from a Config: clock divider, pin mappings, shifts and FIFO join,
+      wrap and side-set come from the program. It starts at the top of the
+      program, stopped, start() sets it running.
+   3. pin() gives a GPIO to the block, the state machines then drive it with
+      out/set/side-set. set_pin_dirs()/set_pins() run `set` instructions on a
+      stopped state machine to give the pins a direction and level first.
+   4. put()/get() go through the TX and RX FIFOs (4 deep, 8 when joined),
+      dma_tx()/dma_rx() hand them to the DMA instead.
+   5. `irq n` in a program sets IRQ flag n, set_handler() calls a function for
+      flags 0-3 from the block's PIO_IRQ_0 interrupt. */
+
+#[repr(C)]
+pub struct SmHw {
+    pub clkdiv: RwAlias,    /* Clock divider, 16.8 fixed point */
+    pub execctrl: RwAlias,  /* Wrap, side-set enable, jmp pin, status */
+    pub shiftctrl: RwAlias, /* Shift directions, thresholds, autopush/pull, FIFO join */
+    pub addr: Ro,           /* Current program counter */
+    pub instr: RwAlias,     /* Writing executes the instruction straight away */
+    pub pinctrl: RwAlias,   /* Pin bases and counts */
+}
+
+#[repr(C)]
+pub struct PioHw {
+    pub ctrl: RwAlias,      /* State machine enables and restarts */
+    pub fstat: Ro,          /* FIFO full/empty flags */
+    pub fdebug: RwAlias,    /* Sticky stall/overflow flags, write 1 to clear */
+    pub flevel: Ro,         /* FIFO levels */
+    pub txf: [Wo; 4],
+    pub rxf: [Ro; 4],
+    pub irq: RwAlias,       /* State machine IRQ flags, write 1 to clear */
+    pub irq_force: Wo,
+    pub input_sync_bypass: RwAlias,
+    pub dbg_padout: Ro,
+    pub dbg_padoe: Ro,
+    pub dbg_cfginfo: Ro,
+    pub instr_mem: [Wo; 32],
+    pub sm: [SmHw; 4],
+    pub intr: Ro,
+    pub irq0_inte: RwAlias,
+    pub irq0_intf: RwAlias,
+    pub irq0_ints: Ro,
+    pub irq1_inte: RwAlias,
+    pub irq1_intf: RwAlias,
+    pub irq1_ints: Ro,
+}
+
+const PIO_BASE: [u32; 2] = [0x50200000, 0x50300000];
+const RESET_PIO: [u32; 2] = [10, 11];
+const FUNCTION: [Function; 2] = [Function::Pio0, Function::Pio1];
+pub const PIO_IRQ_0: [u32; 2] = [7, 9];
+
+#[inline(always)]
+fn pio(index: usize) -> &'static PioHw {
+    block(PIO_BASE[index])
+}
+
+/* CTRL fields */
+const CTRL_SM_ENABLE: Field = Field::new(0, 4);
+const CTRL_SM_RESTART: Field = Field::new(4, 4);
+const CTRL_CLKDIV_RESTART: Field = Field::new(8, 4);
+
+/* FSTAT fields, one bit per state machine */
+const FSTAT_RXEMPTY: Field = Field::new(8, 4);
+const FSTAT_TXFULL: Field = Field::new(16, 4);
+const FSTAT_TXEMPTY: Field = Field::new(24, 4);
+
+/* CLKDIV fields */
+const CLKDIV_FRAC: Field = Field::new(8, 8);
+const CLKDIV_INT: Field = Field::new(16, 16);
+
+/* EXECCTRL fields */
+const EXECCTRL_STATUS_N: Field = Field::new(0, 4);
+const EXECCTRL_STATUS_SEL: Field<bool> = bit(4);   /* 1 = RX level */
+const EXECCTRL_WRAP_BOTTOM: Field = Field::new(7, 5);
+const EXECCTRL_WRAP_TOP: Field = Field::new(12, 5);
+const EXECCTRL_JMP_PIN: Field = Field::new(24, 5);
+const EXECCTRL_SIDE_PINDIR: Field<bool> = bit(29);
+const EXECCTRL_SIDE_EN: Field<bool> = bit(30);
+
+/* SHIFTCTRL fields */
+const SHIFTCTRL_AUTOPUSH: Field<bool> = bit(16);
+const SHIFTCTRL_AUTOPULL: Field<bool> = bit(17);
+const SHIFTCTRL_IN_SHIFTDIR: Field<bool> = bit(18);    /* 1 = right */
+const SHIFTCTRL_OUT_SHIFTDIR: Field<bool> = bit(19);
+const SHIFTCTRL_PUSH_THRESH: Field = Field::new(20, 5); /* 0 = 32 */
+const SHIFTCTRL_PULL_THRESH: Field = Field::new(25, 5);
+const SHIFTCTRL_FJOIN_TX: Field<bool> = bit(30);
+const SHIFTCTRL_FJOIN_RX: Field<bool> = bit(31);
+
+/* PINCTRL fields */
+const PINCTRL_OUT_BASE: Field = Field::new(0, 5);
+const PINCTRL_SET_BASE: Field = Field::new(5, 5);
+const PINCTRL_SIDESET_BASE: Field = Field::new(10, 5);
+const PINCTRL_IN_BASE: Field = Field::new(15, 5);
+const PINCTRL_OUT_COUNT: Field = Field::new(20, 6);
+const PINCTRL_SET_COUNT: Field = Field::new(26, 3);
+const PINCTRL_SIDESET_COUNT: Field = Field::new(29, 3);
+
+/* INTR/INTE/INTS: state machine IRQ flags 0-3 */
+const INT_SM: Field = Field::new(8, 4);
+
+pub const STATE_MACHINES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    NoSpace,        /* Not enough free instruction memory (or .origin taken) */
+}
+
+/* A program in instruction memory */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Loaded {
+    pub offset: u8,     /* Where it starts */
+    len: u8,
+    wrap_target: u8,    /* Absolute addresses */
+    wrap: u8,
+    side_set: SideSet,
+}
+
+/* FIFO join: one 8-deep FIFO instead of two 4-deep ones */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Join {
+    None,
+    Tx,
+    Rx,
+}
+
+/* What `mov x, status` reads: all ones while the FIFO holds fewer than N words, else zero */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    TxBelow(u8),
+    RxBelow(u8),
+}
+
+/* State machine settings, pins are GPIO numbers */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub clock_hz: u32,          /* State machine clock, 0 = clk_sys */
+    pub out_base: u8,           /* out pins */
+    pub out_count: u8,
+    pub set_base: u8,           /* set pins */
+    pub set_count: u8,
+    pub sideset_base: u8,       /* side-set pins, the count comes from the program */
+    pub in_base: u8,            /* in pins and wait pin */
+    pub jmp_pin: u8,            /* jmp pin */
+    pub out_shift_right: bool,
+    pub in_shift_right: bool,
+    pub autopull: bool,
+    pub autopush: bool,
+    pub pull_threshold: u8,     /* 1-32 bits */
+    pub push_threshold: u8,
+    pub join: Join,
+    pub status: Status,
+}
+
+impl Config {
+    /* Full-speed clock, no pins, shift right without autopull/autopush, status always zero */
+    pub const fn new() -> Self {
+        Config {
+            clock_hz: 0,
+            out_base: 0,
+            out_count: 0,
+            set_base: 0,
+            set_count: 0,
+            sideset_base: 0,
+            in_base: 0,
+            jmp_pin: 0,
+            out_shift_right: true,
+            in_shift_right: true,
+            autopull: false,
+            autopush: false,
+            pull_threshold: 32,
+            push_threshold: 32,
+            join: Join::None,
+            status: Status::TxBelow(0),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
+
+/* Called from PIO_IRQ_0 with the IRQ flag (0-3) that was set */
+pub type Handler = fn(flag: usize);
+
+static mut HANDLERS: [[Option<Handler>; STATE_MACHINES]; 2] = [[None; STATE_MACHINES]; 2];
+
+pub struct Pio<const N: usize> {
+    used: u32,      /* Instruction memory words in use */
+    claimed: u8,    /* State machines handed out */
+}
+
+impl<const N: usize> Pio<N> {
+    /* Reset the block: state machines stopped, memory free */
+    pub fn new() -> Self {
+        assert!(N < 2);
+        reset(RESET_PIO[N]);
+        unreset(RESET_PIO[N]);
+        Pio { used: 0, claimed: 0 }
+    }
+
+    /* Write a program into free instruction memory, highest free addresses
+       first (like the SDK) so .origin 0 programs still find room */
+    pub fn load(&mut self, program: &Program) -> Result<Loaded, Error> {
+        let len = program.len();
+        let mask = if len == MAX_INSTRUCTIONS { u32::MAX } else { (1u32 << len) - 1 };
+        let offset = match program.origin {
+            Some(origin) if (origin as usize) + len <= MAX_INSTRUCTIONS => {
+                Some(origin as usize).filter(|&origin| self.used & (mask << origin) == 0)
+            }
+            Some(_) => None,
+            None => (0..=MAX_INSTRUCTIONS - len).rev().find(|&offset| self.used & (mask << offset) == 0),
+        }
+        .ok_or(Error::NoSpace)?;
+
+        for (i, &instruction) in program.instructions().iter().enumerate() {
+            let instruction = if is_jmp(instruction) { instruction + offset as u16 } else { instruction };
+            pio(N).instr_mem[offset + i].write(instruction as u32);
+        }
+        self.used |= mask << offset;
+        Ok(Loaded {
+            offset: offset as u8,
+            len: len as u8,
+            wrap_target: program.wrap_target + offset as u8,
+            wrap: program.wrap + offset as u8,
+            side_set: program.side_set,
+        })
+    }
+
+    /* Free a program's memory, no state machine may still be running it */
+    pub fn unload(&mut self, loaded: Loaded) {
+        let mask = if loaded.len as usize == MAX_INSTRUCTIONS { u32::MAX } else { (1u32 << loaded.len) - 1 };
+        self.used &= !(mask << loaded.offset);
+    }
+
+    /* A free state machine */
+    pub fn claim(&mut self) -> Option<StateMachine<N>> {
+        let index = (0..STATE_MACHINES).find(|&index| self.claimed & (1 << index) == 0)?;
+        self.claimed |= 1 << index;
+        Some(StateMachine { index })
+    }
+
+    /* Stop a state machine and give it back */
+    pub fn release(&mut self, mut sm: StateMachine<N>) {
+        sm.stop();
+        self.claimed &= !(1 << sm.index);
+    }
+
+    /* Hand a GPIO to this PIO block */
+    pub fn pin<const P: u32>(&mut self, pin: Pin<P, Disabled>) -> Pin<P, Alternate> {
+        pin.into_function(FUNCTION[N])
+    }
+
+    /* Start several state machines on the same cycle, with their clock dividers in step */
+    pub fn start_together(&mut self, mask: u8) {
+        let mask = mask as u32 & 0xf;
+        pio(N).ctrl.set(CTRL_SM_ENABLE.bits(mask) | CTRL_CLKDIV_RESTART.bits(mask));
+    }
+
+    /* Handler for IRQ flag 0-3 (`irq n`, or `irq n rel` from state machine
+       n), None to stop calling one. The flag is cleared before the call. */
+    pub fn set_handler(&mut self, flag: usize, handler: Option<Handler>) {
+        let mask = INT_SM.bits(1 << flag);
+        free(|| unsafe {
+            (*addr_of_mut!(HANDLERS))[N][flag] = handler;
+            pio(N).irq0_inte.clear(mask);
+            pio(N).irq.write(1 << flag);
+            if handler.is_some() {
+                pio(N).irq0_inte.set(mask);
+            }
+        });
+        if handler.is_some() {
+            nvic_enable(PIO_IRQ_0[N]);
+        }
+    }
+
+    /* Clear an IRQ flag, a state machine waiting on it carries on */
+    pub fn clear_irq(&mut self, flag: usize) {
+        pio(N).irq.write(1 << flag);
+    }
+}
+
+impl<const N: usize> Default for Pio<N> {
+    fn default() -> Self {
+        Pio::new()
+    }
+}
+
+pub struct StateMachine<const N: usize> {
+    index: usize,
+}
+
+impl<const N: usize> StateMachine<N> {
+    #[inline(always)]
+    fn hw(&self) -> &'static SmHw {
+        &pio(N).sm[self.index]
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /* Set up for a loaded program, stopped at its first instruction with empty FIFOs */
+    pub fn init(&mut self, loaded: &Loaded, config: &Config) {
+        self.stop();
+        let side_set = loaded.side_set;
+        let (status_rx, status_n) = match config.status {
+            Status::TxBelow(n) => (false, n),
+            Status::RxBelow(n) => (true, n),
+        };
+        let threshold = |bits: u8| (bits as u32) & 31;
+
+        self.set_frequency(config.clock_hz);
+        self.hw().execctrl.write(
+            EXECCTRL_WRAP_BOTTOM.bits(loaded.wrap_target as u32)
+                | EXECCTRL_WRAP_TOP.bits(loaded.wrap as u32)
+                | EXECCTRL_JMP_PIN.bits(config.jmp_pin as u32)
+                | EXECCTRL_SIDE_EN.bits(side_set.optional)
+                | EXECCTRL_SIDE_PINDIR.bits(side_set.pindirs)
+                | EXECCTRL_STATUS_SEL.bits(status_rx)
+                | EXECCTRL_STATUS_N.bits(status_n as u32),
+        );
+        self.hw().shiftctrl.write(
+            SHIFTCTRL_AUTOPUSH.bits(config.autopush)
+                | SHIFTCTRL_AUTOPULL.bits(config.autopull)
+                | SHIFTCTRL_IN_SHIFTDIR.bits(config.in_shift_right)
+                | SHIFTCTRL_OUT_SHIFTDIR.bits(config.out_shift_right)
+                | SHIFTCTRL_PUSH_THRESH.bits(threshold(config.push_threshold))
+                | SHIFTCTRL_PULL_THRESH.bits(threshold(config.pull_threshold)),
+        );
+        self.hw().pinctrl.write(
+            PINCTRL_OUT_BASE.bits(config.out_base as u32)
+                | PINCTRL_OUT_COUNT.bits(config.out_count as u32)
+                | PINCTRL_SET_BASE.bits(config.set_base as u32)
+                | PINCTRL_SET_COUNT.bits(config.set_count as u32)
+                | PINCTRL_SIDESET_BASE.bits(config.sideset_base as u32)
+                | PINCTRL_SIDESET_COUNT.bits(side_set.bits as u32)
+                | PINCTRL_IN_BASE.bits(config.in_base as u32),
+        );
+
+        /* Changing the join empties both FIFOs: toggle it to flush, then set it */
+        self.hw().shiftctrl.toggle(SHIFTCTRL_FJOIN_RX.mask());
+        self.hw().shiftctrl.toggle(SHIFTCTRL_FJOIN_RX.mask());
+        match config.join {
+            Join::None => {}
+            Join::Tx => self.hw().shiftctrl.set(SHIFTCTRL_FJOIN_TX.mask()),
+            Join::Rx => self.hw().shiftctrl.set(SHIFTCTRL_FJOIN_RX.mask()),
+        }
+
+        self.restart();
+        self.exec(loaded.offset as u16);    /* jmp offset */
+    }
+
+    /* Clock divider for a state machine clock of hz (0 = clk_sys), 16.8 fixed
+       point from clk_sys. Returns the rate actually set. */
+    pub fn set_frequency(&mut self, hz: u32) -> u32 {
+        let clk = clocks::sys_hz();
+        let div256 = if hz == 0 { 256 } else { (clk as u64 * 256 / hz as u64) as u32 };
+        let div256 = div256.clamp(256, 0x00ff_ffff);
+        self.hw().clkdiv.write(CLKDIV_INT.bits(div256 >> 8) | CLKDIV_FRAC.bits(div256 & 0xff));
+        (clk as u64 * 256 / div256 as u64) as u32
+    }
+
+    pub fn start(&mut self) {
+        pio(N).ctrl.set(CTRL_SM_ENABLE.bits(1 << self.index));
+    }
+
+    /* Stops after the current instruction, state and FIFOs are kept */
+    pub fn stop(&mut self) {
+        pio(N).ctrl.clear(CTRL_SM_ENABLE.bits(1 << self.index));
+    }
+
+    pub fn is_running(&self) -> bool {
+        pio(N).ctrl.read_field(CTRL_SM_ENABLE) & (1 << self.index) != 0
+    }
+
+    /* Clear shift counters, ISR, delay and stall state (not the FIFOs or PC) */
+    pub fn restart(&mut self) {
+        pio(N).ctrl.set(CTRL_SM_RESTART.bits(1 << self.index) | CTRL_CLKDIV_RESTART.bits(1 << self.index));
+    }
+
+    /* Execute one instruction now, also on a stopped state machine */
+    pub fn exec(&mut self, instruction: u16) {
+        self.hw().instr.write(instruction as u32);
+    }
+
+    /* Program counter */
+    pub fn addr(&self) -> u8 {
+        self.hw().addr.read() as u8
+    }
+
+    /* Run `set <destination>, value` over pins base.. (up to 5 at a time) with the
+       SET pins pointed at them, then put PINCTRL back */
+    fn set_over_pins(&mut self, destination: u16, base: u8, count: u8, values: u32) {
+        let pinctrl = self.hw().pinctrl.read();
+        let mut pin = 0;
+        while pin < count {
+            let chunk = (count - pin).min(5);
+            self.hw().pinctrl.write(
+                PINCTRL_SET_BASE.bits((base + pin) as u32) | PINCTRL_SET_COUNT.bits(chunk as u32),
+            );
+            let bits = (values >> pin) as u16 & ((1 << chunk) - 1);
+            self.exec(0xe000 | destination << 5 | bits);
+            pin += chunk;
+        }
+        self.hw().pinctrl.write(pinctrl);
+    }
+
+    /* Pins base..base+count as outputs (or inputs) for this state machine */
+    pub fn set_pin_dirs(&mut self, base: u8, count: u8, output: bool) {
+        self.set_over_pins(4, base, count, if output { u32::MAX } else { 0 });
+    }
+
+    /* Drive pins base..base+count, bit 0 of levels goes to base */
+    pub fn set_pins(&mut self, base: u8, count: u8, levels: u32) {
+        self.set_over_pins(0, base, count, levels);
+    }
+
+    pub fn is_tx_full(&self) -> bool {
+        pio(N).fstat.read_field(FSTAT_TXFULL) & (1 << self.index) != 0
+    }
+
+    pub fn is_tx_empty(&self) -> bool {
+        pio(N).fstat.read_field(FSTAT_TXEMPTY) & (1 << self.index) != 0
+    }
+
+    pub fn is_rx_empty(&self) -> bool {
+        pio(N).fstat.read_field(FSTAT_RXEMPTY) & (1 << self.index) != 0
+    }
+
+    /* Push a word into TX, waiting for room */
+    pub fn put(&mut self, word: u32) {
+        while self.is_tx_full() {}
+        pio(N).txf[self.index].write(word);
+    }
+
+    pub fn try_put(&mut self, word: u32) -> bool {
+        if self.is_tx_full() {
+            return false;
+        }
+        pio(N).txf[self.index].write(word);
+        true
+    }
+
+    /* Pop a word from RX, waiting for one */
+    pub fn get(&mut self) -> u32 {
+        while self.is_rx_empty() {}
+        pio(N).rxf[self.index].read()
+    }
+
+    pub fn try_get(&mut self) -> Option<u32> {
+        if self.is_rx_empty() {
+            None
+        } else {
+            Some(pio(N).rxf[self.index].read())
+        }
+    }
+
+    /* DMA into the TX FIFO (word transfers) */
+    pub fn dma_tx(&self) -> Target {
+        Target {
+            addr: &pio(N).txf[self.index] as *const Wo as u32,
+            dreq: dma::DREQ_PIO_TX[N] + self.index as u8,
+        }
+    }
+
+    /* DMA out of the RX FIFO */
+    pub fn dma_rx(&self) -> Target {
+        Target {
+            addr: &pio(N).rxf[self.index] as *const Ro as u32,
+            dreq: dma::DREQ_PIO_RX[N] + self.index as u8,
+        }
+    }
+}
+
+/* Interrupt: clear the IRQ flags that were set and call their handlers */
+fn pio_irq(index: usize) {
+    let flags = pio(index).irq0_ints.read_field(INT_SM);
+    pio(index).irq.write(flags);
+    for flag in 0..STATE_MACHINES {
+        if flags & (1 << flag) == 0 {
+            continue;
+        }
+        match unsafe { (*addr_of_mut!(HANDLERS))[index][flag] } {
+            Some(handler) => handler(flag),
+            None => pio(index).irq0_inte.clear(INT_SM.bits(1 << flag)),
+        }
+    }
+}
+
+/* Interrupt handlers for PIO0_IRQ_0 and PIO1_IRQ_0 */
+#[no_mangle]
+pub extern "C" fn pio0Irq0() {
+    pio_irq(0);
+}
+
+#[no_mangle]
+pub extern "C" fn pio1Irq0() {
+    pio_irq(1);
+}
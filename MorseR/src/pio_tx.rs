@@ -0,0 +1,211 @@
+use core::ptr::{addr_of, addr_of_mut, read_volatile};
+
+use crate::dma::{self, Channel};
+use crate::gpio::{Disabled, Pin};
+use crate::hw::free;
+use crate::morse::Element;
+use crate::pio::{Config, Join, Loaded, Pio, StateMachine, Status};
+use crate::pio_keyer::{self, KEY_LINE, KEY_LINE_UNIT_US, TONE};
+use crate::sidetone::SidetoneMode;
+use crate::timing::Timing;
+
+/* PIO transmit engine */
+/* The job tx.rs does, without the CPU: a PIO0 state machine runs the keyer
+   program from pio_keyer and times every element itself, so keying is exact
+   to the microsecond and the CPU can sleep for the whole message.
+   1. init() loads pio_keyer::TONE (or KEY_LINE for a silent key line), gives
+      the program the speaker (side-set) and LED (OUT) pins and starts it.
+   2. enqueue_text() turns a message into one word per element and a DMA
+      channel feeds them to the state machine's TX FIFO as it takes them.
+   3. The program raises its IRQ flag once the FIFO has run dry, the
+      completion callback is then called from the PIO interrupt.
+   4. abort() stops the DMA, restarts the program and keys up.
+   One message at a time: enqueue_text() fails while one is being sent. The
+   tone is a square wave keyed hard on and off, there is no envelope. */
+
+/* Words (elements), a long memory is around 300 */
+pub const QUEUE_LEN: usize = 512;
+
+/* Called from the PIO interrupt once the message has been sent */
+pub type CompleteFn = fn();
+
+struct Engine {
+    pio: Pio<0>,
+    sm: StateMachine<0>,
+    loaded: Loaded,
+    config: Config,
+    channel: Channel,
+    unit_us: u32,       /* Half a tone period, or KEY_LINE_UNIT_US */
+    timing: Timing,
+    speaker: u8,
+    led: u8,
+    complete: Option<CompleteFn>,
+}
+
+static mut ENGINE: Option<Engine> = None;
+
+/* Message being fed to the FIFO by DMA */
+static mut WORDS: [u32; QUEUE_LEN] = [0; QUEUE_LEN];
+
+/* A message or element is playing */
+static mut BUSY: bool = false;
+
+impl Engine {
+    /* Program from the top, FIFOs empty and both pins low, waiting for elements */
+    fn restart(&mut self) {
+        self.sm.init(&self.loaded, &self.config);
+        self.sm.set_pins(self.led, 1, 0);
+        self.sm.set_pins(self.speaker, 1, 0);
+        self.sm.put(pio_keyer::delay(self.unit_us));
+        self.sm.start();
+    }
+}
+
+/* IRQ flag from the program: the FIFO ran dry. It is also raised once after a
+   restart, before anything was queued. */
+fn fifo_empty(_flag: usize) {
+    unsafe {
+        let Some(engine) = &mut *addr_of_mut!(ENGINE) else {
+            return;
+        };
+        if *addr_of!(BUSY) && !engine.channel.is_busy() {
+            *addr_of_mut!(BUSY) = false;
+            if let Some(complete) = engine.complete {
+                complete();
+            }
+        }
+    }
+}
+
+/* Take over PIO0, a DMA channel and the two pins and start the keyer program
+   for mode and timing. False if no DMA channel is free. */
+pub fn init<const SPEAKER: u32, const LED: u32>(
+    speaker: Pin<SPEAKER, Disabled>,
+    led: Pin<LED, Disabled>,
+    mode: SidetoneMode,
+    timing: Timing,
+) -> bool {
+    let Some(channel) = dma::claim() else {
+        return false;
+    };
+    let mut pio = Pio::<0>::new();
+    let (program, unit_us) = match mode {
+        SidetoneMode::Tone(freq_hz) => (&TONE, pio_keyer::tone_unit_us(freq_hz)),
+        SidetoneMode::KeyLine => (&KEY_LINE, KEY_LINE_UNIT_US),
+    };
+    /* A fresh block has room for either program and four state machines */
+    let (Ok(loaded), Some(mut sm)) = (pio.load(program), pio.claim()) else {
+        channel.release();
+        return false;
+    };
+    pio.pin(speaker);
+    pio.pin(led);
+    sm.set_pin_dirs(SPEAKER as u8, 1, true);
+    sm.set_pin_dirs(LED as u8, 1, true);
+
+    /* LED on OUT, speaker on side-set, "mov y, status" tells when the FIFO is empty */
+    let mut config = Config::new();
+    config.clock_hz = pio_keyer::CLOCK_HZ;
+    config.out_base = LED as u8;
+    config.out_count = 1;
+    config.sideset_base = SPEAKER as u8;
+    config.status = Status::TxBelow(1);
+    config.join = Join::Tx;
+
+    free(|| unsafe {
+        *addr_of_mut!(BUSY) = false;
+        let engine = (*addr_of_mut!(ENGINE)).insert(Engine {
+            pio,
+            sm,
+            loaded,
+            config,
+            channel,
+            unit_us,
+            timing,
+            speaker: SPEAKER as u8,
+            led: LED as u8,
+            complete: None,
+        });
+        engine.restart();
+        let flag = engine.sm.index();
+        engine.pio.set_handler(flag, Some(fifo_empty));
+    });
+    true
+}
+
+/* New speed, used from the next message on */
+pub fn set_timing(timing: Timing) {
+    free(|| unsafe {
+        if let Some(engine) = &mut *addr_of_mut!(ENGINE) {
+            engine.timing = timing;
+        }
+    });
+}
+
+pub fn set_on_complete(complete: Option<CompleteFn>) {
+    free(|| unsafe {
+        if let Some(engine) = &mut *addr_of_mut!(ENGINE) {
+            engine.complete = complete;
+        }
+    });
+}
+
+/* Play one element (a keyer element from the paddles) */
+pub fn enqueue(element: Element) -> bool {
+    free(|| unsafe {
+        let Some(engine) = &mut *addr_of_mut!(ENGINE) else {
+            return false;
+        };
+        if engine.channel.is_busy() {
+            return false;
+        }
+        let word = pio_keyer::element_word(element, &engine.timing, engine.unit_us);
+        if !engine.sm.try_put(word) {
+            return false;
+        }
+        *addr_of_mut!(BUSY) = true;
+        true
+    })
+}
+
+/* Send a message, followed by a word gap like tx::enqueue_text(). False if
+   one is still being sent or it is longer than QUEUE_LEN elements. */
+pub fn enqueue_text(text: &str) -> bool {
+    free(|| unsafe {
+        let Some(engine) = &mut *addr_of_mut!(ENGINE) else {
+            return false;
+        };
+        if *addr_of!(BUSY) {
+            return false;
+        }
+        let words = &mut *addr_of_mut!(WORDS);
+        let mut count = 0;
+        for word in pio_keyer::words(text, engine.timing, engine.unit_us) {
+            if count == QUEUE_LEN {
+                return false;
+            }
+            words[count] = word;
+            count += 1;
+        }
+        *addr_of_mut!(BUSY) = true;
+        let words: &'static [u32; QUEUE_LEN] = &*addr_of!(WORDS);
+        engine.channel.send(&words[..count], engine.sm.dma_tx());
+        true
+    })
+}
+
+/* Stop sending now: drop what is queued and key up, without the callback */
+pub fn abort() {
+    free(|| unsafe {
+        if let Some(engine) = &mut *addr_of_mut!(ENGINE) {
+            engine.channel.abort();
+            engine.restart();
+            *addr_of_mut!(BUSY) = false;
+        }
+    });
+}
+
+/* Nothing queued or playing */
+pub fn is_idle() -> bool {
+    unsafe { !read_volatile(addr_of!(BUSY)) }
+}
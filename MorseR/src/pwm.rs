@@ -0,0 +1,135 @@
+use crate::dma::{self, Target};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+
+/* PWM registers */
+/* Eight slices, each with two channels (A on even GPIOs, B on odd GPIOs) */
+#[repr(C)]
+pub struct PwmSliceHw {
+    pub csr: RwAlias,    /* Control and status */
+    pub div: RwAlias,    /* Clock divider, 8.4 fixed point */
+    pub ctr: RwAlias,    /* Counter */
+    pub cc: RwAlias,     /* Compare values: channel A in bits 0-15, channel B in bits 16-31 */
+    pub top: RwAlias,    /* Counter wrap value */
+}
+
+#[repr(C)]
+pub struct PwmHw {
+    pub slice: [PwmSliceHw; 8],
+    pub en: RwAlias,     /* Enable bits for all slices at once */
+    pub intr: RwAlias,   /* Raw wrap interrupts */
+    pub inte: RwAlias,   /* Wrap interrupt enable */
+    pub intf: RwAlias,   /* Wrap interrupt force */
+    pub ints: Ro,        /* Wrap interrupt status */
+}
+
+const PWM_BASE: u32 = 0x40050000;
+
+#[inline(always)]
+fn pwm() -> &'static PwmHw {
+    block(PWM_BASE)
+}
+
+/* Register fields */
+const CSR_EN: Field<bool> = bit(0);
+const CC_A: Field = Field::new(0, 16);
+const CC_B: Field = Field::new(16, 16);
+const DIV_8_4: Field = Field::new(0, 12);   /* INT in bits 4-11, FRAC in bits 0-3 */
+const TOP: Field = Field::new(0, 16);
+
+/* Largest counter value. TOP is 16 bits, but the compare level is too, so one
+   below the limit leaves room for a level of top + 1 (output always high). */
+const MAX_TOP: u32 = 0xfffe;
+
+/* One PWM output pin */
+pub struct Pwm {
+    pin: u32,           /* GPIO number */
+    slice: usize,       /* Slice driving the pin */
+    channel_b: bool,    /* Odd pins are channel B */
+    top: u32,           /* Current wrap value, the counter runs 0..=top */
+}
+
+/* Take the PWM block out of reset, call once before using any Pwm */
+pub fn init() {
+    unreset(RESET_PWM);
+}
+
+impl Pwm {
+    /* Work out slice and channel for a GPIO, nothing touches the hardware yet */
+    pub const fn new(pin: u32) -> Self {
+        Pwm {
+            pin,
+            slice: ((pin >> 1) & 7) as usize,
+            channel_b: pin & 1 != 0,
+            top: MAX_TOP,
+        }
+    }
+
+    /* Route the GPIO to its PWM slice, output starts low */
+    pub fn connect(&self) {
+        self.set_level(0);
+        io().gpio[self.pin as usize].ctrl.write(GPIO_CTRL_FUNCSEL.bits(Function::Pwm));
+    }
+
+    /* Set the output frequency for a given input clock
+       1. Divider (8.4 fixed point) is the smallest that lets TOP fit in 16 bits,
+          so the duty cycle gets as much resolution as possible
+       2. TOP = clk / (div * freq) - 1
+       Returns the wrap value, a level of top + 1 keeps the output high */
+    pub fn set_freq(&mut self, clk_hz: u32, freq_hz: u32) -> u32 {
+        /* Work in 1/16ths of the divider so the fractional part comes for free.
+           64-bit maths: clk * 16 and freq * 65536 overflow u32 at full clock */
+        let clk16 = clk_hz as u64 * 16;
+        let freq = freq_hz.max(1) as u64;
+        let per_count = freq * (MAX_TOP as u64 + 1);
+        let div16 = clk16.div_ceil(per_count).clamp(16, 0xfff);
+        let counts = (clk16 / (div16 * freq)).clamp(2, MAX_TOP as u64 + 1);
+        self.top = counts as u32 - 1;
+        let div16 = div16 as u32;
+
+        let slice = &pwm().slice[self.slice];
+        slice.div.write(DIV_8_4.bits(div16));
+        slice.top.write(TOP.bits(self.top));
+        self.top
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin
+    }
+
+    /* Wrap value from the last set_freq */
+    pub fn top(&self) -> u32 {
+        self.top
+    }
+
+    /* Set the compare level: 0 = always low, above top = always high */
+    pub fn set_level(&self, level: u32) {
+        /* Only this channel's half changes, the XOR alias leaves the other channel alone */
+        let channel = if self.channel_b { CC_B } else { CC_A };
+        pwm().slice[self.slice].cc.write_field_atomic(channel, level.min(0xffff));
+    }
+
+    /* Square wave: 50% duty cycle */
+    pub fn set_half(&self) {
+        self.set_level(self.top.div_ceil(2));
+    }
+
+    /* DMA to the compare register, one level per counter wrap (PWM audio: the
+       sample rate is the PWM frequency). The APB replicates a halfword write
+       into both halves of CC, so 16-bit transfers set the other channel of
+       the slice too, use them only when it is free. */
+    pub fn dma_target(&self) -> Target {
+        Target {
+            addr: &pwm().slice[self.slice].cc as *const RwAlias as u32,
+            dreq: dma::DREQ_PWM_WRAP + self.slice as u8,
+        }
+    }
+
+    pub fn enable(&self) {
+        pwm().slice[self.slice].csr.set(CSR_EN.mask());
+    }
+
+    pub fn disable(&self) {
+        pwm().slice[self.slice].csr.clear(CSR_EN.mask());
+    }
+}
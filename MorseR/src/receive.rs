@@ -0,0 +1,264 @@
+use core::arch::asm;
+use core::ptr::addr_of_mut;
+
+use core::fmt::Write;
+
+use crate::adc::{self, Adc};
+use crate::decoder::{Decoder, Symbol};
+use crate::gpio::{Output, Pin, Pins, Pull};
+use crate::hw::*;
+use crate::i2c::I2c;
+use crate::ladder::{Ladder, Level};
+use crate::lcd::{self, Lcd, LineView};
+use crate::morse::Charset;
+use crate::timer::{self, delay_ms, time_us};
+use crate::uart::{Config, Uart};
+use crate::watchdog;
+
+/* Pin definitions (see README, Receiver Pico) */
+const LED_PIN: u32 = 25;       /* Onboard LED, mirrors the key */
+
+/* Buttons on ADC1 (GPIO27), a resistor ladder against a 10k pull-up to 3V3:
+   the key shorts the pin to ground, Mode pulls it down through 4.7k and
+   Menu through 22k. The internal pull-up stays on so a plain key button on
+   its own still works. It is 50-80k, taken as 65k: in parallel with the 10k
+   that makes 8.67k, and the ends of its range move a level by under 40 counts. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RxButton {
+    Key,        /* Straight key into the decoder */
+    Mode,       /* Next character set */
+    Menu,       /* Clear the text line and restart the decoder */
+}
+
+static LEVELS: [Level<RxButton>; 3] = [
+    Level { button: RxButton::Key, reading: 0 },
+    Level { button: RxButton::Mode, reading: 1440 },    /* 4095 * 4.7k / 13.37k */
+    Level { button: RxButton::Menu, reading: 2938 },    /* 4095 * 22k / 30.67k */
+];
+
+const LADDER_HYSTERESIS: u16 = 100;
+const LADDER_DEBOUNCE_US: u32 = 5_000;
+const LADDER_SAMPLE_HZ: u32 = 1_000;
+
+/* Watchdog: the main loop has to come round within this */
+const WATCHDOG_TIMEOUT_US: u32 = 2_000_000;
+
+/* Chip temperature on the top LCD line, read this often */
+const TEMP_INTERVAL_US: u32 = 5_000_000;
+
+/* Debug console on UART0 (TX GPIO0, RX GPIO1), decoded text is echoed to it */
+const CONSOLE_BAUD: u32 = 115_200;
+
+/* Speed guess before the decoder has adapted */
+const START_WPM: u32 = 12;
+
+/* LCD1602 on I2C0 (SDA GPIO4, SCL GPIO5) */
+const LCD_I2C_BAUD: u32 = 100_000;
+
+/* Shared between the key interrupt and the main loop */
+static mut DECODER: Decoder = Decoder::new(START_WPM);
+
+/* ADC, ladder and LED, moved here once main has configured them */
+static mut ADC: Option<Adc> = None;
+static mut LADDER: Ladder<RxButton> = Ladder::new(&LEVELS, LADDER_HYSTERESIS, LADDER_DEBOUNCE_US);
+static mut LED: Option<Pin<LED_PIN, Output>> = None;
+static mut CONSOLE: Option<Uart<0>> = None;
+
+/* Mode and Menu presses, handled by the main loop */
+static mut MODE_PRESSED: bool = false;
+static mut MENU_PRESSED: bool = false;
+
+/* Last 16 decoded characters for the bottom LCD line, newest at the end */
+static mut RX_LINE: LineView = LineView::new();
+
+/* Shift a decoded character into the display line and echo it to the console */
+fn push_char(c: u8) {
+    unsafe {
+        (*addr_of_mut!(RX_LINE)).push(c);
+        if let Some(console) = &mut *addr_of_mut!(CONSOLE) {
+            console.try_write_byte(c);
+        }
+    }
+}
+
+/* ADC FIFO interrupt, one sample per millisecond. The ladder event carries
+   the time the button state first appeared, so the key timing the decoder
+   sees is not delayed by the debounce. */
+fn ladder_sample() {
+    unsafe {
+        let now = time_us();
+        let Some(adc) = &mut *addr_of_mut!(ADC) else {
+            return;
+        };
+        while let Some(sample) = adc.fifo_pop() {
+            if sample.error {
+                continue;
+            }
+            let Some(event) = (*addr_of_mut!(LADDER)).sample(sample.value, now) else {
+                continue;
+            };
+            match (event.button, event.pressed) {
+                (RxButton::Key, pressed) => {
+                    let decoder = &mut *addr_of_mut!(DECODER);
+                    if pressed {
+                        decoder.key_down(event.at);
+                    } else {
+                        decoder.key_up(event.at);
+                    }
+                    if let Some(led) = &mut *addr_of_mut!(LED) {
+                        if pressed {
+                            led.set_high();
+                        } else {
+                            led.set_low();
+                        }
+                    }
+                }
+                (RxButton::Mode, true) => MODE_PRESSED = true,
+                (RxButton::Menu, true) => MENU_PRESSED = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+/* Chip temperature in millidegrees. The ladder keeps the ADC free-running,
+   so it is stopped for the one-shot reads (about 35 us, the ladder misses
+   a sample at most) and the temperature results are drained from the FIFO
+   before it goes back to the buttons. */
+fn read_temperature(input: u8) -> Option<i32> {
+    free(|| unsafe {
+        let adc = (*addr_of_mut!(ADC)).as_mut()?;
+        adc.stop();
+        let millicelsius = adc.read_temperature();
+        adc.select(input);
+        adc.fifo_drain();
+        adc.start_free_running();
+        Some(millicelsius)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> ! {
+    unsafe {
+        /* Take IO Bank 0, the pads and the timer out of reset */
+        let pins = Pins::take().unwrap();
+        timer::init();
+
+        /* Button ladder on ADC1 (GPIO27), internal pull-up on (see LEVELS) */
+        let mut buttons = pins.gpio27.into_analog();
+        buttons.set_pull(Pull::Up);
+
+        /* Configure LED (GPIO25) */
+        let led = &mut *addr_of_mut!(LED);
+        *led = Some(pins.gpio25.into_output());
+
+        /* Console */
+        CONSOLE = Some(Uart::new(pins.gpio0, pins.gpio1, &Config::new(CONSOLE_BAUD)));
+
+        /* Why we were reset, kept in the watchdog scratch registers */
+        let reset = watchdog::check_reset();
+        if let Some(console) = &mut *addr_of_mut!(CONSOLE) {
+            let _ = write!(
+                console,
+                "RX reset: {} ({} watchdog resets)\r\n",
+                reset.name(),
+                watchdog::watchdog_resets()
+            );
+        }
+
+        /* LCD: top line shows the decoder speed, the bottom one the decoded text.
+           The receiver works without it, the console still gets the text. */
+        let i2c = I2c::<0>::new(pins.gpio4, pins.gpio5, LCD_I2C_BAUD);
+        let mut lcd = Lcd::new(i2c, lcd::ADDRESS).ok();
+        let mut shown_wpm = 0;
+        let mut shown_charset = Charset::Latin;
+        let mut shown_temp = None;
+        let mut temp = None;
+        let mut temp_at = time_us();
+
+        /* Sample the buttons free-running at 1 kHz, interrupt on every sample */
+        let ladder_input = adc::input(&buttons);
+        let mut converter = Adc::new();
+        converter.set_temperature_sensor(true);
+        converter.select(ladder_input);
+        converter.set_sample_rate(LADDER_SAMPLE_HZ);
+        converter.fifo_enable(1);
+        converter.set_handler(Some(ladder_sample));
+        let adc = &mut *addr_of_mut!(ADC);
+        *adc = Some(converter);
+        if let Some(adc) = adc {
+            adc.start_free_running();
+        }
+
+        /* Startup blink so we know the receiver image is running */
+        if let Some(led) = led {
+            led.set_high();
+            delay_ms(500);
+            led.set_low();
+
+            /* Reset reason: one blink for power-on, two for the RUN pin ... (see watchdog.rs) */
+            delay_ms(500);
+            for _ in 0..reset.blinks() {
+                led.set_high();
+                delay_ms(150);
+                led.set_low();
+                delay_ms(150);
+            }
+        }
+
+        /* Watchdog on from here, the loop below never sleeps and feeds it every pass */
+        watchdog::start(WATCHDOG_TIMEOUT_US, true);
+
+        /* Poll the decoder so the last character of a word is flushed
+           even when no further key press arrives */
+        loop {
+            watchdog::feed();
+            asm!("cpsid i");
+            let decoder = &mut *addr_of_mut!(DECODER);
+            decoder.poll(time_us());
+            while let Some(symbol) = decoder.read() {
+                match symbol {
+                    Symbol::Char(c) if c.is_ascii() => push_char(c as u8),
+                    Symbol::Char(_) | Symbol::Unknown => push_char(b'?'),
+                    Symbol::Prosign(name) => {
+                        push_char(b'<');
+                        name.bytes().for_each(push_char);
+                        push_char(b'>');
+                    }
+                    Symbol::Space => push_char(b' '),
+                }
+            }
+            if MODE_PRESSED {
+                MODE_PRESSED = false;
+                decoder.set_charset(decoder.charset().next());
+            }
+            if MENU_PRESSED {
+                MENU_PRESSED = false;
+                decoder.reset();
+                (*addr_of_mut!(RX_LINE)).clear();
+            }
+            let wpm = decoder.wpm();
+            let charset = decoder.charset();
+            asm!("cpsie i");
+
+            if temp.is_none() || time_us().wrapping_sub(temp_at) >= TEMP_INTERVAL_US {
+                temp = read_temperature(ladder_input).map(adc::celsius);
+                temp_at = time_us();
+            }
+
+            if let Some(lcd) = &mut lcd {
+                if wpm != shown_wpm || charset != shown_charset || temp != shown_temp {
+                    shown_wpm = wpm;
+                    shown_charset = charset;
+                    shown_temp = temp;
+                    let _ = lcd.set_cursor(0, 0);
+                    let _ = write!(lcd, "{:>2}WPM {}", wpm, charset.short_name());
+                    if let Some(celsius) = temp {
+                        let _ = write!(lcd, " {:>4}C", celsius);
+                    }
+                }
+                let _ = (*addr_of_mut!(RX_LINE)).draw(lcd, 1);
+            }
+        }
+    }
+}
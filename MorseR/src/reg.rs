@@ -0,0 +1,214 @@
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr;
+
+/* Volatile register access */
+/* Register blocks are #[repr(C)] structs of these cells, reached through a
+   &'static reference to their base address. Every access is a single volatile
+   load or store, so the compiler can't merge, elide or reorder them.
+   1. Ro: read only (status, input values)
+   2. Wo: write only (SIO set/clear strobes, write-1-to-clear acknowledges)
+   3. Rw: read, write and read-modify-write
+   4. RwAlias: an Rw register on an APB/AHB-Lite peripheral, which also has the
+      RP2040 atomic alias windows: +0x1000 XOR, +0x2000 SET, +0x3000 CLR.
+      SIO and the Cortex-M registers (NVIC, SysTick) don't have these. */
+
+/* Atomic alias offsets */
+const ALIAS_XOR: usize = 0x1000;
+const ALIAS_SET: usize = 0x2000;
+const ALIAS_CLR: usize = 0x3000;
+
+#[repr(transparent)]
+pub struct Ro(UnsafeCell<u32>);
+
+#[repr(transparent)]
+pub struct Wo(UnsafeCell<u32>);
+
+#[repr(transparent)]
+pub struct Rw(UnsafeCell<u32>);
+
+#[repr(transparent)]
+pub struct RwAlias(Rw);
+
+/* Register blocks only ever live at fixed MMIO addresses */
+unsafe impl Sync for Ro {}
+unsafe impl Sync for Wo {}
+unsafe impl Sync for Rw {}
+unsafe impl Sync for RwAlias {}
+
+impl Ro {
+    #[inline(always)]
+    pub fn read(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+
+    #[inline(always)]
+    pub fn read_field<T: FieldValue>(&self, field: Field<T>) -> T {
+        field.get(self.read())
+    }
+}
+
+impl Wo {
+    #[inline(always)]
+    pub fn write(&self, value: u32) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+}
+
+impl Rw {
+    #[inline(always)]
+    pub fn read(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+
+    #[inline(always)]
+    pub fn write(&self, value: u32) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+
+    /* Read-modify-write. Not atomic: an interrupt touching the same register
+       in between is lost, use the RwAlias set/clear where that matters */
+    #[inline(always)]
+    pub fn modify(&self, f: impl FnOnce(u32) -> u32) {
+        self.write(f(self.read()));
+    }
+
+    #[inline(always)]
+    pub fn read_field<T: FieldValue>(&self, field: Field<T>) -> T {
+        field.get(self.read())
+    }
+
+    /* Change one field, leaving the others alone (read-modify-write) */
+    #[inline(always)]
+    pub fn write_field<T: FieldValue>(&self, field: Field<T>, value: T) {
+        self.modify(|bits| (bits & !field.mask()) | field.bits(value));
+    }
+
+    #[inline(always)]
+    fn alias(&self, offset: usize) -> *mut u32 {
+        (self.0.get() as usize + offset) as *mut u32
+    }
+}
+
+impl RwAlias {
+    /* Atomically set the bits in mask (SET alias) */
+    #[inline(always)]
+    pub fn set(&self, mask: u32) {
+        unsafe { ptr::write_volatile(self.0.alias(ALIAS_SET), mask) }
+    }
+
+    /* Atomically clear the bits in mask (CLR alias) */
+    #[inline(always)]
+    pub fn clear(&self, mask: u32) {
+        unsafe { ptr::write_volatile(self.0.alias(ALIAS_CLR), mask) }
+    }
+
+    /* Atomically flip the bits in mask (XOR alias) */
+    #[inline(always)]
+    pub fn toggle(&self, mask: u32) {
+        unsafe { ptr::write_volatile(self.0.alias(ALIAS_XOR), mask) }
+    }
+
+    /* Change one field without a read-modify-write: the bits that differ are
+       flipped through the XOR alias, so concurrent changes to other fields survive */
+    #[inline(always)]
+    pub fn write_field_atomic<T: FieldValue>(&self, field: Field<T>, value: T) {
+        let current = self.0.read() & field.mask();
+        self.toggle(current ^ field.bits(value));
+    }
+}
+
+/* An RwAlias register can do everything a plain Rw register can */
+impl core::ops::Deref for RwAlias {
+    type Target = Rw;
+
+    #[inline(always)]
+    fn deref(&self) -> &Rw {
+        &self.0
+    }
+}
+
+/* Values a bitfield can hold: u32 for numbers, bool for single bits,
+   enums for function selects and modes */
+pub trait FieldValue: Copy {
+    fn into_bits(self) -> u32;
+    fn from_bits(bits: u32) -> Self;
+}
+
+impl FieldValue for u32 {
+    #[inline(always)]
+    fn into_bits(self) -> u32 {
+        self
+    }
+
+    #[inline(always)]
+    fn from_bits(bits: u32) -> Self {
+        bits
+    }
+}
+
+impl FieldValue for bool {
+    #[inline(always)]
+    fn into_bits(self) -> u32 {
+        self as u32
+    }
+
+    #[inline(always)]
+    fn from_bits(bits: u32) -> Self {
+        bits != 0
+    }
+}
+
+/* A bitfield inside a register: `width` bits starting at bit `shift` */
+pub struct Field<T: FieldValue = u32> {
+    shift: u32,
+    width: u32,
+    value: PhantomData<T>,
+}
+
+impl<T: FieldValue> Clone for Field<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: FieldValue> Copy for Field<T> {}
+
+impl<T: FieldValue> Field<T> {
+    pub const fn new(shift: u32, width: u32) -> Self {
+        Field {
+            shift,
+            width,
+            value: PhantomData,
+        }
+    }
+
+    /* Bits the field occupies */
+    #[inline(always)]
+    pub const fn mask(self) -> u32 {
+        (u32::MAX >> (32 - self.width)) << self.shift
+    }
+
+    /* Value shifted into place, for building a whole register value with | */
+    #[inline(always)]
+    pub fn bits(self, value: T) -> u32 {
+        (value.into_bits() << self.shift) & self.mask()
+    }
+
+    /* Extract the field from a register value */
+    #[inline(always)]
+    pub fn get(self, register: u32) -> T {
+        T::from_bits((register & self.mask()) >> self.shift)
+    }
+}
+
+/* A single bit field */
+pub const fn bit(shift: u32) -> Field<bool> {
+    Field::new(shift, 1)
+}
+
+/* Register block struct at a fixed base address */
+#[inline(always)]
+pub fn block<T>(base: u32) -> &'static T {
+    unsafe { &*(base as *const T) }
+}
@@ -0,0 +1,114 @@
+use crate::envelope::{Envelope, LEVEL_MAX};
+use crate::gpio::{Disabled, Pin};
+use crate::hw::*;
+use crate::pwm::Pwm;
+
+/* What the speaker pin does while the key is down */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidetoneMode {
+    Tone(u32),  /* Square wave at this frequency in Hz (400-1000 Hz sounds right) */
+    KeyLine,    /* Silent: the pin is a plain on/off key line (active buzzer, rig key input) */
+}
+
+/* Keyed output on the speaker pin (GPIO PIN). It owns the pin once initialised
+   and switches it between PWM and SIO itself when the mode changes. */
+pub struct Sidetone<const PIN: u32> {
+    mode: SidetoneMode,
+    pwm: Pwm,
+    envelope: Option<Envelope>,  /* Rise/fall shaping, None keys the tone hard on/off */
+    tick_cycles: u32,            /* SysTick reload for the envelope tick rate */
+}
+
+impl<const PIN: u32> Sidetone<PIN> {
+    pub const fn new(mode: SidetoneMode, envelope: Option<Envelope>) -> Self {
+        Sidetone {
+            mode,
+            pwm: Pwm::new(PIN),
+            envelope,
+            tick_cycles: 0,
+        }
+    }
+
+    /* Take over the pin and configure it for the current mode, key up.
+       The PWM block must be out of reset (pwm::init) for tone mode. */
+    pub fn init(&mut self, _pin: Pin<PIN, Disabled>, clk_hz: u32) {
+        self.configure(clk_hz);
+    }
+
+    fn configure(&mut self, clk_hz: u32) {
+        let pin = self.pwm.pin();
+        pads().gpio[pin as usize].clear(PADS_OD.mask());
+        if let Some(envelope) = &self.envelope {
+            self.tick_cycles = clk_hz / envelope.tick_hz();
+        }
+        match self.mode {
+            SidetoneMode::Tone(freq_hz) => {
+                self.pwm.set_freq(clk_hz, freq_hz);
+                self.pwm.connect();
+                self.pwm.enable();
+            }
+            SidetoneMode::KeyLine => {
+                self.pwm.disable();
+                sio().gpio_out_clr.write(1u32 << pin);
+                io().gpio[pin as usize].ctrl.write(GPIO_CTRL_FUNCSEL.bits(Function::Sio));
+                sio().gpio_oe_set.write(1u32 << pin);
+            }
+        }
+    }
+
+    /* Switch between tone and key line, or change the tone frequency */
+    pub fn set_mode(&mut self, mode: SidetoneMode, clk_hz: u32) {
+        self.mode = mode;
+        self.configure(clk_hz);
+    }
+
+    pub fn mode(&self) -> SidetoneMode {
+        self.mode
+    }
+
+    pub fn key_down(&mut self) {
+        self.key(true);
+    }
+
+    pub fn key_up(&mut self) {
+        self.key(false);
+    }
+
+    /* Step the envelope, call from the SysTick interrupt.
+       The duty cycle follows the envelope from 0 up to the 50% square wave,
+       SysTick is stopped again once the ramp has settled. */
+    pub fn tick(&mut self) {
+        if let Some(envelope) = &mut self.envelope {
+            let level = envelope.tick();
+            let half = self.pwm.top().div_ceil(2);
+            self.pwm.set_level(level * half / LEVEL_MAX);
+            if envelope.is_settled() {
+                systick_stop();
+            }
+        }
+    }
+
+    fn key(&mut self, down: bool) {
+        let pin = self.pwm.pin();
+        match (self.mode, &mut self.envelope) {
+            (SidetoneMode::Tone(_), Some(envelope)) => {
+                envelope.key(down);
+                systick_start(self.tick_cycles);
+            }
+            (SidetoneMode::Tone(_), None) => {
+                if down {
+                    self.pwm.set_half();
+                } else {
+                    self.pwm.set_level(0);
+                }
+            }
+            (SidetoneMode::KeyLine, _) => {
+                if down {
+                    sio().gpio_out_set.write(1u32 << pin);
+                } else {
+                    sio().gpio_out_clr.write(1u32 << pin);
+                }
+            }
+        }
+    }
+}
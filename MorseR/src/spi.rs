@@ -0,0 +1,500 @@
+use core::marker::PhantomData;
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::dma::{self, Target};
+use crate::gpio::{Disabled, Output, Pin};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, Rw, RwAlias, Wo};
+
+/* SPI (ARM PL022) */
+/* Two identical SPI blocks, Motorola frame format. Spi<N, Master> or
+   Spi<N, Slave> owns SPI block N and its pins:
+   1. Pins are checked at compile time: GPIO 4n is RX (MISO on a master),
+      4n+1 CSn, 4n+2 SCK and 4n+3 TX (MOSI on a master). SPI0 has GPIO 0-7
+      and 16-23, SPI1 GPIO 8-15 and 24-29.
+   2. SCK = clk_peri / (prescale * (1 + scr)), prescale even 2-254, scr 0-255.
+      set_baud() picks the smallest prescale that leaves scr in range, then the
+      fastest rate not above the one asked for, and returns it.
+   3. Frames are 4 to 16 bits (u8 buffers up to 8, u16 up to 16), in the four
+      CPOL/CPHA modes.
+   4. Both FIFOs are 8 frames deep. Every frame sent clocks one in, transfer()
+      keeps the TX FIFO topped up while draining RX so the bus never stalls.
+   5. Master: the hardware CSn pulses between frames in mode 0 and 2, which
+      most devices don't want, so chip selects are ordinary GPIO outputs (any
+      pin) held low for a whole transaction(). Each Device carries its own
+      Config, devices with different modes and rates share one bus.
+   6. Slave: the master drives SCK and CSn. Frames are preloaded into the TX
+      FIFO and what arrives is picked up with the try_ calls or the interrupt.
+      In modes 0 and 2 CSn has to go high between frames, a PL022 restriction.
+      clk_peri must be at least 12 times the SCK rate.
+   7. Interrupts (RX half full, TX half empty, RX timeout, RX overrun) go to a
+      handler set with set_handler(), called from SPI0_IRQ/SPI1_IRQ */
+
+#[repr(C)]
+pub struct SpiHw {
+    pub cr0: RwAlias,       /* Frame format, size, mode and serial clock rate */
+    pub cr1: RwAlias,       /* Enable, master/slave */
+    pub dr: Rw,             /* Data, writing pushes TX, reading pops RX */
+    pub sr: Ro,             /* Status: FIFO levels, busy */
+    pub cpsr: RwAlias,      /* Clock prescale, even 2-254 */
+    pub imsc: RwAlias,      /* Interrupt mask, 1 = enabled */
+    pub ris: Ro,            /* Raw interrupt status */
+    pub mis: Ro,            /* Masked interrupt status */
+    pub icr: Wo,            /* Interrupt clear, write 1 (overrun and timeout only) */
+    pub dmacr: RwAlias,     /* DMA enables */
+}
+
+const SPI_BASE: [u32; 2] = [0x4003c000, 0x40040000];
+const RESET_SPI: [u32; 2] = [16, 17];
+pub const SPI_IRQ: [u32; 2] = [18, 19];
+
+#[inline(always)]
+fn spi(index: usize) -> &'static SpiHw {
+    block(SPI_BASE[index])
+}
+
+/* CR0 fields */
+const CR0_DSS: Field = Field::new(0, 4);    /* Frame size - 1 */
+const CR0_FRF: Field = Field::new(4, 2);    /* 0 = Motorola */
+const CR0_SPO: Field<bool> = bit(6);        /* CPOL */
+const CR0_SPH: Field<bool> = bit(7);        /* CPHA */
+const CR0_SCR: Field = Field::new(8, 8);
+
+/* CR1 fields */
+const CR1_SSE: Field<bool> = bit(1);
+const CR1_MS: Field<bool> = bit(2);
+
+/* SR fields */
+const SR_TNF: Field<bool> = bit(1);
+const SR_RNE: Field<bool> = bit(2);
+const SR_BSY: Field<bool> = bit(4);
+
+/* DMACR fields */
+const DMACR_RXDMAE: Field<bool> = bit(0);
+const DMACR_TXDMAE: Field<bool> = bit(1);
+
+/* CPSR field */
+const CPSR_CPSDVSR: Field = Field::new(0, 8);
+
+/* Interrupt bits (IMSC, RIS, MIS, ICR) */
+pub const INT_OVERRUN: u32 = 1 << 0;    /* Frame arrived with the RX FIFO full */
+pub const INT_TIMEOUT: u32 = 1 << 1;    /* RX FIFO not empty and nothing new for 32 bit times */
+pub const INT_RX: u32 = 1 << 2;         /* RX FIFO half full or more */
+pub const INT_TX: u32 = 1 << 3;         /* TX FIFO half empty or more */
+const INT_ALL: u32 = 0xf;
+
+pub const FIFO_DEPTH: usize = 8;
+
+/* Roles */
+pub struct Master;
+pub struct Slave;
+
+/* Clock polarity and phase */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Mode0,      /* CPOL 0, CPHA 0: idle low, sample on the rising edge */
+    Mode1,      /* CPOL 0, CPHA 1: idle low, sample on the falling edge */
+    Mode2,      /* CPOL 1, CPHA 0: idle high, sample on the falling edge */
+    Mode3,      /* CPOL 1, CPHA 1: idle high, sample on the rising edge */
+}
+
+impl Mode {
+    const fn cpol(self) -> bool {
+        matches!(self, Mode::Mode2 | Mode::Mode3)
+    }
+
+    const fn cpha(self) -> bool {
+        matches!(self, Mode::Mode1 | Mode::Mode3)
+    }
+}
+
+/* Bus settings */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,          /* SCK rate in Hz, ignored by a slave */
+    pub mode: Mode,
+    pub bits: u8,           /* Frame size, 4 to 16 */
+}
+
+impl Config {
+    /* Mode 0, 8-bit frames at the given SCK rate */
+    pub const fn new(baud: u32) -> Self {
+        Config {
+            baud,
+            mode: Mode::Mode0,
+            bits: 8,
+        }
+    }
+}
+
+/* Frame buffer types: u8 for frames up to 8 bits, u16 up to 16 */
+pub trait Word: Copy {
+    const BITS: u8;
+    fn into_frame(self) -> u32;
+    fn from_frame(frame: u32) -> Self;
+}
+
+impl Word for u8 {
+    const BITS: u8 = 8;
+
+    #[inline(always)]
+    fn into_frame(self) -> u32 {
+        self as u32
+    }
+
+    #[inline(always)]
+    fn from_frame(frame: u32) -> Self {
+        frame as u8
+    }
+}
+
+impl Word for u16 {
+    const BITS: u8 = 16;
+
+    #[inline(always)]
+    fn into_frame(self) -> u32 {
+        self as u32
+    }
+
+    #[inline(always)]
+    fn from_frame(frame: u32) -> Self {
+        frame as u16
+    }
+}
+
+/* Called from the SPI interrupt with the masked interrupt status */
+pub type Handler = fn(status: u32);
+
+static mut HANDLERS: [Option<Handler>; 2] = [None; 2];
+
+pub struct Spi<const N: usize, ROLE> {
+    bits: u8,
+    role: PhantomData<ROLE>,
+}
+
+/* A device on a master's bus: its chip select (any GPIO, active low) and settings */
+pub struct Device<const CS: u32> {
+    cs: Pin<CS, Output>,
+    config: Config,
+}
+
+impl<const CS: u32> Device<CS> {
+    /* Chip select starts high (deselected), without a low pulse on the way */
+    pub fn new(cs: Pin<CS, Disabled>, config: Config) -> Self {
+        Device { cs: cs.into_output_high(), config }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+}
+
+/* Compile-time pin check, see the top of this file. FUNCTION is the pin's
+   place in its group of four: 0 RX, 1 CSn, 2 SCK, 3 TX. */
+struct PinCheck<const N: usize, const PIN: u32, const FUNCTION: u32>;
+
+impl<const N: usize, const PIN: u32, const FUNCTION: u32> PinCheck<N, PIN, FUNCTION> {
+    const OK: () = {
+        assert!(N < 2, "there are two SPI blocks");
+        assert!(PIN % 4 == FUNCTION, "pin can't be used for this SPI signal");
+        assert!((PIN / 8) % 2 == N as u32, "pin belongs to the other SPI block");
+    };
+}
+
+const RX: u32 = 0;
+const CSN: u32 = 1;
+const SCK: u32 = 2;
+const TX: u32 = 3;
+
+impl<const N: usize> Spi<N, Master> {
+    /* Master on SCK, TX (MOSI) and RX (MISO), the pins stay with the SPI for good */
+    pub fn master<const SCK_PIN: u32, const TX_PIN: u32, const RX_PIN: u32>(
+        sck: Pin<SCK_PIN, Disabled>,
+        tx: Pin<TX_PIN, Disabled>,
+        rx: Pin<RX_PIN, Disabled>,
+        config: &Config,
+    ) -> Self {
+        let _: () = PinCheck::<N, RX_PIN, RX>::OK;
+        let spi = Self::master_tx(sck, tx, config);
+        rx.into_function(Function::Spi);
+        spi
+    }
+
+    /* Master that only sends (a DAC, shift registers): no RX pin, reads give zeros */
+    pub fn master_tx<const SCK_PIN: u32, const TX_PIN: u32>(
+        sck: Pin<SCK_PIN, Disabled>,
+        tx: Pin<TX_PIN, Disabled>,
+        config: &Config,
+    ) -> Self {
+        let _: () = PinCheck::<N, SCK_PIN, SCK>::OK;
+        let _: () = PinCheck::<N, TX_PIN, TX>::OK;
+        let mut spi = Self::init(false);
+        spi.configure(config);
+        sck.into_function(Function::Spi);
+        tx.into_function(Function::Spi);
+        spi
+    }
+
+    /* SCK rate, see the top of this file. Returns the rate actually set. */
+    pub fn set_baud(&mut self, baud: u32) -> u32 {
+        let clk = clocks::peri_hz() as u64;
+        let baud = baud.max(1) as u64;
+        /* Smallest prescale where scr can bring the rate down to baud */
+        let mut prescale = 2;
+        while prescale < 254 && clk > prescale * 256 * baud {
+            prescale += 2;
+        }
+        /* Smallest divisor that doesn't go over baud */
+        let postdiv = clk.div_ceil(prescale * baud).clamp(1, 256);
+        self.hw().cpsr.write(CPSR_CPSDVSR.bits(prescale as u32));
+        self.hw().cr0.write_field(CR0_SCR, postdiv as u32 - 1);
+        (clk / (prescale * postdiv)) as u32
+    }
+
+    /* Rate, mode and frame size, waiting for the frame in flight first */
+    pub fn configure(&mut self, config: &Config) -> u32 {
+        self.flush();
+        self.set_format(config);
+        self.set_baud(config.baud)
+    }
+
+    /* Select the device, run f, wait for the last frame and deselect.
+       The bus is switched to the device's settings first. */
+    pub fn transaction<const CS: u32, R>(
+        &mut self,
+        device: &mut Device<CS>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        self.configure(&device.config);
+        device.cs.set_low();
+        let result = f(self);
+        self.flush();
+        device.cs.set_high();
+        result
+    }
+}
+
+impl<const N: usize> Spi<N, Slave> {
+    /* Slave on SCK, TX (MISO), RX (MOSI) and the CSn input. config.baud is ignored. */
+    pub fn slave<const SCK_PIN: u32, const TX_PIN: u32, const RX_PIN: u32, const CS_PIN: u32>(
+        sck: Pin<SCK_PIN, Disabled>,
+        tx: Pin<TX_PIN, Disabled>,
+        rx: Pin<RX_PIN, Disabled>,
+        cs: Pin<CS_PIN, Disabled>,
+        config: &Config,
+    ) -> Self {
+        let _: () = PinCheck::<N, SCK_PIN, SCK>::OK;
+        let _: () = PinCheck::<N, TX_PIN, TX>::OK;
+        let _: () = PinCheck::<N, RX_PIN, RX>::OK;
+        let _: () = PinCheck::<N, CS_PIN, CSN>::OK;
+        let mut spi = Self::init(true);
+        spi.set_format(config);
+        sck.into_function(Function::Spi);
+        tx.into_function(Function::Spi);
+        rx.into_function(Function::Spi);
+        cs.into_function(Function::Spi);
+        spi
+    }
+}
+
+impl<const N: usize, ROLE> Spi<N, ROLE> {
+    /* Out of reset, role set, then enabled (the role can only change while disabled) */
+    fn init(slave: bool) -> Self {
+        reset(RESET_SPI[N]);
+        unreset(RESET_SPI[N]);
+        spi(N).cr1.write(CR1_MS.bits(slave));
+        spi(N).cr1.set(CR1_SSE.mask());
+        Spi {
+            bits: 8,
+            role: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn hw(&self) -> &'static SpiHw {
+        spi(N)
+    }
+
+    /* Mode and frame size, SCR (the rate) is left alone. Only changed while
+       disabled, or the frame in progress is corrupted. */
+    fn set_format(&mut self, config: &Config) {
+        assert!(config.bits >= 4 && config.bits <= 16);
+        self.bits = config.bits;
+        self.hw().cr1.clear(CR1_SSE.mask());
+        self.hw().cr0.modify(|cr0| {
+            (cr0 & CR0_SCR.mask())
+                | CR0_DSS.bits(config.bits as u32 - 1)
+                | CR0_FRF.bits(0)
+                | CR0_SPO.bits(config.mode.cpol())
+                | CR0_SPH.bits(config.mode.cpha())
+        });
+        self.hw().cr1.set(CR1_SSE.mask());
+    }
+
+    /* Room in the TX FIFO */
+    #[inline(always)]
+    pub fn is_writable(&self) -> bool {
+        self.hw().sr.read_field(SR_TNF)
+    }
+
+    /* Frames in the RX FIFO */
+    #[inline(always)]
+    pub fn is_readable(&self) -> bool {
+        self.hw().sr.read_field(SR_RNE)
+    }
+
+    /* Frames still to go out, or one being shifted */
+    #[inline(always)]
+    pub fn is_busy(&self) -> bool {
+        self.hw().sr.read_field(SR_BSY)
+    }
+
+    /* Wait until the last frame has been shifted out */
+    pub fn flush(&self) {
+        while self.is_busy() {}
+    }
+
+    /* Push a frame if there is room */
+    pub fn try_write_word<W: Word>(&mut self, word: W) -> bool {
+        if !self.is_writable() {
+            return false;
+        }
+        self.hw().dr.write(word.into_frame());
+        true
+    }
+
+    /* Pop a received frame if there is one */
+    pub fn try_read_word<W: Word>(&mut self) -> Option<W> {
+        if self.is_readable() {
+            Some(W::from_frame(self.hw().dr.read()))
+        } else {
+            None
+        }
+    }
+
+    /* Full duplex: send each word and replace it with the one received.
+       Blocking, on a slave it waits for the master to clock everything. */
+    pub fn transfer<W: Word>(&mut self, words: &mut [W]) {
+        debug_assert!(self.bits <= W::BITS);
+        let (mut sent, mut received) = (0, 0);
+        while received < words.len() {
+            /* Never more in flight than RX can hold, or it overruns */
+            if sent < words.len() && sent - received < FIFO_DEPTH && self.is_writable() {
+                self.hw().dr.write(words[sent].into_frame());
+                sent += 1;
+            }
+            if let Some(word) = self.try_read_word() {
+                words[received] = word;
+                received += 1;
+            }
+        }
+    }
+
+    /* Send only, what comes back is dropped */
+    pub fn write<W: Word>(&mut self, words: &[W]) {
+        debug_assert!(self.bits <= W::BITS);
+        for &word in words {
+            while !self.try_write_word(word) {
+                self.drain();
+            }
+        }
+        self.flush();
+        self.drain();
+        self.hw().icr.write(INT_OVERRUN);
+    }
+
+    /* Receive only, sending `fill` for every word */
+    pub fn read<W: Word>(&mut self, fill: W, words: &mut [W]) {
+        words.fill(fill);
+        self.transfer(words);
+    }
+
+    /* Single frame exchange */
+    pub fn transfer_word<W: Word>(&mut self, word: W) -> W {
+        let mut words = [word];
+        self.transfer(&mut words);
+        words[0]
+    }
+
+    /* Empty the RX FIFO */
+    pub fn drain(&mut self) {
+        while self.try_read_word::<u16>().is_some() {}
+    }
+
+    /* A frame was lost to a full RX FIFO since the last call */
+    pub fn overrun(&mut self) -> bool {
+        let overrun = self.hw().ris.read() & INT_OVERRUN != 0;
+        self.hw().icr.write(INT_OVERRUN);
+        overrun
+    }
+
+    /* DMA into the TX FIFO, transfers as wide as the frame buffer type */
+    pub fn dma_tx(&mut self) -> Target {
+        self.hw().dmacr.set(DMACR_TXDMAE.mask());
+        Target {
+            addr: &self.hw().dr as *const Rw as u32,
+            dreq: dma::DREQ_SPI_TX[N],
+        }
+    }
+
+    /* DMA out of the RX FIFO */
+    pub fn dma_rx(&mut self) -> Target {
+        self.hw().dmacr.set(DMACR_RXDMAE.mask());
+        Target {
+            addr: &self.hw().dr as *const Rw as u32,
+            dreq: dma::DREQ_SPI_RX[N],
+        }
+    }
+
+    /* Handler for this SPI's interrupt, None to stop calling one */
+    pub fn set_handler(&mut self, handler: Option<Handler>) {
+        free(|| unsafe { (*addr_of_mut!(HANDLERS))[N] = handler });
+        if handler.is_some() {
+            nvic_enable(SPI_IRQ[N]);
+        } else {
+            nvic_disable(SPI_IRQ[N]);
+        }
+    }
+
+    /* Enable the INT_* interrupts in mask */
+    pub fn enable_interrupts(&mut self, mask: u32) {
+        self.hw().imsc.set(mask & INT_ALL);
+    }
+
+    pub fn disable_interrupts(&mut self, mask: u32) {
+        self.hw().imsc.clear(mask & INT_ALL);
+    }
+
+    /* Acknowledge INT_OVERRUN and INT_TIMEOUT. The FIFO level interrupts
+       clear themselves once the FIFO is read or written past half. */
+    pub fn clear_interrupts(&mut self, mask: u32) {
+        self.hw().icr.write(mask & (INT_OVERRUN | INT_TIMEOUT));
+    }
+}
+
+/* Interrupt: hand the status to the handler, overrun and timeout are
+   acknowledged here so an unhandled one can't keep firing */
+fn spi_irq(index: usize) {
+    let status = spi(index).mis.read();
+    spi(index).icr.write(status & (INT_OVERRUN | INT_TIMEOUT));
+    match unsafe { (*addr_of_mut!(HANDLERS))[index] } {
+        Some(handler) => handler(status),
+        None => spi(index).imsc.clear(INT_ALL),
+    }
+}
+
+/* Interrupt handlers for SPI0_IRQ and SPI1_IRQ */
+#[no_mangle]
+pub extern "C" fn spi0Irq() {
+    spi_irq(0);
+}
+
+#[no_mangle]
+pub extern "C" fn spi1Irq() {
+    spi_irq(1);
+}
@@ -10,6 +10,8 @@ extern "C" {
     static _edata: u32;     // End of .data section (in RAM)
     static _sbss: u32;      // Start of .bss section (in RAM)
     static _ebss: u32;      // End of .bss section (in RAM)
+    // Clock tree setup, provided by the application (clocks.rs)
+    fn clocksInit();
     // External main function
     fn main();
     // External interrupt handler
@@ -53,6 +55,9 @@ pub extern "C" fn resetHandler() -> ! {
         let vtor = 0xE000ED08 as *mut u32;
         ptr::write_volatile(vtor, 0x10000100);  
 
+        // Crystal and PLLs up before main, data and bss are ready by now
+        clocksInit();
+
         // Call main function
         main();
        
@@ -94,13 +99,97 @@ pub extern "C" fn sysTickHandler() -> ! {
     defaultHandler()
 }
 
-// Wrapper for GPIO IRQ handler
+// Timer alarm interrupts, overridden by the application (timer.rs).
+// Peripheral handlers return like ioIrqBank0Handler, so they go in the table as isr.
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn timerIrq0() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn timerIrq1() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn timerIrq2() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn timerIrq3() {
+    defaultHandler()
+}
+
+// PIO interrupts, overridden by the application (pio.rs)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn pio0Irq0() {
+    defaultHandler()
+}
+
 #[no_mangle]
-pub extern "C" fn ioIrqBank0Handler() -> ! {
+#[linkage = "weak"]
+pub extern "C" fn pio1Irq0() {
+    defaultHandler()
+}
+
+// DMA interrupts, overridden by the application (dma.rs)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn dmaIrq0() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn dmaIrq1() {
+    defaultHandler()
+}
+
+// SPI interrupts, overridden by the application (spi.rs)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn spi0Irq() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn spi1Irq() {
+    defaultHandler()
+}
+
+// UART interrupts, overridden by the application (uart.rs)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn uart0Irq() {
+    defaultHandler()
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn uart1Irq() {
+    defaultHandler()
+}
+
+// ADC FIFO interrupt, overridden by the application (adc.rs)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn adcIrqFifo() {
+    defaultHandler()
+}
+
+// Wrapper for GPIO IRQ handler - returns so the next edge can be taken
+#[no_mangle]
+pub extern "C" fn ioIrqBank0Handler() {
     unsafe {
         ioIrqBank0();
     }
-    defaultHandler()
 }
 
 // Define the type for vector table entries
@@ -108,6 +197,7 @@ pub extern "C" fn ioIrqBank0Handler() -> ! {
 #[derive(Copy, Clone)]
 union VectorTableEntry {
     handler: unsafe extern "C" fn() -> !,
+    isr: unsafe extern "C" fn(),
     reserved: u32,
     stack_top: *const u32,
 }
@@ -143,29 +233,29 @@ pub static VECTOR_TABLE: [VectorTableEntry; 48] = [
     VectorTableEntry { handler: sysTickHandler },
     
     // Peripheral IRQs - RP2040 has 32 IRQs
-    VectorTableEntry { handler: defaultHandler }, // IRQ0
-    VectorTableEntry { handler: defaultHandler }, // IRQ1
-    VectorTableEntry { handler: defaultHandler }, // IRQ2
-    VectorTableEntry { handler: defaultHandler }, // IRQ3
+    VectorTableEntry { isr: timerIrq0 }, // IRQ0 (TIMER_IRQ_0)
+    VectorTableEntry { isr: timerIrq1 }, // IRQ1 (TIMER_IRQ_1)
+    VectorTableEntry { isr: timerIrq2 }, // IRQ2 (TIMER_IRQ_2)
+    VectorTableEntry { isr: timerIrq3 }, // IRQ3 (TIMER_IRQ_3)
     VectorTableEntry { handler: defaultHandler }, // IRQ4
     VectorTableEntry { handler: defaultHandler }, // IRQ5
     VectorTableEntry { handler: defaultHandler }, // IRQ6
-    VectorTableEntry { handler: defaultHandler }, // IRQ7
+    VectorTableEntry { isr: pio0Irq0 }, // IRQ7 (PIO0_IRQ_0)
     VectorTableEntry { handler: defaultHandler }, // IRQ8
-    VectorTableEntry { handler: defaultHandler }, // IRQ9
+    VectorTableEntry { isr: pio1Irq0 }, // IRQ9 (PIO1_IRQ_0)
     VectorTableEntry { handler: defaultHandler }, // IRQ10
-    VectorTableEntry { handler: defaultHandler }, // IRQ11
-    VectorTableEntry { handler: defaultHandler }, // IRQ12
-    VectorTableEntry { handler: ioIrqBank0Handler }, // IRQ13 (IO_BANK0)
+    VectorTableEntry { isr: dmaIrq0 }, // IRQ11 (DMA_IRQ_0)
+    VectorTableEntry { isr: dmaIrq1 }, // IRQ12 (DMA_IRQ_1)
+    VectorTableEntry { isr: ioIrqBank0Handler }, // IRQ13 (IO_BANK0)
     VectorTableEntry { handler: defaultHandler }, // IRQ14
     VectorTableEntry { handler: defaultHandler }, // IRQ15
     VectorTableEntry { handler: defaultHandler }, // IRQ16
     VectorTableEntry { handler: defaultHandler }, // IRQ17
-    VectorTableEntry { handler: defaultHandler }, // IRQ18
-    VectorTableEntry { handler: defaultHandler }, // IRQ19
-    VectorTableEntry { handler: defaultHandler }, // IRQ20
-    VectorTableEntry { handler: defaultHandler }, // IRQ21
-    VectorTableEntry { handler: defaultHandler }, // IRQ22
+    VectorTableEntry { isr: spi0Irq }, // IRQ18 (SPI0_IRQ)
+    VectorTableEntry { isr: spi1Irq }, // IRQ19 (SPI1_IRQ)
+    VectorTableEntry { isr: uart0Irq }, // IRQ20 (UART0)
+    VectorTableEntry { isr: uart1Irq }, // IRQ21 (UART1)
+    VectorTableEntry { isr: adcIrqFifo }, // IRQ22 (ADC_IRQ_FIFO)
     VectorTableEntry { handler: defaultHandler }, // IRQ23
     VectorTableEntry { handler: defaultHandler }, // IRQ24
     VectorTableEntry { handler: defaultHandler }, // IRQ25
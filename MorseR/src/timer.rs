@@ -0,0 +1,127 @@
+use core::ptr::addr_of_mut;
+
+use crate::hw::*;
+use crate::watchdog;
+
+/* 64-bit microsecond timer */
+/* The TIMER counts 1 us ticks from the watchdog tick generator and never stops:
+   1. now() reads all 64 bits through the latched TIMELR/TIMEHR pair: reading
+      TIMELR latches TIMEHR, so the two halves always belong together
+   2. time_us() is the raw low word, cheap and fine for differences up to ~71 minutes
+   3. delay_us()/delay_ms() busy-wait on the counter, they don't depend on clk_sys
+   4. Four alarms compare the low 32 bits against a target and raise TIMER_IRQ_0..3.
+      An alarm fires once and calls its handler from the interrupt, the handler can
+      arm it again for periodic work. */
+
+pub const ALARMS: usize = 4;
+
+/* Called from the alarm interrupt with the alarm number */
+pub type AlarmHandler = fn(alarm: usize);
+
+static mut HANDLERS: [Option<AlarmHandler>; ALARMS] = [None; ALARMS];
+
+/* Start the timer:
+   1. Take TIMER out of reset
+   2. The timer counts watchdog ticks: divide clk_ref down to 1 MHz */
+pub fn init() {
+    unreset(RESET_TIMER);
+    watchdog::start_tick();
+}
+
+/* Full 64-bit time. The latch is shared, so an interrupt reading it between
+   our two reads would pair our TIMEHR with its TIMELR: keep them together. */
+pub fn now() -> u64 {
+    free(|| {
+        let low = timer().timelr.read();
+        let high = timer().timehr.read();
+        ((high as u64) << 32) | low as u64
+    })
+}
+
+/* Lower 32 bits of the microsecond timer (raw read, wraps every ~71 minutes) */
+#[inline(always)]
+pub fn time_us() -> u32 {
+    timer().timerawl.read()
+}
+
+pub fn delay_us(us: u32) {
+    let start = time_us();
+    while time_us().wrapping_sub(start) < us {}
+}
+
+/* 64-bit target so long delays don't wrap */
+pub fn delay_ms(ms: u32) {
+    let until = now() + ms as u64 * 1000;
+    while now() < until {}
+}
+
+/* Arm alarm to call handler once the low 32 bits of the timer reach target.
+   A target already in the past fires straight away instead of after the
+   counter wraps round to it. */
+pub fn alarm_at(alarm: usize, target: u32, handler: AlarmHandler) {
+    let mask = 1u32 << alarm;
+    free(|| {
+        unsafe { (*addr_of_mut!(HANDLERS))[alarm] = Some(handler) };
+        timer().intr.write(mask);
+        timer().inte.set(mask);
+        timer().alarm[alarm].write(target);
+        if target.wrapping_sub(time_us()) as i32 <= 0 && timer().armed.read() & mask != 0 {
+            timer().armed.write(mask);
+            timer().intf.set(mask);
+        }
+    });
+    nvic_enable(TIMER_IRQ_0 + alarm as u32);
+}
+
+/* Arm alarm to call handler us microseconds from now */
+pub fn alarm_in(alarm: usize, us: u32, handler: AlarmHandler) {
+    alarm_at(alarm, time_us().wrapping_add(us), handler);
+}
+
+/* Disarm alarm, its handler won't be called */
+pub fn alarm_cancel(alarm: usize) {
+    let mask = 1u32 << alarm;
+    free(|| {
+        timer().armed.write(mask);
+        timer().inte.clear(mask);
+        timer().intf.clear(mask);
+        timer().intr.write(mask);
+        unsafe { (*addr_of_mut!(HANDLERS))[alarm] = None };
+    });
+}
+
+/* Waiting to fire */
+pub fn alarm_pending(alarm: usize) -> bool {
+    unsafe { (*addr_of_mut!(HANDLERS))[alarm].is_some() }
+}
+
+/* Acknowledge the alarm (raw and forced) and call its handler once */
+fn alarm_irq(alarm: usize) {
+    let mask = 1u32 << alarm;
+    timer().intf.clear(mask);
+    timer().intr.write(mask);
+    if let Some(handler) = unsafe { (*addr_of_mut!(HANDLERS))[alarm].take() } {
+        handler(alarm);
+    }
+}
+
+/* Interrupt handlers for TIMER_IRQ_0..3 */
+#[no_mangle]
+pub extern "C" fn timerIrq0() {
+    alarm_irq(0);
+}
+
+#[no_mangle]
+pub extern "C" fn timerIrq1() {
+    alarm_irq(1);
+}
+
+#[no_mangle]
+pub extern "C" fn timerIrq2() {
+    alarm_irq(2);
+}
+
+#[no_mangle]
+pub extern "C" fn timerIrq3() {
+    alarm_irq(3);
+}
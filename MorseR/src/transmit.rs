@@ -2,191 +2,477 @@
 #![no_main]
 
 use core::arch::asm;
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
-use core::ptr::addr_of_mut;
-
-/* Hardware Register Structures */
-/* SIO (Single-cycle IO) registers for fast GPIO access */
-#[repr(C)]
-struct SioHw {
-    cpuid: u32,          /* Processor core identifier */
-    gpio_in: u32,        /* Input values for GPIO 0-29 */
-    gpio_hi_in: u32,     /* Input values for GPIO 30-35 */
-    unused: u32,         /* Reserved */
-    gpio_out: u32,       /* GPIO output values */
-    gpio_out_set: u32,   /* Set GPIO output bits */
-    gpio_out_clr: u32,   /* Clear GPIO output bits */
-    gpio_out_xor: u32,   /* XOR GPIO output bits */
-    gpio_oe: u32,        /* GPIO output enable */
-    gpio_oe_set: u32,    /* Set GPIO output enable bits */
-    gpio_oe_clr: u32,    /* Clear GPIO output enable bits */
-    gpio_oe_xor: u32,    /* XOR GPIO output enable bits */
-}
-
-/* IO Bank 0 registers for GPIO configuration and interrupts */
-/* Each structure has two 32-bit values: status and ctrl */
-#[repr(C)]
-struct IoBank0Hw {
-    gpio: [GpioCtrl; 30],    /* We repeated this 30 times for each GPIO */
-    intr: [u32; 4],          /* Raw interrupts */
-    proc0_inte: [u32; 4],    /* Interrupt enable for processor 0 */
-    proc0_intf: [u32; 4],    /* Interrupt force for processor 0 */
-    proc0_ints: [u32; 4],    /* Interrupt status for processor 0 */
-}
-
-#[repr(C)]
-struct GpioCtrl {
-    status: u32,     /* GPIO status */
-    ctrl: u32,       /* GPIO control including function selection */
-}
-
-/* Pad control registers for GPIO electrical properties */
-#[repr(C)]
-struct PadsBank0Hw {
-    voltage_select: u32,  /* Voltage select */
-    gpio: [u32; 30],      /* Pad control register for each GPIO */
-    swclk: u32,           /* Pad control register for SWCLK */
-    swd: u32,             /* Pad control register for SWD */
-}
-
-/* Base addresses for hardware registers */
-const SIO_BASE: u32 = 0xd0000000;
-const IO_BANK0_BASE: u32 = 0x40014000;
-const PADS_BANK0_BASE: u32 = 0x4001c000;
-const RESETS_BASE: u32 = 0x4000c000;
-
-/* Register access pointers */
-/* This means:
-   1. Take base address
-   2. Cast it to a pointer to the struct
-   3. Access registers through this pointer */
-#[inline(always)]
-fn sio() -> *mut SioHw {
-    SIO_BASE as *mut SioHw
-}
+use core::ptr::{addr_of, addr_of_mut, read_volatile};
 
-#[inline(always)]
-fn io() -> *mut IoBank0Hw {
-    IO_BANK0_BASE as *mut IoBank0Hw
-}
-
-#[inline(always)]
-fn pads() -> *mut PadsBank0Hw {
-    PADS_BANK0_BASE as *mut PadsBank0Hw
-}
+use crate::adc::{self, Adc};
+use crate::clocks;
+use crate::envelope::{Envelope, Shape};
+use crate::gesture::{Gesture, Gestures};
+use crate::gpio::{Input, Output, Pin, Pins, Pull};
+use crate::hw::*;
+use crate::io_irq;
+use crate::keyer::{Keyer, KeyerMode};
+use crate::morse::Element;
+use crate::pio_tx;
+use crate::pwm;
+use crate::sidetone::{Sidetone, SidetoneMode};
+use crate::timer::{self, delay_ms, delay_us, time_us};
+use crate::timing::{Spacing, Timing};
+use crate::tx;
+use crate::uart::{Config, Uart};
+use crate::watchdog;
 
 /* Pin definitions */
 const BUTTON_PIN: u32 = 16;    /* Push button input */
 const SPEAKER_PIN: u32 = 21;   /* Speaker output */
 const LED_PIN: u32 = 25;       /* Onboard LED */
-const GPIO_FUNC_SIO: u32 = 5;  /* SIO function for GPIO */
+const DIT_PADDLE_PIN: u32 = 14; /* Iambic dit paddle, to ground */
+const DAH_PADDLE_PIN: u32 = 15; /* Iambic dah paddle, to ground */
 
-/* Interrupt configuration */
-const GPIO_INT_EDGE_HIGH: u32 = 0x8;
-const IO_BANK0_IRQ: u32 = 13;      /* IO Bank 0 interrupt number */
-const NVIC_BASE: u32 = 0xe000e000;
-const NVIC_ISER: *mut u32 = (NVIC_BASE + 0x100) as *mut u32;
+/* Debug console on UART0 (TX GPIO0, RX GPIO1), every message sent is logged to it */
+const CONSOLE_BAUD: u32 = 115_200;
 
-/* Simple delay function using NOP instructions */
-#[inline(always)]
-fn delay(count: u32) {
-    /* Loop to create delay with NOP instructions */
-    for i in 0..count {
+/* Speed: characters at CHAR_WPM, gaps stretched for an overall EFFECTIVE_WPM
+   (Spacing::Farnsworth or Spacing::Wordsworth, Spacing::Standard ignores EFFECTIVE_WPM) */
+const CHAR_WPM: u32 = 15;
+const EFFECTIVE_WPM: u32 = 15;
+const SPACING: Spacing = Spacing::Farnsworth;
+const TIMING: Timing = Timing::new(CHAR_WPM, EFFECTIVE_WPM, SPACING);
+
+/* Keyer settings (paddles always key at CHAR_WPM) */
+const KEYER_MODE: KeyerMode = KeyerMode::IambicB;
+const KEYER_AUTO_SPACE: bool = false;
+
+/* Speaker output: 700 Hz tone, or SidetoneMode::KeyLine for a silent on/off key line */
+const SIDETONE_MODE: SidetoneMode = SidetoneMode::Tone(700);
+
+/* Callsign used in the message memories */
+macro_rules! callsign {
+    () => { "N0CALL" };
+}
+
+/* Message memories, kept in flash. Click the button once to send the first,
+   twice for the second and so on. Prosigns go in angle brackets. */
+const MEMORIES: [&str; 3] = [
+    concat!("DE ", callsign!()),
+    concat!("CQ CQ CQ DE ", callsign!(), " ", callsign!(), " <AR> K"),
+    "TEST PARIS PARIS PARIS",
+];
+
+/* One more memory after MEMORIES reports the chip temperature ("TEMP 23C"),
+   read when it is sent so a beacon always has the current value */
+const TEMP_MEMORY: usize = MEMORIES.len();
+const MEMORY_COUNT: usize = MEMORIES.len() + 1;
+
+/* Beacon: holding the button repeats the last memory sent (BEACON_MEMORY before any)
+   with BEACON_PAUSE_MS of silence in between, until the button is pressed again */
+const BEACON_MEMORY: usize = 0;
+const BEACON_PAUSE_MS: u32 = 10_000;
+const BEACON_AT_STARTUP: bool = false;
+
+/* Click-free keying: 5 ms raised-cosine rise and fall, stepped at 4 kHz by SysTick.
+   None keys the tone hard on and off. */
+const ENVELOPE_TICK_HZ: u32 = 4000;
+const SIDETONE_ENVELOPE: Option<Envelope> =
+    Some(Envelope::new(Shape::RaisedCosine, 5_000, 5_000, ENVELOPE_TICK_HZ));
+
+static mut SIDETONE: Sidetone<SPEAKER_PIN> = Sidetone::new(SIDETONE_MODE, SIDETONE_ENVELOPE);
+
+/* PIO keying: a PIO0 state machine fed by DMA plays messages and paddle elements
+   (pio_tx.rs) instead of the timer alarm engine, timed to the microsecond with
+   the CPU asleep. The speaker and LED belong to PIO0 after the startup blinks,
+   the tone is a plain square wave (SIDETONE_ENVELOPE isn't used). */
+const PIO_KEYING: bool = false;
+
+/* Pins used outside main, filled in once main has configured them */
+static mut LED: Option<Pin<LED_PIN, Output>> = None;
+static mut BUTTON: Option<Pin<BUTTON_PIN, Input>> = None;
+static mut PADDLES: Option<(Pin<DIT_PADDLE_PIN, Input>, Pin<DAH_PADDLE_PIN, Input>)> = None;
+static mut CONSOLE: Option<Uart<0>> = None;
+static mut ADC: Option<Adc> = None;
+
+/* Button state, fed by the button interrupt */
+static mut GESTURES: Gestures = Gestures::new();
+
+/* Memory being repeated as a beacon, None when no beacon runs */
+static mut BEACON: Option<usize> = None;
+
+/* The beacon memory is due to be sent again. Set from the alarm interrupt,
+   main sends it: the temperature memory runs the ADC and send() logs to the
+   console, neither belongs in an interrupt. */
+static mut BEACON_DUE: bool = false;
+
+/* Timer alarm that ends the beacon pause (the engine uses tx::ALARM) */
+const BEACON_ALARM: usize = 1;
+
+/* Watchdog: main has to come round its loop within WATCHDOG_TIMEOUT_US.
+   A timer alarm wakes it from wfi every WATCHDOG_WAKE_US to feed it. */
+const WATCHDOG_TIMEOUT_US: u32 = 2_000_000;
+const WATCHDOG_WAKE_US: u32 = 250_000;
+const WATCHDOG_ALARM: usize = 2;
+
+/* Paddle memory is sampled this many times per dot unit */
+const SAMPLES_PER_DOT: u32 = 8;
+
+/* Key down: LED on and sidetone (or key line) on. With PIO_KEYING the LED
+   only blinks at startup and the sidetone isn't set up. */
+fn key_down() {
+    unsafe {
+        if let Some(led) = &mut *addr_of_mut!(LED) {
+            led.set_high();
+        }
+        if !PIO_KEYING {
+            (*addr_of_mut!(SIDETONE)).key_down();
+        }
+    }
+}
+
+/* Key up: LED and sidetone off */
+fn key_up() {
+    unsafe {
+        if let Some(led) = &mut *addr_of_mut!(LED) {
+            led.set_low();
+        }
+        if !PIO_KEYING {
+            (*addr_of_mut!(SIDETONE)).key_up();
+        }
+    }
+}
+
+/* Neither engine has anything queued or playing */
+fn engine_idle() -> bool {
+    tx::is_idle() && pio_tx::is_idle()
+}
+
+/* Queue a message and log it to the console */
+fn send(text: &str) {
+    let queued = if PIO_KEYING {
+        pio_tx::enqueue_text(text)
+    } else {
+        tx::enqueue_text(text)
+    };
+    if queued {
         unsafe {
-            asm!("nop");
+            if let Some(console) = &mut *addr_of_mut!(CONSOLE) {
+                console.write(text.as_bytes());
+                console.write(b"\r\n");
+            }
+        }
+    }
+}
+
+/* Message text built at run time */
+struct Text {
+    bytes: [u8; 16],
+    len: usize,
+}
+
+impl Text {
+    const fn new() -> Self {
+        Text { bytes: [0; 16], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        /* Only whole str pieces are ever copied in */
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/* Queue memory N, the temperature memory is read and formatted now */
+fn send_memory(memory: usize) {
+    if memory != TEMP_MEMORY {
+        send(MEMORIES[memory]);
+        return;
+    }
+    let millicelsius =
+        free(|| unsafe { (*addr_of_mut!(ADC)).as_mut().map(|adc| adc.read_temperature()) });
+    if let Some(millicelsius) = millicelsius {
+        let mut text = Text::new();
+        if write!(text, "TEMP {}C", adc::celsius(millicelsius)).is_ok() {
+            send(text.as_str());
         }
     }
 }
 
-/* Interrupt handler for IO Bank 0 */
+/* Key output for the transmit engine */
+fn key(down: bool) {
+    if down {
+        key_down();
+    } else {
+        key_up();
+    }
+}
+
+/* Message finished (alarm interrupt): a running beacon pauses, then goes again */
+fn message_sent() {
+    if unsafe { (*addr_of!(BEACON)).is_some() } {
+        timer::alarm_in(BEACON_ALARM, BEACON_PAUSE_MS * 1000, beacon_repeat);
+    }
+}
+
+fn beacon_repeat(_alarm: usize) {
+    unsafe { *addr_of_mut!(BEACON_DUE) = (*addr_of!(BEACON)).is_some() };
+}
+
+/* Repeat a memory until stopped, main sends it the first time round */
+fn start_beacon(memory: usize) {
+    free(|| unsafe {
+        *addr_of_mut!(BEACON) = Some(memory);
+        if engine_idle() {
+            *addr_of_mut!(BEACON_DUE) = true;
+        }
+    });
+}
+
+/* Stop the message or beacon being sent */
+fn stop_sending() {
+    free(|| unsafe {
+        *addr_of_mut!(BEACON) = None;
+        *addr_of_mut!(BEACON_DUE) = false;
+        timer::alarm_cancel(BEACON_ALARM);
+        tx::abort();
+        pio_tx::abort();
+    });
+}
+
+/* Only wakes main from wfi, the feeding is done there so a stuck main loop
+   (or an interrupt that never returns) still resets the chip */
+fn watchdog_wake(alarm: usize) {
+    timer::alarm_in(alarm, WATCHDOG_WAKE_US, watchdog_wake);
+}
+
+/* Sending a message or running a beacon (even while it pauses) */
+fn is_sending() -> bool {
+    !engine_idle() || unsafe { read_volatile(addr_of!(BEACON)) }.is_some()
+}
+
+/* Paddles pull their pin to ground when pressed */
+#[inline(always)]
+fn paddles() -> (bool, bool) {
+    match unsafe { &*addr_of!(PADDLES) } {
+        Some((dit, dah)) => (dit.is_low(), dah.is_low()),
+        None => (false, false),
+    }
+}
+
+/* Play a keyer element, sampling the paddles while it plays so dit/dah memory works.
+   With PIO_KEYING the state machine plays it and this only times the sampling. */
+fn play_keyed(keyer: &mut Keyer, element: Element) {
+    if PIO_KEYING {
+        pio_tx::enqueue(element);
+    } else if element.is_key_down() {
+        key_down();
+    }
+    for _ in 0..element.units() * SAMPLES_PER_DOT {
+        delay_us(TIMING.dot_us / SAMPLES_PER_DOT);
+        let (dit, dah) = paddles();
+        keyer.sample(dit, dah);
+    }
+    key_up();
+}
+
+/* SysTick steps the sidetone envelope while a ramp is running */
 #[no_mangle]
-pub extern "C" fn ioIrqBank0() {
+pub extern "C" fn sysTickHandler() {
     unsafe {
-        /* This checks if button caused interrupt:
-           1. proc0_ints[BUTTON_PIN / 8] gets the right interrupt status register
-           2. BUTTON_PIN / 8 divides pin number by 8 to get right register (pins 0-7 use 0, 8-15 use 1, etc)
-           3. BUTTON_PIN % 8 gets remainder to find position within register
-           4. << (4 * (BUTTON_PIN % 8)) shifts bits to right position (each pin uses 4 bits) */
-        let pin_index = BUTTON_PIN / 8;
-        let pin_offset = 4 * (BUTTON_PIN % 8);
-        
-        if (*io()).proc0_ints[pin_index as usize] & (GPIO_INT_EDGE_HIGH << pin_offset) != 0 {
-            /* Set bits using OR operation:
-               1. 1U creates unsigned integer 1
-               2. << LED_PIN shifts 1 left by LED_PIN positions 
-               3. Writing to gpio_out_set sets those pins high */
-            (*sio()).gpio_out_set = (1u32 << LED_PIN) | (1u32 << SPEAKER_PIN);
-            delay(100000);
-            
-            /* Deactivate LED and speaker - same but writes to clear register */
-            (*sio()).gpio_out_clr = (1u32 << LED_PIN) | (1u32 << SPEAKER_PIN);
-            
-            /* Clear the interrupt */
-            (*io()).intr[pin_index as usize] = 0xF << pin_offset;
+        (*addr_of_mut!(SIDETONE)).tick();
+    }
+}
+
+/* Button press or release. The button pulls the pin low, so a falling edge
+   is a press and a rising edge a release. Both edges may be pending after
+   a bounce: go by the pin level. A press while sending only stops it, it
+   doesn't count as a click or hold. */
+fn button_event(_pin: u32, _events: u32) {
+    unsafe {
+        let now = time_us();
+        let gestures = &mut *addr_of_mut!(GESTURES);
+        if matches!(&*addr_of!(BUTTON), Some(button) if button.is_low()) {
+            gestures.press(now);
+            if is_sending() {
+                stop_sending();
+                gestures.cancel();
+            }
+        } else {
+            gestures.release(now);
         }
     }
 }
 
+/* Paddle presses only wake the keyer loop in main, which reads the paddles itself */
+fn paddle_event(_pin: u32, _events: u32) {}
+
 #[no_mangle]
 pub extern "C" fn main() -> ! {
     unsafe {
-        /* Reset IO Bank 0 peripheral:
-           1. Create pointer to reset register
-           2. Clear bit 5 using AND with inverted bit pattern
-           3. Wait while bit is still set */
-        let resets_reset = (RESETS_BASE + 0x0) as *mut u32;
-        *resets_reset &= !(1u32 << 5);
-        
-        let resets_reset_done = (RESETS_BASE + 0x8) as *mut u32;
-        while (*resets_reset_done & (1u32 << 5)) == 0 {}
-
-        /* Configure button (GPIO16)
-           1. Set GPIO function using direct register write
-           2. Clear output enable bit for input mode
-           3. Set pad control (pull-up and input enable) */
-        (*io()).gpio[BUTTON_PIN as usize].ctrl = GPIO_FUNC_SIO;    /* Set to SIO function */
-        (*sio()).gpio_oe_clr = 1u32 << BUTTON_PIN;                 /* Set as input */
-        (*pads()).gpio[BUTTON_PIN as usize] = (1u32 << 3) | (1u32 << 6); /* Enable pull-up and input */
-        
+        /* Take IO Bank 0 and the pads out of reset, start the microsecond timer */
+        let pins = Pins::take().unwrap();
+        timer::init();
+
+        /* Configure button (GPIO16): SIO input with pull-up, the button shorts it to ground */
+        let mut button = pins.gpio16.into_input();
+        button.set_pull(Pull::Up);
+        BUTTON = Some(button);
+
         /* Configure LED (GPIO25) */
-        (*io()).gpio[LED_PIN as usize].ctrl = GPIO_FUNC_SIO;       /* Set to SIO function */
-        (*sio()).gpio_oe_set = 1u32 << LED_PIN;                    /* Set as output */
-        
-        /* Configure speaker (GPIO21) */
-        (*io()).gpio[SPEAKER_PIN as usize].ctrl = GPIO_FUNC_SIO;   /* Set to SIO function */
-        (*sio()).gpio_oe_set = 1u32 << SPEAKER_PIN;                /* Set as output */
-        
-        /* Setup button interrupt 
-           1. Clear existing interrupts
-           2. Enable rising edge interrupt for button
-           3. Enable IO Bank 0 interrupt in NVIC (Nested Vectored Interrupt Controller) */
-        let pin_index = BUTTON_PIN / 8;
-        let pin_offset = 4 * (BUTTON_PIN % 8);
-        
-        (*io()).intr[pin_index as usize] = 0xF << pin_offset;  /* Clear pending interrupts */
-        (*io()).proc0_inte[pin_index as usize] |= GPIO_INT_EDGE_HIGH << pin_offset;  /* Enable rising edge interrupt */
-        *NVIC_ISER = 1u32 << IO_BANK0_IRQ;  /* Enable interrupt in NVIC */
+        LED = Some(pins.gpio25.into_output());
+
+        /* Console */
+        CONSOLE = Some(Uart::new(pins.gpio0, pins.gpio1, &Config::new(CONSOLE_BAUD)));
+
+        /* Why we were reset, kept in the watchdog scratch registers */
+        let reset = watchdog::check_reset();
+        if let Some(console) = &mut *addr_of_mut!(CONSOLE) {
+            let _ = write!(
+                console,
+                "TX reset: {} ({} watchdog resets)\r\n",
+                reset.name(),
+                watchdog::watchdog_resets()
+            );
+        }
+
+        /* Configure speaker (GPIO21):
+           1. Take the PWM block out of reset
+           2. Tone mode routes the pin to PWM slice 2 channel B, key line mode to SIO
+           3. With PIO_KEYING it is kept for PIO0 instead, handed over below */
+        pwm::init();
+        let mut speaker = None;
+        if PIO_KEYING {
+            speaker = Some(pins.gpio21);
+        } else {
+            (*addr_of_mut!(SIDETONE)).init(pins.gpio21, clocks::sys_hz());
+        }
+
+        /* ADC for the temperature memory, the sensor settles while we start up */
+        let mut converter = Adc::new();
+        converter.set_temperature_sensor(true);
+        ADC = Some(converter);
+
+        /* Configure paddles as inputs with pull-up */
+        let mut dit = pins.gpio14.into_input();
+        let mut dah = pins.gpio15.into_input();
+        dit.set_pull(Pull::Up);
+        dah.set_pull(Pull::Up);
+        PADDLES = Some((dit, dah));
+
+        /* Interrupts:
+           1. Paddles on press (falling edge)
+           2. Button on press and release (falling and rising edge) */
+        io_irq::register(DIT_PADDLE_PIN, GPIO_INT_EDGE_LOW, paddle_event);
+        io_irq::register(DAH_PADDLE_PIN, GPIO_INT_EDGE_LOW, paddle_event);
+        io_irq::register(BUTTON_PIN, GPIO_INT_EDGE_LOW | GPIO_INT_EDGE_HIGH, button_event);
+
+        /* Messages are sent by the transmit engine in the background */
+        tx::init(TIMING, key);
+        tx::set_on_complete(Some(message_sent));
 
         /* Startup test pattern */
         for _ in 0..3 {
             /* Turn on LED and speaker */
-            (*sio()).gpio_out_set = (1u32 << LED_PIN) | (1u32 << SPEAKER_PIN);
-            delay(100000);
+            key_down();
+            delay_ms(100);
             /* Turn off LED and speaker */
-            (*sio()).gpio_out_clr = (1u32 << LED_PIN) | (1u32 << SPEAKER_PIN);
-            delay(100000);
+            key_up();
+            delay_ms(100);
         }
         
         /* Debug LED flash */
-        (*sio()).gpio_out_set = 1u32 << LED_PIN;
-        delay(500000);
-        (*sio()).gpio_out_clr = 1u32 << LED_PIN;
+        if let Some(led) = &mut *addr_of_mut!(LED) {
+            led.set_high();
+            delay_ms(500);
+            led.set_low();
+
+            /* Reset reason: one blink for power-on, two for the RUN pin ... (see watchdog.rs) */
+            delay_ms(500);
+            for _ in 0..reset.blinks() {
+                led.set_high();
+                delay_ms(150);
+                led.set_low();
+                delay_ms(150);
+            }
+        }
+
+        /* PIO keying: the startup blinks are done, PIO0 takes the speaker and LED */
+        if let Some(speaker) = speaker {
+            let led = (*addr_of_mut!(LED)).take().unwrap();
+            if pio_tx::init(speaker, led.into_disabled(), SIDETONE_MODE, TIMING) {
+                pio_tx::set_on_complete(Some(message_sent));
+            } else if let Some(console) = &mut *addr_of_mut!(CONSOLE) {
+                console.write(b"TX: no DMA channel for PIO keying\r\n");
+            }
+        }
+
+        /* Watchdog on from here, fed once per loop */
+        watchdog::start(WATCHDOG_TIMEOUT_US, true);
+        timer::alarm_in(WATCHDOG_ALARM, WATCHDOG_WAKE_US, watchdog_wake);
         
-        /* 1. CPU sleeps until interrupt occurs
-           2. wfi = Wait For Interrupt instruction
-           3. loop prevents function from returning */
+        /* Main loop:
+           1. Button gestures: N clicks send memory N (one more click than there
+              are MEMORIES sends the temperature), a hold starts the beacon.
+              The transmit engine sends them, any button press stops it.
+           2. While nothing is being sent, ask the keyer for the next element and play it
+           3. A beacon that is due (its pause is over) is sent from here
+           4. When idle, sleep until a paddle, button or timer interrupt
+           5. Interrupts are masked around the check so a press between
+              reading the inputs and wfi still wakes us (wfi ignores PRIMASK).
+              While a gesture is in progress we keep polling instead of sleeping
+              since its end is a timeout, not an edge. */
+        let mut keyer = Keyer::new(KEYER_MODE, KEYER_AUTO_SPACE);
+        let mut selected = BEACON_MEMORY;
+        if BEACON_AT_STARTUP {
+            start_beacon(selected);
+        }
         loop {
-            asm!("wfi");
+            watchdog::feed();
+            asm!("cpsid i");
+            let gestures = &mut *addr_of_mut!(GESTURES);
+            let gesture = gestures.poll(time_us());
+            let element = if is_sending() {
+                None
+            } else {
+                let (dit, dah) = paddles();
+                keyer.next(dit, dah)
+            };
+            let beacon_due = core::mem::replace(&mut *addr_of_mut!(BEACON_DUE), false);
+            if gesture.is_none() && element.is_none() && !beacon_due && gestures.is_idle() {
+                asm!("wfi");
+            }
+            asm!("cpsie i");
+
+            /* A press may have stopped the beacon since */
+            if beacon_due {
+                if let Some(memory) = read_volatile(addr_of!(BEACON)) {
+                    send_memory(memory);
+                }
+            }
+            match gesture {
+                Some(Gesture::Click(clicks)) => {
+                    let index = clicks as usize - 1;
+                    if index < MEMORY_COUNT {
+                        selected = index;
+                        send_memory(index);
+                    }
+                }
+                Some(Gesture::Hold) => start_beacon(selected),
+                None => {}
+            }
+            if let Some(element) = element {
+                play_keyed(&mut keyer, element);
+            }
         }
     }
-}
\ No newline at end of file
+}
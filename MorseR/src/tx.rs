@@ -0,0 +1,168 @@
+use core::ptr::{addr_of, addr_of_mut, read_volatile};
+
+use crate::hw::free;
+use crate::morse::{self, Element};
+use crate::timer::{self, time_us};
+use crate::timing::{Spacing, Timing};
+
+/* Interrupt-driven transmit engine */
+/* Messages are encoded into key elements up front and queued, a timer alarm
+   then plays the queue without main having to wait:
+   1. enqueue_text() adds a whole message to the queue and starts the alarm if idle
+   2. Each alarm keys down or up for the next element and re-arms itself for the
+      end of that element. Deadlines are absolute (last deadline + duration) so
+      interrupt latency doesn't add up over a message.
+   3. When the queue runs dry the key goes up and the completion callback is
+      called (from the alarm interrupt)
+   4. abort() empties the queue and keys up straight away, without the callback
+   main can sleep in wfi between elements, every alarm wakes it. */
+
+/* Elements, a long memory is around 300 */
+pub const QUEUE_LEN: usize = 512;
+
+/* Timer alarm the engine runs on */
+pub const ALARM: usize = 0;
+
+/* Turns the key (tone, LED, key line) on or off */
+pub type KeyFn = fn(down: bool);
+
+/* Called from the alarm interrupt once the queue has been sent */
+pub type CompleteFn = fn();
+
+struct Engine {
+    queue: [Element; QUEUE_LEN],   /* Ring buffer */
+    head: usize,                   /* Next element to play */
+    len: usize,                    /* Elements waiting */
+    busy: bool,                    /* Alarm running, the key may be down */
+    deadline: u32,                 /* End of the element playing */
+    timing: Timing,
+    key: Option<KeyFn>,
+    complete: Option<CompleteFn>,
+}
+
+static mut ENGINE: Engine = Engine {
+    queue: [Element::WordGap; QUEUE_LEN],
+    head: 0,
+    len: 0,
+    busy: false,
+    deadline: 0,
+    timing: Timing::new(20, 20, Spacing::Standard),
+    key: None,
+    complete: None,
+};
+
+impl Engine {
+    fn push(&mut self, element: Element) {
+        self.queue[(self.head + self.len) % QUEUE_LEN] = element;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Element> {
+        if self.len == 0 {
+            return None;
+        }
+        let element = self.queue[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(element)
+    }
+
+    fn key(&self, down: bool) {
+        if let Some(key) = self.key {
+            key(down);
+        }
+    }
+
+    /* First alarm is due now, it fires as soon as interrupts allow */
+    fn start(&mut self) {
+        if !self.busy && self.len > 0 {
+            self.busy = true;
+            self.deadline = time_us();
+            timer::alarm_at(ALARM, self.deadline, step);
+        }
+    }
+}
+
+/* Alarm handler: end of one element, start of the next */
+fn step(_alarm: usize) {
+    let engine = unsafe { &mut *addr_of_mut!(ENGINE) };
+    match engine.pop() {
+        Some(element) => {
+            engine.key(element.is_key_down());
+            engine.deadline = engine.deadline.wrapping_add(engine.timing.duration_us(element));
+            timer::alarm_at(ALARM, engine.deadline, step);
+        }
+        None => {
+            engine.key(false);
+            engine.busy = false;
+            if let Some(complete) = engine.complete {
+                complete();
+            }
+        }
+    }
+}
+
+/* Set the speed and the function that keys the output */
+pub fn init(timing: Timing, key: KeyFn) {
+    free(|| unsafe {
+        let engine = &mut *addr_of_mut!(ENGINE);
+        engine.timing = timing;
+        engine.key = Some(key);
+    });
+}
+
+/* New speed, used from the next element on */
+pub fn set_timing(timing: Timing) {
+    free(|| unsafe { (*addr_of_mut!(ENGINE)).timing = timing });
+}
+
+pub fn set_on_complete(complete: Option<CompleteFn>) {
+    free(|| unsafe { (*addr_of_mut!(ENGINE)).complete = complete });
+}
+
+/* Queue one element */
+pub fn enqueue(element: Element) -> bool {
+    free(|| {
+        let engine = unsafe { &mut *addr_of_mut!(ENGINE) };
+        if engine.len == QUEUE_LEN {
+            return false;
+        }
+        engine.push(element);
+        engine.start();
+        true
+    })
+}
+
+/* Queue a message, followed by a word gap so messages sent back to back stay
+   apart. All or nothing: false if it doesn't fit behind what is queued. */
+pub fn enqueue_text(text: &str) -> bool {
+    let count = morse::encode(text).count() + 1;
+    free(|| {
+        let engine = unsafe { &mut *addr_of_mut!(ENGINE) };
+        if count > QUEUE_LEN - engine.len {
+            return false;
+        }
+        morse::encode(text).for_each(|element| engine.push(element));
+        engine.push(Element::WordGap);
+        engine.start();
+        true
+    })
+}
+
+/* Stop sending now: empty the queue and key up */
+pub fn abort() {
+    free(|| {
+        timer::alarm_cancel(ALARM);
+        let engine = unsafe { &mut *addr_of_mut!(ENGINE) };
+        engine.len = 0;
+        if engine.busy {
+            engine.busy = false;
+            engine.key(false);
+        }
+    });
+}
+
+/* Nothing queued or playing */
+pub fn is_idle() -> bool {
+    unsafe { !read_volatile(addr_of!(ENGINE.busy)) }
+}
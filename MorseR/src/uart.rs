@@ -0,0 +1,407 @@
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::dma::{self, Target};
+use crate::gpio::{Disabled, Pin};
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, Rw, RwAlias, Wo};
+use crate::timer::delay_us;
+
+/* UART (ARM PL011) */
+/* Two identical UARTs. Uart<N> owns UART N and its TX and RX pins:
+   1. new() takes the UART out of reset, muxes the pins and sets the framing.
+      The pins are checked at compile time: UART0 TX/RX are GPIO 0/1, 12/13,
+      16/17 or 28/29, UART1 GPIO 4/5, 8/9, 20/21 or 24/25
+   2. The baud rate divisor is 16.6 fixed point from clk_peri, set_baud()
+      returns the rate actually achieved
+   3. Both FIFOs are 32 deep. write()/read() block until everything has gone
+      through, try_write()/try_read() only move what fits right now
+   4. Received bytes carry their own error flags: break, framing, parity, overrun
+   5. Interrupts (RX level, TX level, RX timeout, errors) go to a handler set
+      with set_handler(), called from UART0_IRQ/UART1_IRQ */
+
+#[repr(C)]
+pub struct UartHw {
+    pub dr: Rw,             /* Data, reading pops the RX FIFO with the error flags */
+    pub rsr: RwAlias,       /* Receive status, write to clear */
+    _reserved0: [u32; 4],
+    pub fr: Ro,             /* Flags: FIFO full/empty, busy */
+    _reserved1: u32,
+    pub ilpr: RwAlias,      /* IrDA low-power counter */
+    pub ibrd: RwAlias,      /* Integer baud rate divisor */
+    pub fbrd: RwAlias,      /* Fractional baud rate divisor (1/64ths) */
+    pub lcr_h: RwAlias,     /* Line control: framing and FIFO enable */
+    pub cr: RwAlias,        /* Control: UART, TX and RX enable */
+    pub ifls: RwAlias,      /* FIFO interrupt levels */
+    pub imsc: RwAlias,      /* Interrupt mask, 1 = enabled */
+    pub ris: Ro,            /* Raw interrupt status */
+    pub mis: Ro,            /* Masked interrupt status */
+    pub icr: Wo,            /* Interrupt clear, write 1 */
+    pub dmacr: RwAlias,     /* DMA enables */
+}
+
+const UART_BASE: [u32; 2] = [0x40034000, 0x40038000];
+const RESET_UART: [u32; 2] = [22, 23];
+pub const UART_IRQ: [u32; 2] = [20, 21];
+
+#[inline(always)]
+fn uart(index: usize) -> &'static UartHw {
+    block(UART_BASE[index])
+}
+
+/* DR fields */
+const DR_DATA: Field = Field::new(0, 8);
+const DR_FE: Field<bool> = bit(8);
+const DR_PE: Field<bool> = bit(9);
+const DR_BE: Field<bool> = bit(10);
+const DR_OE: Field<bool> = bit(11);
+
+/* FR fields */
+const FR_BUSY: Field<bool> = bit(3);
+const FR_RXFE: Field<bool> = bit(4);
+const FR_TXFF: Field<bool> = bit(5);
+
+/* LCR_H fields */
+const LCR_H_BRK: Field<bool> = bit(0);
+const LCR_H_PEN: Field<bool> = bit(1);
+const LCR_H_EPS: Field<bool> = bit(2);
+const LCR_H_STP2: Field<bool> = bit(3);
+const LCR_H_FEN: Field<bool> = bit(4);
+const LCR_H_WLEN: Field = Field::new(5, 2);
+
+/* CR fields */
+const CR_UARTEN: Field<bool> = bit(0);
+const CR_TXE: Field<bool> = bit(8);
+const CR_RXE: Field<bool> = bit(9);
+
+/* DMACR fields */
+const DMACR_RXDMAE: Field<bool> = bit(0);
+const DMACR_TXDMAE: Field<bool> = bit(1);
+
+/* IFLS fields */
+const IFLS_TX: Field = Field::new(0, 3);
+const IFLS_RX: Field = Field::new(3, 3);
+
+/* Interrupt bits (IMSC, RIS, MIS, ICR) */
+pub const INT_RX: u32 = 1 << 4;         /* RX FIFO reached its level */
+pub const INT_TX: u32 = 1 << 5;         /* TX FIFO drained to its level */
+pub const INT_RX_TIMEOUT: u32 = 1 << 6; /* RX FIFO not empty and the line idle for 32 bits */
+pub const INT_FRAMING: u32 = 1 << 7;
+pub const INT_PARITY: u32 = 1 << 8;
+pub const INT_BREAK: u32 = 1 << 9;
+pub const INT_OVERRUN: u32 = 1 << 10;
+const INT_ALL: u32 = 0x7ff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/* FIFO interrupt level */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FifoLevel {
+    Eighth = 0,
+    Quarter = 1,
+    Half = 2,
+    ThreeQuarters = 3,
+    SevenEighths = 4,
+}
+
+/* Line settings */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,
+    pub data_bits: u8,      /* 5 to 8 */
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Config {
+    /* 8N1 at the given baud rate */
+    pub const fn new(baud: u32) -> Self {
+        Config {
+            baud,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+/* Receive errors, reported with the byte they belong to */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    Break,      /* Line held low for longer than a whole frame */
+    Framing,    /* No valid stop bit */
+    Parity,
+    Overrun,    /* FIFO was full, bytes after this one were lost */
+}
+
+/* Called from the UART interrupt with the masked interrupt status */
+pub type Handler = fn(status: u32);
+
+static mut HANDLERS: [Option<Handler>; 2] = [None; 2];
+
+pub struct Uart<const N: usize> {
+    _private: (),
+}
+
+/* Compile-time pin check, see the top of this file */
+struct PinCheck<const N: usize, const TX: u32, const RX: u32>;
+
+impl<const N: usize, const TX: u32, const RX: u32> PinCheck<N, TX, RX> {
+    const OK: () = {
+        assert!(N < 2, "there are two UARTs");
+        assert!(TX.is_multiple_of(4) && RX == TX + 1, "TX must be GPIO 4n and RX the pin after it");
+        assert!(((TX + 4) / 8) % 2 == N as u32, "pins belong to the other UART");
+    };
+}
+
+impl<const N: usize> Uart<N> {
+    /* The pins stay with the UART for good */
+    pub fn new<const TX: u32, const RX: u32>(
+        tx: Pin<TX, Disabled>,
+        rx: Pin<RX, Disabled>,
+        config: &Config,
+    ) -> Self {
+        let _: () = PinCheck::<N, TX, RX>::OK;
+        assert!(config.data_bits >= 5 && config.data_bits <= 8);
+
+        reset(RESET_UART[N]);
+        unreset(RESET_UART[N]);
+
+        let mut uart = Uart { _private: () };
+        uart.set_baud(config.baud);
+        /* Writing LCR_H also latches the divisors written by set_baud */
+        uart.hw().lcr_h.write(
+            LCR_H_WLEN.bits(config.data_bits as u32 - 5)
+                | LCR_H_FEN.bits(true)
+                | LCR_H_PEN.bits(config.parity != Parity::None)
+                | LCR_H_EPS.bits(config.parity == Parity::Even)
+                | LCR_H_STP2.bits(config.stop_bits == StopBits::Two),
+        );
+        uart.hw().cr.write(CR_UARTEN.bits(true) | CR_TXE.bits(true) | CR_RXE.bits(true));
+
+        tx.into_function(Function::Uart);
+        rx.into_function(Function::Uart);
+        uart
+    }
+
+    #[inline(always)]
+    fn hw(&self) -> &'static UartHw {
+        uart(N)
+    }
+
+    /* Baud rate divisor = clk_peri / (16 * baud), as 16.6 fixed point:
+       1. div = 8 * clk / baud has 7 fraction bits, round them to 6
+       2. Clamp to the 1..65535 integer range
+       3. Takes effect on the next LCR_H write
+       A baud of 0 is taken as 1, like spi's set_baud(). Returns the rate actually set */
+    pub fn set_baud(&mut self, baud: u32) -> u32 {
+        let clk = clocks::peri_hz();
+        let div = (8 * clk as u64 / baud.max(1) as u64) as u32;
+        let (ibrd, fbrd) = match div >> 7 {
+            0 => (1, 0),
+            ibrd if ibrd >= 65535 => (65535, 0),
+            ibrd => (ibrd, (div & 0x7f).div_ceil(2)),
+        };
+        self.hw().ibrd.write(ibrd);
+        self.hw().fbrd.write(fbrd);
+        /* Dummy LCR_H write to latch them */
+        self.hw().lcr_h.set(0);
+        (4 * clk as u64 / (64 * ibrd + fbrd) as u64) as u32
+    }
+
+    /* Room in the TX FIFO */
+    #[inline(always)]
+    pub fn is_writable(&self) -> bool {
+        !self.hw().fr.read_field(FR_TXFF)
+    }
+
+    /* Data in the RX FIFO */
+    #[inline(always)]
+    pub fn is_readable(&self) -> bool {
+        !self.hw().fr.read_field(FR_RXFE)
+    }
+
+    /* Send a byte, waiting for FIFO space */
+    pub fn write_byte(&mut self, byte: u8) {
+        while !self.is_writable() {}
+        self.hw().dr.write(byte as u32);
+    }
+
+    /* Send a byte if there is FIFO space */
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if !self.is_writable() {
+            return false;
+        }
+        self.hw().dr.write(byte as u32);
+        true
+    }
+
+    /* Blocking: returns once the last byte is in the FIFO */
+    pub fn write(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|&byte| self.write_byte(byte));
+    }
+
+    /* Non-blocking: as many bytes as fit, returns how many */
+    pub fn try_write(&mut self, bytes: &[u8]) -> usize {
+        bytes.iter().take_while(|&&byte| self.try_write_byte(byte)).count()
+    }
+
+    /* Wait until everything has been shifted out */
+    pub fn flush(&self) {
+        while self.hw().fr.read_field(FR_BUSY) {}
+    }
+
+    /* Next received byte, or the error that came with it */
+    fn pop(&self) -> Result<u8, Error> {
+        let dr = self.hw().dr.read();
+        if dr & (DR_BE.mask() | DR_FE.mask() | DR_PE.mask() | DR_OE.mask()) == 0 {
+            return Ok(DR_DATA.get(dr) as u8);
+        }
+        Err(if DR_BE.get(dr) {
+            Error::Break
+        } else if DR_FE.get(dr) {
+            Error::Framing
+        } else if DR_PE.get(dr) {
+            Error::Parity
+        } else {
+            Error::Overrun
+        })
+    }
+
+    /* Receive a byte, waiting for one to arrive */
+    pub fn read_byte(&mut self) -> Result<u8, Error> {
+        while !self.is_readable() {}
+        self.pop()
+    }
+
+    /* Receive a byte if one is waiting */
+    pub fn try_read_byte(&mut self) -> Option<Result<u8, Error>> {
+        if self.is_readable() {
+            Some(self.pop())
+        } else {
+            None
+        }
+    }
+
+    /* Blocking: fill the whole buffer, stops at the first error */
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        Ok(())
+    }
+
+    /* Non-blocking: what is waiting, up to the buffer size. Returns how many
+       bytes were read, or the first error (bytes before it are in the buffer). */
+    pub fn try_read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut count = 0;
+        while count < buffer.len() {
+            match self.try_read_byte() {
+                Some(byte) => buffer[count] = byte?,
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /* Hold the line low for duration_us (after the bytes already queued) */
+    pub fn send_break(&mut self, duration_us: u32) {
+        self.flush();
+        self.hw().lcr_h.set(LCR_H_BRK.mask());
+        delay_us(duration_us);
+        self.hw().lcr_h.clear(LCR_H_BRK.mask());
+    }
+
+    /* FIFO levels for INT_TX and INT_RX */
+    pub fn set_fifo_levels(&mut self, tx: FifoLevel, rx: FifoLevel) {
+        self.hw().ifls.write(IFLS_TX.bits(tx as u32) | IFLS_RX.bits(rx as u32));
+    }
+
+    /* Handler for this UART's interrupt, None to stop calling one */
+    pub fn set_handler(&mut self, handler: Option<Handler>) {
+        free(|| unsafe { (*addr_of_mut!(HANDLERS))[N] = handler });
+        if handler.is_some() {
+            nvic_enable(UART_IRQ[N]);
+        } else {
+            nvic_disable(UART_IRQ[N]);
+        }
+    }
+
+    /* Enable the INT_* interrupts in mask */
+    pub fn enable_interrupts(&mut self, mask: u32) {
+        self.hw().imsc.set(mask & INT_ALL);
+    }
+
+    pub fn disable_interrupts(&mut self, mask: u32) {
+        self.hw().imsc.clear(mask & INT_ALL);
+    }
+
+    /* Acknowledge INT_* interrupts. RX and TX level interrupts clear themselves
+       once the FIFO is read or written past the level. */
+    pub fn clear_interrupts(&mut self, mask: u32) {
+        self.hw().icr.write(mask & INT_ALL);
+    }
+}
+
+impl<const N: usize> Uart<N> {
+    /* DMA into the TX FIFO: byte transfers to DR, paced by the UART's TX DREQ */
+    pub fn dma_tx(&mut self) -> Target {
+        self.hw().dmacr.set(DMACR_TXDMAE.mask());
+        Target {
+            addr: &self.hw().dr as *const Rw as u32,
+            dreq: dma::DREQ_UART_TX[N],
+        }
+    }
+
+    /* DMA out of the RX FIFO. Byte transfers drop the error flags. */
+    pub fn dma_rx(&mut self) -> Target {
+        self.hw().dmacr.set(DMACR_RXDMAE.mask());
+        Target {
+            addr: &self.hw().dr as *const Rw as u32,
+            dreq: dma::DREQ_UART_RX[N],
+        }
+    }
+}
+
+/* Debug console: write!(uart, ...) */
+impl<const N: usize> fmt::Write for Uart<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/* Interrupt: hand the status to the handler, errors and timeouts are
+   acknowledged here so an unhandled one can't keep firing */
+fn uart_irq(index: usize) {
+    let status = uart(index).mis.read();
+    uart(index).icr.write(status & !(INT_RX | INT_TX));
+    match unsafe { (*addr_of_mut!(HANDLERS))[index] } {
+        Some(handler) => handler(status),
+        None => uart(index).imsc.clear(INT_ALL),
+    }
+}
+
+/* Interrupt handlers for UART0_IRQ and UART1_IRQ */
+#[no_mangle]
+pub extern "C" fn uart0Irq() {
+    uart_irq(0);
+}
+
+#[no_mangle]
+pub extern "C" fn uart1Irq() {
+    uart_irq(1);
+}
@@ -0,0 +1,218 @@
+use core::ptr::addr_of_mut;
+
+use crate::clocks;
+use crate::hw::*;
+use crate::reg::{bit, block, Field, Ro, RwAlias};
+
+/* Watchdog and reset reason */
+/* 1. The watchdog block also holds the tick generator: clk_ref divided down
+      to the 1 us tick the TIMER and the watchdog counter both run on.
+      start_tick() runs it, timer::init() calls it.
+   2. start() loads the counter and enables it, feed() reloads it. Reaching
+      zero resets everything except the oscillators (through the PSM), so
+      main has to feed() more often than the timeout. The counter goes down
+      by 2 per tick (RP2040-E1), the load is doubled to make up for it,
+      which caps the timeout at MAX_TIMEOUT_US.
+   3. Why we were reset: the watchdog REASON register says whether it timed
+      out or a reset was forced, CHIP_RESET (in VREG_AND_CHIP_RESET) tells a
+      power-on, the RUN pin and a debugger restart apart. check_reset() works
+      it out once at startup and keeps it in the scratch registers, which
+      only a power-on or RUN reset clears. Scratch 4-7 belong to the boot ROM. */
+
+/* Power-on state machine, picks what the watchdog resets */
+#[repr(C)]
+pub struct PsmHw {
+    pub frce_on: RwAlias,
+    pub frce_off: RwAlias,
+    pub wdsel: RwAlias,     /* 1 = reset by the watchdog */
+    pub done: Ro,
+}
+
+/* VREG_AND_CHIP_RESET, only CHIP_RESET is used */
+#[repr(C)]
+pub struct ChipResetHw {
+    pub vreg: RwAlias,
+    pub bod: RwAlias,
+    pub chip_reset: RwAlias,    /* Cause of the last chip-level reset */
+}
+
+const PSM_BASE: u32 = 0x40010000;
+const CHIP_RESET_BASE: u32 = 0x40064000;
+
+#[inline(always)]
+fn psm() -> &'static PsmHw {
+    block(PSM_BASE)
+}
+
+#[inline(always)]
+fn chip() -> &'static ChipResetHw {
+    block(CHIP_RESET_BASE)
+}
+
+/* CTRL fields */
+const CTRL_PAUSE_JTAG: Field<bool> = bit(24);
+const CTRL_PAUSE_DBG0: Field<bool> = bit(25);
+const CTRL_PAUSE_DBG1: Field<bool> = bit(26);
+const CTRL_ENABLE: Field<bool> = bit(30);
+const CTRL_TRIGGER: Field<bool> = bit(31);
+
+/* REASON fields */
+const REASON_TIMER: Field<bool> = bit(0);
+const REASON_FORCE: Field<bool> = bit(1);
+
+/* TICK fields */
+const TICK_CYCLES: Field = Field::new(0, 9);
+const TICK_ENABLE: Field<bool> = bit(9);
+
+/* CHIP_RESET fields */
+const CHIP_HAD_POR: Field<bool> = bit(8);
+const CHIP_HAD_RUN: Field<bool> = bit(16);
+const CHIP_HAD_PSM_RESTART: Field<bool> = bit(20);
+
+/* PSM WDSEL: everything (bits 0-16) except ROSC (0) and XOSC (1) */
+const PSM_WDSEL_ALL: u32 = 0x1_fffc;
+
+/* Largest load is 0xffffff, counted down 2 per tick */
+pub const MAX_TIMEOUT_US: u32 = 0xff_ffff / 2;
+
+/* Scratch registers we keep across a watchdog reset */
+const SCRATCH_MAGIC: usize = 0;         /* SCRATCH_VALID once the others are set */
+const SCRATCH_REASON: usize = 1;        /* ResetReason of the last reset */
+const SCRATCH_WATCHDOG_RESETS: usize = 2; /* Watchdog timeouts since power-on */
+const SCRATCH_VALID: u32 = 0x4d4f_5253; /* "MORS" */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn = 0,    /* Power applied, or brown-out */
+    RunPin = 1,     /* RUN pin pulled low */
+    Debugger = 2,   /* Restart from the debug port (SWD rescue or PSM restart) */
+    Watchdog = 3,   /* Watchdog timed out: something stopped feeding it */
+    Forced = 4,     /* Reset asked for with force_reset() */
+    Unknown = 5,
+}
+
+impl ResetReason {
+    fn from_u32(value: u32) -> ResetReason {
+        match value {
+            0 => ResetReason::PowerOn,
+            1 => ResetReason::RunPin,
+            2 => ResetReason::Debugger,
+            3 => ResetReason::Watchdog,
+            4 => ResetReason::Forced,
+            _ => ResetReason::Unknown,
+        }
+    }
+
+    /* For the console */
+    pub fn name(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "power-on",
+            ResetReason::RunPin => "RUN pin",
+            ResetReason::Debugger => "debugger",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::Forced => "forced",
+            ResetReason::Unknown => "unknown",
+        }
+    }
+
+    /* LED blinks for a board without a console: 1 for power-on, 2 RUN pin ... */
+    pub fn blinks(self) -> u32 {
+        self as u32 + 1
+    }
+}
+
+/* Load value, reloaded by feed() */
+static mut LOAD: u32 = 0;
+
+/* 1 us tick for the TIMER and the watchdog counter */
+pub fn start_tick() {
+    watchdog().tick.write(
+        TICK_ENABLE.bits(true) | TICK_CYCLES.bits(clocks::ref_hz() / 1_000_000),
+    );
+}
+
+/* Start the watchdog (or change its timeout), up to MAX_TIMEOUT_US.
+   pause_on_debug stops the counter while a debugger halts either core. */
+pub fn start(timeout_us: u32, pause_on_debug: bool) {
+    assert!(timeout_us > 0 && timeout_us <= MAX_TIMEOUT_US);
+    free(|| {
+        watchdog().ctrl.clear(CTRL_ENABLE.mask());
+        psm().wdsel.write(PSM_WDSEL_ALL);
+        unsafe { *addr_of_mut!(LOAD) = timeout_us * 2 };
+        feed();
+        let pause = CTRL_PAUSE_JTAG.mask() | CTRL_PAUSE_DBG0.mask() | CTRL_PAUSE_DBG1.mask();
+        if pause_on_debug {
+            watchdog().ctrl.set(pause);
+        } else {
+            watchdog().ctrl.clear(pause);
+        }
+        watchdog().ctrl.set(CTRL_ENABLE.mask());
+    });
+}
+
+/* Reload the counter */
+#[inline(always)]
+pub fn feed() {
+    watchdog().load.write(unsafe { *addr_of_mut!(LOAD) });
+}
+
+pub fn stop() {
+    watchdog().ctrl.clear(CTRL_ENABLE.mask());
+}
+
+/* Reset the chip now, the next boot sees ResetReason::Forced */
+pub fn force_reset() -> ! {
+    psm().wdsel.write(PSM_WDSEL_ALL);
+    watchdog().ctrl.set(CTRL_TRIGGER.mask());
+    /* The reset takes a few cycles to land */
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
+
+/* Why the chip was last reset, from the hardware registers */
+pub fn reset_reason() -> ResetReason {
+    let reason = watchdog().reason.read();
+    let chip_reset = chip().chip_reset.read();
+    if REASON_TIMER.get(reason) {
+        ResetReason::Watchdog
+    } else if REASON_FORCE.get(reason) {
+        ResetReason::Forced
+    } else if CHIP_HAD_PSM_RESTART.get(chip_reset) {
+        ResetReason::Debugger
+    } else if CHIP_HAD_RUN.get(chip_reset) {
+        ResetReason::RunPin
+    } else if CHIP_HAD_POR.get(chip_reset) {
+        ResetReason::PowerOn
+    } else {
+        ResetReason::Unknown
+    }
+}
+
+/* Call once at startup: records the reset reason in the scratch registers
+   and counts watchdog timeouts. A power-on or RUN reset clears the scratch
+   registers, so stale values are recognised by the missing magic. */
+pub fn check_reset() -> ResetReason {
+    let reason = reset_reason();
+    let scratch = &watchdog().scratch;
+    if scratch[SCRATCH_MAGIC].read() != SCRATCH_VALID {
+        scratch[SCRATCH_WATCHDOG_RESETS].write(0);
+        scratch[SCRATCH_MAGIC].write(SCRATCH_VALID);
+    }
+    if reason == ResetReason::Watchdog {
+        let count = scratch[SCRATCH_WATCHDOG_RESETS].read();
+        scratch[SCRATCH_WATCHDOG_RESETS].write(count.wrapping_add(1));
+    }
+    scratch[SCRATCH_REASON].write(reason as u32);
+    reason
+}
+
+/* Reason recorded by the last check_reset() */
+pub fn last_reset() -> ResetReason {
+    ResetReason::from_u32(watchdog().scratch[SCRATCH_REASON].read())
+}
+
+/* Watchdog timeouts since the last power-on or RUN reset */
+pub fn watchdog_resets() -> u32 {
+    watchdog().scratch[SCRATCH_WATCHDOG_RESETS].read()
+}